@@ -0,0 +1,32 @@
+use bevy::asset::{AssetLoader, LoadedAsset};
+
+use super::{animation_sheet::AnimationSheet, AssetErrors};
+
+pub struct AnimationSheetLoader(pub AssetErrors);
+
+impl AssetLoader for AnimationSheetLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut bevy::asset::LoadContext,
+    ) -> bevy::utils::BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let result = load_sheet(bytes, load_context);
+            self.0.record(load_context.path(), result)
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["anim.json"]
+    }
+}
+
+fn load_sheet(
+    bytes: &[u8],
+    load_context: &mut bevy::asset::LoadContext,
+) -> Result<(), bevy::asset::Error> {
+    let sheet: AnimationSheet = serde_json::from_slice(bytes)?;
+    sheet.validate()?;
+    load_context.set_default_asset(LoadedAsset::new(sheet));
+    Ok(())
+}
@@ -0,0 +1,167 @@
+use std::{collections::HashMap, fmt};
+
+use bevy::{math::Rect, prelude::Vec2, reflect::TypeUuid};
+use serde::Deserialize;
+
+use crate::game::isometric::IsometricDirection;
+
+#[derive(Debug)]
+pub struct AnimationSheetError(String);
+
+impl fmt::Display for AnimationSheetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid animation sheet: {}", self.0)
+    }
+}
+
+impl std::error::Error for AnimationSheetError {}
+
+// Animation clips cut from a sprite sheet of equally sized frames, loaded from .anim.json files
+#[derive(Deserialize, Debug, Clone, TypeUuid)]
+#[uuid = "4f0c3e2a-8d7b-4b1e-9a35-6c2f1d9e7b40"]
+pub struct AnimationSheet {
+    pub frame_width: u32,
+    pub frame_height: u32,
+    // pixels between two frames
+    #[serde(default)]
+    pub spacing: u32,
+    // pixels between two rows of frames, if they are spaced differently than the columns
+    pub row_spacing: Option<u32>,
+    // by name
+    pub clips: HashMap<String, AnimationClip>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct AnimationClip {
+    // how long frames without a duration of their own are shown
+    pub seconds: Option<f32>,
+    #[serde(default)]
+    pub repeating: bool,
+    pub frames: Vec<ClipFrame>,
+    // clip played once this one ended, only for clips that don't repeat
+    pub next: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct ClipFrame {
+    pub column: u32,
+    pub row: u32,
+    pub seconds: Option<f32>,
+    // sent as an animation event when the frame is shown
+    pub event: Option<String>,
+}
+
+impl AnimationSheet {
+    // the clips units play: idle, and walking, attacking and dying in every direction
+    pub fn required_clips() -> Vec<String> {
+        let mut clips = vec!["idle".to_string()];
+        for direction in IsometricDirection::all() {
+            for action in ["walk", "attack", "death"] {
+                clips.push(format!("{action}_{}", direction.name()));
+            }
+        }
+        clips
+    }
+
+    pub fn validate(&self) -> Result<(), AnimationSheetError> {
+        for name in Self::required_clips() {
+            if !self.clips.contains_key(&name) {
+                return Err(AnimationSheetError(format!("the clip '{name}' is missing")));
+            }
+        }
+        for (name, clip) in &self.clips {
+            if clip.frames.is_empty() {
+                return Err(AnimationSheetError(format!("clip '{name}' has no frames")));
+            }
+            for (index, frame) in clip.frames.iter().enumerate() {
+                match clip.frame_seconds(frame) {
+                    Some(seconds) if seconds > 0. => (),
+                    _ => {
+                        return Err(AnimationSheetError(format!(
+                            "frame {index} of clip '{name}' needs a positive duration"
+                        )))
+                    }
+                }
+            }
+            if let Some(next) = &clip.next {
+                if !self.clips.contains_key(next) {
+                    return Err(AnimationSheetError(format!(
+                        "clip '{name}' continues with the unknown clip '{next}'"
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Every frame has to lie within the sprite sheet's image
+    pub fn validate_size(&self, image_size: Vec2) -> Result<(), AnimationSheetError> {
+        for (name, clip) in &self.clips {
+            for (index, frame) in clip.frames.iter().enumerate() {
+                let rect = self.frame_rect(frame);
+                if rect.max.x > image_size.x || rect.max.y > image_size.y {
+                    return Err(AnimationSheetError(format!(
+                        "frame {index} of clip '{name}' reaches past the {}x{} image",
+                        image_size.x, image_size.y
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // where the frame is in the sprite sheet, in pixels
+    pub fn frame_rect(&self, frame: &ClipFrame) -> Rect {
+        let row_spacing = self.row_spacing.unwrap_or(self.spacing);
+        let min = Vec2::new(
+            (frame.column * (self.frame_width + self.spacing)) as f32,
+            (frame.row * (self.frame_height + row_spacing)) as f32,
+        );
+        Rect {
+            min,
+            max: min + Vec2::new(self.frame_width as f32, self.frame_height as f32),
+        }
+    }
+}
+
+impl AnimationClip {
+    pub fn frame_seconds(&self, frame: &ClipFrame) -> Option<f32> {
+        frame.seconds.or(self.seconds)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path};
+
+    use super::*;
+
+    // width and height from the PNG header
+    fn image_size(png: &[u8]) -> Vec2 {
+        let number = |at: usize| u32::from_be_bytes(png[at..at + 4].try_into().unwrap());
+        Vec2::new(number(16) as f32, number(20) as f32)
+    }
+
+    #[test]
+    fn unit_sheets_are_valid() {
+        let units = Path::new(env!("CARGO_MANIFEST_DIR")).join("assets/units");
+        let text = fs::read_to_string(units.join("ogre.anim.json")).unwrap();
+        let sheet: AnimationSheet = serde_json::from_str(&text).unwrap();
+        sheet.validate().unwrap();
+
+        let png = fs::read(units.join("ogre.png")).unwrap();
+        sheet.validate_size(image_size(&png)).unwrap();
+    }
+
+    #[test]
+    fn missing_clips_are_errors() {
+        let units = Path::new(env!("CARGO_MANIFEST_DIR")).join("assets/units");
+        let text = fs::read_to_string(units.join("ogre.anim.json")).unwrap();
+        let mut sheet: AnimationSheet = serde_json::from_str(&text).unwrap();
+        sheet.clips.remove("walk_up_left");
+        assert_eq!(
+            sheet.validate().unwrap_err().to_string(),
+            "invalid animation sheet: the clip 'walk_up_left' is missing"
+        );
+    }
+}
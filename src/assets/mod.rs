@@ -1,10 +1,20 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
 use bevy::prelude::*;
 
 use self::{
+    animation_loader::AnimationSheetLoader,
+    animation_sheet::AnimationSheet,
     tiled_loader::{TiledMapLoader, TiledSetLoader},
     types::*,
 };
 
+mod animation_loader;
+pub mod animation_sheet;
 mod tiled_loader;
 pub mod types;
 
@@ -12,9 +22,37 @@ pub struct AssetsPlugin;
 
 impl Plugin for AssetsPlugin {
     fn build(&self, app: &mut App) {
-        app.add_asset::<TiledMap>()
-            .init_asset_loader::<TiledMapLoader>()
+        let errors = AssetErrors::default();
+        app.insert_resource(errors.clone())
+            .add_asset::<TiledMap>()
+            .add_asset_loader(TiledMapLoader(errors.clone()))
             .add_asset::<TiledSet>()
-            .init_asset_loader::<TiledSetLoader>();
+            .add_asset_loader(TiledSetLoader(errors.clone()))
+            .add_asset::<AnimationSheet>()
+            .add_asset_loader(AnimationSheetLoader(errors));
+    }
+}
+
+// Why our loaders failed by asset path, the asset server only tells that they did
+#[derive(Resource, Clone, Default)]
+pub struct AssetErrors(Arc<Mutex<HashMap<PathBuf, String>>>);
+
+impl AssetErrors {
+    // loaders run on other threads, so the errors are shared
+    fn record(
+        &self,
+        path: &Path,
+        result: Result<(), bevy::asset::Error>,
+    ) -> Result<(), bevy::asset::Error> {
+        let mut errors = self.0.lock().unwrap();
+        match &result {
+            Ok(()) => errors.remove(path),
+            Err(error) => errors.insert(path.to_path_buf(), error.to_string()),
+        };
+        result
+    }
+
+    pub fn get(&self, path: &Path) -> Option<String> {
+        self.0.lock().unwrap().get(path).cloned()
     }
 }
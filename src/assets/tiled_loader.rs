@@ -1,9 +1,11 @@
 use bevy::asset::{AssetLoader, LoadedAsset};
 
-use super::types::{TiledMap, TiledSet};
+use super::{
+    types::{TiledMap, TiledSet},
+    AssetErrors,
+};
 
-#[derive(Default)]
-pub struct TiledMapLoader;
+pub struct TiledMapLoader(pub AssetErrors);
 
 impl AssetLoader for TiledMapLoader {
     fn load<'a>(
@@ -12,9 +14,8 @@ impl AssetLoader for TiledMapLoader {
         load_context: &'a mut bevy::asset::LoadContext,
     ) -> bevy::utils::BoxedFuture<'a, Result<(), bevy::asset::Error>> {
         Box::pin(async move {
-            let map: TiledMap = serde_json::from_slice(bytes)?;
-            load_context.set_default_asset(LoadedAsset::new(map));
-            Ok(())
+            let result = load_map(bytes, load_context);
+            self.0.record(load_context.path(), result)
         })
     }
 
@@ -23,8 +24,7 @@ impl AssetLoader for TiledMapLoader {
     }
 }
 
-#[derive(Default)]
-pub struct TiledSetLoader;
+pub struct TiledSetLoader(pub AssetErrors);
 
 impl AssetLoader for TiledSetLoader {
     fn load<'a>(
@@ -33,12 +33,8 @@ impl AssetLoader for TiledSetLoader {
         load_context: &'a mut bevy::asset::LoadContext,
     ) -> bevy::utils::BoxedFuture<'a, Result<(), bevy::asset::Error>> {
         Box::pin(async move {
-            let set: TiledSet = serde_json::from_slice(bytes)?;
-            let asset = LoadedAsset::new(set.clone());
-            let asset =
-                asset.with_dependencies(set.tiles.iter().map(|tile| tile.path().into()).collect());
-            load_context.set_default_asset(asset);
-            Ok(())
+            let result = load_set(bytes, load_context);
+            self.0.record(load_context.path(), result)
         })
     }
 
@@ -46,3 +42,29 @@ impl AssetLoader for TiledSetLoader {
         &["tsj"]
     }
 }
+
+fn load_map(
+    bytes: &[u8],
+    load_context: &mut bevy::asset::LoadContext,
+) -> Result<(), bevy::asset::Error> {
+    let mut map: TiledMap = serde_json::from_slice(bytes)?;
+    map.resolve(load_context.path())?;
+    let dependencies = map
+        .tilesets
+        .iter()
+        .map(|tileset| tileset.path.clone().into())
+        .collect();
+    load_context.set_default_asset(LoadedAsset::new(map).with_dependencies(dependencies));
+    Ok(())
+}
+
+fn load_set(
+    bytes: &[u8],
+    load_context: &mut bevy::asset::LoadContext,
+) -> Result<(), bevy::asset::Error> {
+    let mut set: TiledSet = serde_json::from_slice(bytes)?;
+    set.resolve(load_context.path())?;
+    let dependencies = set.image_paths().map(|path| path.clone().into()).collect();
+    load_context.set_default_asset(LoadedAsset::new(set).with_dependencies(dependencies));
+    Ok(())
+}
@@ -1,37 +1,778 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use base64::Engine;
 use bevy::reflect::TypeUuid;
+use flate2::read::{GzDecoder, ZlibDecoder};
 use serde::Deserialize;
 use serde::*;
 
-#[derive(Deserialize, Debug)]
+// The editor saves maps back to their files. Every struct with an `extra` field keeps the fields
+// the game doesn't use in it, so saving doesn't lose them
+
+#[derive(Debug)]
+pub enum TiledError {
+    // valid Tiled files using features the game can't handle
+    Unsupported(String),
+    Invalid(String),
+}
+
+impl fmt::Display for TiledError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TiledError::Unsupported(feature) => write!(f, "unsupported Tiled feature: {feature}"),
+            TiledError::Invalid(reason) => write!(f, "invalid Tiled file: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for TiledError {}
+
+// written into saved maps
+const TILED_VERSION: &str = "1.10.1";
+const FORMAT_VERSION: &str = "1.10";
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum TiledPropertyValue {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    // also colors and files
+    String(String),
+    // custom class properties
+    Class(serde_json::Map<String, serde_json::Value>),
+}
+
+impl TiledPropertyValue {
+    fn tiled_type(&self) -> &'static str {
+        match self {
+            TiledPropertyValue::Bool(_) => "bool",
+            TiledPropertyValue::Int(_) => "int",
+            TiledPropertyValue::Float(_) => "float",
+            TiledPropertyValue::String(_) => "string",
+            TiledPropertyValue::Class(_) => "class",
+        }
+    }
+}
+
+// Tiled custom property
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct TiledProperty {
+    pub name: String,
+    // e.g. int, color or object, which all share a json type
+    #[serde(rename = "type", default, skip_serializing_if = "String::is_empty")]
+    pub kind: String,
+    pub value: TiledPropertyValue,
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct TiledProperties(Vec<TiledProperty>);
+
+impl TiledProperties {
+    // Existing properties keep their type and position
+    pub fn set(&mut self, name: &str, value: TiledPropertyValue) {
+        match self.0.iter_mut().find(|property| property.name == name) {
+            Some(property) => property.value = value,
+            None => self.0.push(TiledProperty {
+                name: name.to_string(),
+                kind: value.tiled_type().to_string(),
+                value,
+                extra: Default::default(),
+            }),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&TiledPropertyValue> {
+        self.0
+            .iter()
+            .find(|property| property.name == name)
+            .map(|property| &property.value)
+    }
+
+    // object properties are stored as the referenced object's id
+    pub fn int(&self, name: &str) -> Option<i64> {
+        match self.get(name)? {
+            TiledPropertyValue::Int(value) => Some(*value),
+            TiledPropertyValue::Float(value) => Some(*value as i64),
+            _ => None,
+        }
+    }
+
+    pub fn bool(&self, name: &str) -> Option<bool> {
+        match self.get(name)? {
+            TiledPropertyValue::Bool(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn string(&self, name: &str) -> Option<&str> {
+        match self.get(name)? {
+            TiledPropertyValue::String(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+const FLIPPED_HORIZONTALLY: u32 = 0x80000000;
+const FLIPPED_VERTICALLY: u32 = 0x40000000;
+const FLIPPED_DIAGONALLY: u32 = 0x20000000;
+const ROTATED_HEXAGONAL: u32 = 0x10000000;
+const FLAGS: u32 =
+    FLIPPED_HORIZONTALLY | FLIPPED_VERTICALLY | FLIPPED_DIAGONALLY | ROTATED_HEXAGONAL;
+
+// Global tile id, the highest bits flag flipped / rotated tiles
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TiledGid(pub u32);
+
+impl TiledGid {
+    // 0 means no tile
+    pub fn id(&self) -> u32 {
+        self.0 & !FLAGS
+    }
+
+    pub fn flip_x(&self) -> bool {
+        self.0 & FLIPPED_HORIZONTALLY != 0
+    }
+
+    pub fn flip_y(&self) -> bool {
+        self.0 & FLIPPED_VERTICALLY != 0
+    }
+
+    // rotated by 90 degrees, which isometric tiles can't be
+    fn is_rotated(&self) -> bool {
+        self.0 & (FLIPPED_DIAGONALLY | ROTATED_HEXAGONAL) != 0
+    }
+}
+
+// csv data is stored as a plain array
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum TiledData {
+    Csv(Vec<u32>),
+    Encoded(String),
+}
+
+// Part of an infinite map's layer
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct TiledChunk {
+    pub data: TiledData,
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct TiledLayer {
-    pub data: Vec<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<TiledData>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub chunks: Option<Vec<TiledChunk>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encoding: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub compression: Option<String>,
     pub id: i32,
     pub name: String,
     pub height: u32,
     pub width: u32,
+    // in pixels
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub offsetx: f32,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub offsety: f32,
+    #[serde(default = "default_opacity")]
+    pub opacity: f32,
+    #[serde(default = "default_visible")]
+    pub visible: bool,
+    #[serde(default, skip_serializing_if = "TiledProperties::is_empty")]
+    pub properties: TiledProperties,
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+
+    // editor position => gid, filled by decode
+    #[serde(skip)]
+    tiles: HashMap<(i32, i32), TiledGid>,
+    // expected and actual length of the data or each chunk
+    #[serde(skip)]
+    pub data_lengths: Vec<(u32, usize)>,
+}
+
+fn default_opacity() -> f32 {
+    1.
+}
+
+fn default_visible() -> bool {
+    true
+}
+
+// Tiled leaves out offsets of 0
+fn is_zero(value: &f32) -> bool {
+    *value == 0.
+}
+
+impl TiledLayer {
+    // Uncompressed csv layer, decoded by TiledMap::resolve
+    pub fn new(id: i32, name: &str, width: u32, height: u32, data: Vec<u32>) -> Self {
+        TiledLayer {
+            data: Some(TiledData::Csv(data)),
+            chunks: None,
+            encoding: None,
+            compression: None,
+            id,
+            name: name.to_string(),
+            height,
+            width,
+            offsetx: 0.,
+            offsety: 0.,
+            opacity: 1.,
+            visible: true,
+            properties: TiledProperties::default(),
+            extra: Default::default(),
+            tiles: HashMap::new(),
+            data_lengths: vec![],
+        }
+    }
+
+    pub fn tiles(&self) -> impl Iterator<Item = ((i32, i32), TiledGid)> + '_ {
+        self.tiles.iter().map(|(position, gid)| (*position, *gid))
+    }
+
+    pub fn gid_at(&self, position: &(i32, i32)) -> Option<TiledGid> {
+        self.tiles.get(position).copied()
+    }
+
+    // None removes the tile, encode has to be called before saving
+    pub fn set_gid(&mut self, position: (i32, i32), gid: Option<TiledGid>) {
+        match gid {
+            Some(gid) if gid.id() != 0 => self.tiles.insert(position, gid),
+            _ => self.tiles.remove(&position),
+        };
+    }
+
+    // Writes the tiles back as uncompressed csv data, infinite maps get a single chunk
+    pub fn encode(&mut self) {
+        let (x, y, width, height) = if self.chunks.is_some() {
+            let xs = self.tiles.keys().map(|position| position.0);
+            let ys = self.tiles.keys().map(|position| position.1);
+            let (min_x, min_y) = (xs.clone().min().unwrap_or(0), ys.clone().min().unwrap_or(0));
+            let (max_x, max_y) = (xs.max().unwrap_or(0), ys.max().unwrap_or(0));
+            (
+                min_x,
+                min_y,
+                (max_x - min_x + 1) as u32,
+                (max_y - min_y + 1) as u32,
+            )
+        } else {
+            (0, 0, self.width, self.height)
+        };
+
+        let mut data = vec![0; (width * height) as usize];
+        for ((tile_x, tile_y), gid) in self.tiles.iter() {
+            let (column, row) = (tile_x - x, tile_y - y);
+            if (0..width as i32).contains(&column) && (0..height as i32).contains(&row) {
+                data[(column + row * width as i32) as usize] = gid.0;
+            }
+        }
+
+        self.encoding = None;
+        self.compression = None;
+        self.data_lengths = vec![(width * height, data.len())];
+        if self.chunks.is_some() {
+            self.chunks = Some(vec![TiledChunk {
+                data: TiledData::Csv(data),
+                x,
+                y,
+                width,
+                height,
+            }]);
+        } else {
+            self.data = Some(TiledData::Csv(data));
+        }
+    }
+
+    fn decode(&mut self) -> Result<(), TiledError> {
+        let encoding = self.encoding.as_deref();
+        let compression = self.compression.as_deref();
+
+        let mut tiles = HashMap::new();
+        let mut data_lengths = vec![];
+        let mut add_tiles = |gids: Vec<u32>, x: i32, y: i32, width: u32, height: u32| {
+            // wrong sizes are reported by the map validation
+            data_lengths.push((width * height, gids.len()));
+            for (i, gid) in gids.into_iter().take((width * height) as usize).enumerate() {
+                let gid = TiledGid(gid);
+                if gid.id() == 0 {
+                    continue;
+                }
+                let position = (x + (i as u32 % width) as i32, y + (i as u32 / width) as i32);
+                if gid.is_rotated() {
+                    return Err(TiledError::Unsupported(format!(
+                        "rotated tile at {position:?} in layer '{}', isometric tiles can only be flipped",
+                        self.name
+                    )));
+                }
+                tiles.insert(position, gid);
+            }
+            Ok(())
+        };
+
+        match (&self.data, &self.chunks) {
+            (Some(data), _) => {
+                let gids = decode_data(data, encoding, compression)?;
+                add_tiles(gids, 0, 0, self.width, self.height)?;
+            }
+            (None, Some(chunks)) => {
+                for chunk in chunks {
+                    let gids = decode_data(&chunk.data, encoding, compression)?;
+                    add_tiles(gids, chunk.x, chunk.y, chunk.width, chunk.height)?;
+                }
+            }
+            (None, None) => {
+                return Err(TiledError::Invalid(format!(
+                    "layer '{}' has neither data nor chunks",
+                    self.name
+                )))
+            }
+        }
+
+        self.tiles = tiles;
+        self.data_lengths = data_lengths;
+        Ok(())
+    }
+}
+
+fn decode_data(
+    data: &TiledData,
+    encoding: Option<&str>,
+    compression: Option<&str>,
+) -> Result<Vec<u32>, TiledError> {
+    Ok(match (data, encoding) {
+        (TiledData::Csv(gids), None | Some("csv")) => gids.clone(),
+        (TiledData::Encoded(text), Some("base64")) => {
+            let bytes = base64::engine::general_purpose::STANDARD
+                .decode(text.trim())
+                .map_err(|error| TiledError::Invalid(format!("broken base64 data: {error}")))?;
+            let bytes = match compression {
+                None | Some("") => bytes,
+                Some("zlib") => decompress(ZlibDecoder::new(bytes.as_slice()))?,
+                Some("gzip") => decompress(GzDecoder::new(bytes.as_slice()))?,
+                Some(compression) => {
+                    return Err(TiledError::Unsupported(format!(
+                        "{compression} compressed layer data"
+                    )))
+                }
+            };
+            let gids = bytes.chunks_exact(4);
+            if !gids.remainder().is_empty() {
+                return Err(TiledError::Invalid(format!(
+                    "layer data of {} bytes, gids take 4 bytes each",
+                    bytes.len()
+                )));
+            }
+            gids.map(|gid| u32::from_le_bytes([gid[0], gid[1], gid[2], gid[3]]))
+                .collect()
+        }
+        (_, Some(encoding)) => {
+            return Err(TiledError::Unsupported(format!(
+                "layer data encoded as {encoding}"
+            )))
+        }
+        (_, None) => {
+            return Err(TiledError::Invalid(
+                "layer data is a string without encoding".to_string(),
+            ))
+        }
+    })
+}
+
+fn decompress(mut decoder: impl Read) -> Result<Vec<u8>, TiledError> {
+    let mut bytes = vec![];
+    decoder
+        .read_to_end(&mut bytes)
+        .map_err(|error| TiledError::Invalid(format!("broken compressed data: {error}")))?;
+    Ok(bytes)
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct TiledObject {
+    pub id: u32,
+    #[serde(default)]
+    pub name: String,
+    // the class in the Tiled ui, saved as "type" except by Tiled 1.9 which used "class",
+    // both are kept as they were so saving doesn't rename the key
+    #[serde(rename = "type", default, skip_serializing_if = "Option::is_none")]
+    type_key: Option<String>,
+    #[serde(rename = "class", default, skip_serializing_if = "Option::is_none")]
+    class_key: Option<String>,
+    // in pixels, on isometric maps both axes are measured in tile heights
+    pub x: f32,
+    pub y: f32,
+    #[serde(default, skip_serializing_if = "TiledProperties::is_empty")]
+    pub properties: TiledProperties,
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+impl TiledObject {
+    // Point objects mark a single tile, the id is assigned by TiledMap::add_object
+    pub fn point(class: &str, x: f32, y: f32) -> Self {
+        TiledObject {
+            id: 0,
+            name: String::new(),
+            type_key: Some(class.to_string()),
+            class_key: None,
+            x,
+            y,
+            properties: TiledProperties::default(),
+            extra: serde_json::Map::from_iter([("point".to_string(), true.into())]),
+        }
+    }
+
+    pub fn class(&self) -> &str {
+        self.type_key
+            .as_deref()
+            .or(self.class_key.as_deref())
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct TiledObjectLayer {
+    pub id: i32,
+    pub name: String,
+    pub objects: Vec<TiledObject>,
+    #[serde(default, skip_serializing_if = "TiledProperties::is_empty")]
+    pub properties: TiledProperties,
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(tag = "type")]
+pub enum TiledLayerKind {
+    #[serde(rename = "tilelayer")]
+    Tiles(TiledLayer),
+    #[serde(rename = "objectgroup")]
+    Objects(TiledObjectLayer),
+    // kept as they are, the game can't show them
+    #[serde(rename = "imagelayer")]
+    Image(serde_json::Map<String, serde_json::Value>),
+    #[serde(rename = "group")]
+    Group(serde_json::Map<String, serde_json::Value>),
 }
 
-#[derive(Deserialize, Debug, TypeUuid)]
+// Embedded tilesets have no source
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct TiledTilesetRef {
+    pub firstgid: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+
+    // asset path of the tileset, filled by resolve
+    #[serde(skip)]
+    pub path: PathBuf,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, TypeUuid)]
 #[uuid = "1cb1e503-3c34-4f38-ab9e-2338e2c4a0f4"]
 pub struct TiledMap {
     pub compressionlevel: i32,
     pub height: u32,
     pub width: u32,
-    pub layers: Vec<TiledLayer>,
+    #[serde(default)]
+    pub infinite: bool,
+    pub orientation: String,
+    pub layers: Vec<TiledLayerKind>,
+    pub tilesets: Vec<TiledTilesetRef>,
     pub tilewidth: u32,
     pub tileheight: u32,
+    #[serde(default, skip_serializing_if = "TiledProperties::is_empty")]
+    pub properties: TiledProperties,
+    // ids Tiled gives the next new layer or object
+    #[serde(default)]
+    pub nextlayerid: i32,
+    #[serde(default)]
+    pub nextobjectid: u32,
+    #[serde(default = "default_render_order")]
+    pub renderorder: String,
+    #[serde(default)]
+    pub tiledversion: String,
+    #[serde(rename = "type", default = "default_map_type")]
+    pub kind: String,
+    #[serde(default)]
+    pub version: String,
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
 }
 
-#[derive(Deserialize, Serialize, Debug, Clone)]
+fn default_render_order() -> String {
+    "right-down".to_string()
+}
+
+fn default_map_type() -> String {
+    "map".to_string()
+}
+
+impl TiledMap {
+    // Empty isometric map, as Tiled would create it
+    pub fn new(width: u32, height: u32, tilewidth: u32, tileheight: u32) -> Self {
+        TiledMap {
+            compressionlevel: -1,
+            height,
+            width,
+            infinite: false,
+            orientation: "isometric".to_string(),
+            layers: vec![],
+            tilesets: vec![],
+            tilewidth,
+            tileheight,
+            properties: TiledProperties::default(),
+            nextlayerid: 1,
+            nextobjectid: 1,
+            renderorder: default_render_order(),
+            tiledversion: TILED_VERSION.to_string(),
+            kind: default_map_type(),
+            version: FORMAT_VERSION.to_string(),
+            extra: Default::default(),
+        }
+    }
+
+    // every tile layer is one height level
+    pub fn tile_layers(&self) -> impl Iterator<Item = &TiledLayer> {
+        self.layers.iter().filter_map(|layer| match layer {
+            TiledLayerKind::Tiles(layer) => Some(layer),
+            _ => None,
+        })
+    }
+
+    pub fn tile_layers_mut(&mut self) -> impl Iterator<Item = &mut TiledLayer> {
+        self.layers.iter_mut().filter_map(|layer| match layer {
+            TiledLayerKind::Tiles(layer) => Some(layer),
+            _ => None,
+        })
+    }
+
+    // New empty tile layer on top of the others
+    pub fn add_tile_layer(&mut self) -> &mut TiledLayer {
+        let id = self.next_layer_id();
+        let layer = TiledLayer::new(
+            id,
+            &format!("layer{id}"),
+            self.width,
+            self.height,
+            vec![0; (self.width * self.height) as usize],
+        );
+        let index = self
+            .layers
+            .iter()
+            .rposition(|layer| matches!(layer, TiledLayerKind::Tiles(_)))
+            .map_or(0, |index| index + 1);
+        self.layers.insert(index, TiledLayerKind::Tiles(layer));
+        let TiledLayerKind::Tiles(layer) = &mut self.layers[index] else {
+            unreachable!();
+        };
+        layer
+    }
+
+    // Tiled never reuses ids, even of deleted layers and objects
+    fn update_next_ids(&mut self) {
+        let highest_layer = self
+            .layers
+            .iter()
+            .filter_map(|layer| match layer {
+                TiledLayerKind::Tiles(layer) => Some(layer.id),
+                TiledLayerKind::Objects(layer) => Some(layer.id),
+                _ => None,
+            })
+            .max()
+            .unwrap_or(0);
+        let highest_object = self.objects().map(|object| object.id).max().unwrap_or(0);
+        self.nextlayerid = self.nextlayerid.max(highest_layer + 1);
+        self.nextobjectid = self.nextobjectid.max(highest_object + 1);
+    }
+
+    fn next_layer_id(&mut self) -> i32 {
+        self.update_next_ids();
+        self.nextlayerid += 1;
+        self.nextlayerid - 1
+    }
+
+    fn next_object_id(&mut self) -> u32 {
+        self.update_next_ids();
+        self.nextobjectid += 1;
+        self.nextobjectid - 1
+    }
+
+    // Writes the decoded tiles back into the layer data, call before saving a resolved map
+    pub fn encode(&mut self) {
+        for layer in self.tile_layers_mut() {
+            layer.encode();
+        }
+        self.update_next_ids();
+    }
+
+    pub fn objects_mut(&mut self) -> impl Iterator<Item = &mut TiledObject> {
+        self.layers
+            .iter_mut()
+            .filter_map(|layer| match layer {
+                TiledLayerKind::Objects(layer) => Some(layer.objects.iter_mut()),
+                _ => None,
+            })
+            .flatten()
+    }
+
+    // Objects go into the first object layer, which is created if needed
+    pub fn add_object(&mut self, mut object: TiledObject) {
+        object.id = self.next_object_id();
+        if !self
+            .layers
+            .iter()
+            .any(|layer| matches!(layer, TiledLayerKind::Objects(_)))
+        {
+            let id = self.next_layer_id();
+            self.layers.push(TiledLayerKind::Objects(TiledObjectLayer {
+                id,
+                name: "objects".to_string(),
+                objects: vec![],
+                properties: TiledProperties::default(),
+                extra: Default::default(),
+            }));
+        }
+        for layer in self.layers.iter_mut() {
+            if let TiledLayerKind::Objects(layer) = layer {
+                layer.objects.push(object);
+                return;
+            }
+        }
+    }
+
+    pub fn remove_objects(&mut self, remove: impl Fn(&TiledObject) -> bool) {
+        for layer in self.layers.iter_mut() {
+            if let TiledLayerKind::Objects(layer) = layer {
+                layer.objects.retain(|object| !remove(object));
+            }
+        }
+    }
+
+    pub fn objects(&self) -> impl Iterator<Item = &TiledObject> {
+        self.layers
+            .iter()
+            .filter_map(|layer| match layer {
+                TiledLayerKind::Objects(layer) => Some(layer.objects.iter()),
+                _ => None,
+            })
+            .flatten()
+    }
+
+    // Decodes the layer data and resolves tileset paths relative to the map file
+    pub fn resolve(&mut self, map_path: &Path) -> Result<(), TiledError> {
+        if self.orientation != "isometric" {
+            return Err(TiledError::Unsupported(format!(
+                "{} maps, only isometric maps can be played",
+                self.orientation
+            )));
+        }
+
+        for layer in self.layers.iter_mut() {
+            match layer {
+                TiledLayerKind::Tiles(layer) => layer.decode()?,
+                TiledLayerKind::Objects(_) => {}
+                TiledLayerKind::Image(_) => {
+                    return Err(TiledError::Unsupported("image layers".to_string()))
+                }
+                TiledLayerKind::Group(_) => {
+                    return Err(TiledError::Unsupported("group layers".to_string()))
+                }
+            }
+        }
+
+        let directory = map_path.parent().unwrap_or(Path::new(""));
+        for tileset in self.tilesets.iter_mut() {
+            let Some(source) = &tileset.source else {
+                return Err(TiledError::Unsupported(
+                    "embedded tilesets, export the tileset to a .tsj file".to_string(),
+                ));
+            };
+            tileset.path = join_asset_path(directory, source);
+        }
+        Ok(())
+    }
+}
+
+// Resolves "..", asset paths can't point outside the asset folder anyway
+pub fn join_asset_path(directory: &Path, relative: &str) -> PathBuf {
+    let mut path = PathBuf::new();
+    for component in directory.join(relative).components() {
+        match component {
+            std::path::Component::ParentDir => {
+                path.pop();
+            }
+            std::path::Component::CurDir => {}
+            component => path.push(component),
+        }
+    }
+    path
+}
+
+// Tiles of atlas tilesets only show up here if they have properties
+#[derive(Deserialize, Debug, Clone)]
 pub struct TiledTile {
     pub id: u32,
-    pub image: String,
+    pub image: Option<String>,
+    // size of the tile's own image, atlas tiles use the tileset's tile size
+    #[serde(default)]
+    pub imagewidth: u32,
+    #[serde(default)]
+    pub imageheight: u32,
+    #[serde(default)]
+    pub properties: TiledProperties,
+    // collision shapes drawn in Tiled's tile collision editor
+    pub objectgroup: Option<TiledCollisionGroup>,
+
+    // asset path of the image, filled by resolve
+    #[serde(skip)]
+    pub path: Option<PathBuf>,
 }
 
-impl TiledTile {
-    pub fn path(&self) -> String {
-        String::from("tileset/prototype/Tiles/") + &self.image[9..]
-    }
+#[derive(Deserialize, Debug, Clone)]
+pub struct TiledCollisionGroup {
+    #[serde(default)]
+    pub objects: Vec<TiledShape>,
+}
+
+// Rectangle, ellipse or polygon in pixels of the tile image, rotation is ignored
+#[derive(Deserialize, Debug, Clone)]
+pub struct TiledShape {
+    pub x: f32,
+    pub y: f32,
+    #[serde(default)]
+    pub width: f32,
+    #[serde(default)]
+    pub height: f32,
+    #[serde(default)]
+    pub ellipse: bool,
+    // relative to x and y
+    pub polygon: Option<Vec<TiledPoint>>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+pub struct TiledPoint {
+    pub x: f32,
+    pub y: f32,
 }
 
 #[derive(Deserialize, Debug, Clone, TypeUuid)]
@@ -39,7 +780,241 @@ impl TiledTile {
 pub struct TiledSet {
     pub name: String,
     pub tilecount: u32,
+    #[serde(default)]
     pub tiles: Vec<TiledTile>,
+    #[serde(default)]
+    pub properties: TiledProperties,
     pub tileheight: u32,
     pub tilewidth: u32,
+    // atlas tilesets use a single image, cut into a grid
+    pub image: Option<String>,
+    #[serde(default)]
+    pub columns: u32,
+    #[serde(default)]
+    pub margin: u32,
+    #[serde(default)]
+    pub spacing: u32,
+
+    // asset path of the atlas image, filled by resolve
+    #[serde(skip)]
+    pub image_path: Option<PathBuf>,
+}
+
+impl TiledSet {
+    // Image paths are relative to the tileset file
+    pub fn resolve(&mut self, tileset_path: &Path) -> Result<(), TiledError> {
+        let directory = tileset_path.parent().unwrap_or(Path::new(""));
+        self.image_path = self
+            .image
+            .as_ref()
+            .map(|image| join_asset_path(directory, image));
+        for tile in self.tiles.iter_mut() {
+            tile.path = tile
+                .image
+                .as_ref()
+                .map(|image| join_asset_path(directory, image));
+        }
+
+        if self.image_path.is_some() {
+            if self.columns == 0 {
+                return Err(TiledError::Invalid(format!(
+                    "atlas tileset '{}' has no columns",
+                    self.name
+                )));
+            }
+        } else if self.tiles.iter().any(|tile| tile.path.is_none()) {
+            return Err(TiledError::Invalid(format!(
+                "tileset '{}' has tiles without an image",
+                self.name
+            )));
+        }
+        Ok(())
+    }
+
+    // every image the tileset needs
+    pub fn image_paths(&self) -> impl Iterator<Item = &PathBuf> {
+        self.image_path
+            .iter()
+            .chain(self.tiles.iter().filter_map(|tile| tile.path.as_ref()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, io::Write};
+
+    use flate2::{write::ZlibEncoder, Compression};
+    use serde_json::{json, Value};
+
+    use super::*;
+
+    const MAP: &str = "tilemap/1.tmj";
+
+    fn load(path: &str) -> (Value, TiledMap) {
+        let text = fs::read_to_string(
+            Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("assets")
+                .join(path),
+        )
+        .unwrap();
+        let mut tilemap: TiledMap = serde_json::from_str(&text).unwrap();
+        tilemap.resolve(Path::new(path)).unwrap();
+        (serde_json::from_str(&text).unwrap(), tilemap)
+    }
+
+    // 1 and 1.0 are the same number to Tiled
+    fn normalize(value: Value) -> Value {
+        match value {
+            Value::Number(number) => number.as_f64().map_or(Value::Number(number), Value::from),
+            Value::Array(values) => values.into_iter().map(normalize).collect(),
+            Value::Object(fields) => fields
+                .into_iter()
+                .map(|(name, value)| (name, normalize(value)))
+                .collect(),
+            value => value,
+        }
+    }
+
+    fn saved(tilemap: &TiledMap) -> Value {
+        normalize(serde_json::to_value(tilemap).unwrap())
+    }
+
+    fn reload(tilemap: &TiledMap) -> TiledMap {
+        let mut reloaded: TiledMap =
+            serde_json::from_value(serde_json::to_value(tilemap).unwrap()).unwrap();
+        reloaded.resolve(Path::new(MAP)).unwrap();
+        reloaded
+    }
+
+    fn tiles(tilemap: &TiledMap) -> Vec<HashMap<(i32, i32), TiledGid>> {
+        tilemap
+            .tile_layers()
+            .map(|layer| layer.tiles().collect())
+            .collect()
+    }
+
+    #[test]
+    fn saving_a_loaded_map_keeps_the_file() {
+        let (original, tilemap) = load(MAP);
+        assert_eq!(saved(&tilemap), normalize(original));
+    }
+
+    #[test]
+    fn encoding_an_unchanged_map_keeps_the_file() {
+        let (original, mut tilemap) = load(MAP);
+        tilemap.encode();
+        assert_eq!(saved(&tilemap), normalize(original));
+    }
+
+    #[test]
+    fn edits_survive_saving() {
+        let (_, mut tilemap) = load(MAP);
+        let flipped = TiledGid(5 | FLIPPED_HORIZONTALLY);
+        let layer = tilemap.tile_layers_mut().next().unwrap();
+        layer.set_gid((0, 0), Some(flipped));
+        layer.set_gid((1, 1), None);
+        tilemap.add_tile_layer().set_gid((2, 3), Some(TiledGid(7)));
+        let mut chest = TiledObject::point("chest", 55., 55.);
+        chest.properties.set("heal", TiledPropertyValue::Int(2));
+        tilemap.add_object(chest);
+        tilemap.encode();
+
+        let reloaded = reload(&tilemap);
+        assert_eq!(tiles(&reloaded), tiles(&tilemap));
+        let layers: Vec<&TiledLayer> = reloaded.tile_layers().collect();
+        assert_eq!(layers[0].gid_at(&(0, 0)), Some(flipped));
+        assert_eq!(layers[0].gid_at(&(1, 1)), None);
+        assert_eq!(layers[2].gid_at(&(2, 3)), Some(TiledGid(7)));
+        // ids continue where Tiled stopped
+        assert_eq!(layers[2].id, 4);
+        assert_eq!(reloaded.nextlayerid, 5);
+        let chest = reloaded.objects().last().unwrap();
+        assert_eq!((chest.id, chest.properties.int("heal")), (8, Some(2)));
+        assert_eq!(reloaded.nextobjectid, 9);
+    }
+
+    #[test]
+    fn compressed_layers_are_saved_as_csv() {
+        let gids: Vec<u32> = vec![0, 113, 114, 0, 0, 120];
+        let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+        for gid in &gids {
+            encoder.write_all(&gid.to_le_bytes()).unwrap();
+        }
+        let data = base64::engine::general_purpose::STANDARD.encode(encoder.finish().unwrap());
+
+        let (_, mut tilemap) = load(MAP);
+        let mut layer: TiledLayer = serde_json::from_value(json!({
+            "data": data,
+            "encoding": "base64",
+            "compression": "zlib",
+            "id": 1,
+            "name": "compressed",
+            "width": 3,
+            "height": 2,
+        }))
+        .unwrap();
+        layer.decode().unwrap();
+        layer.encode();
+        assert_eq!(layer.encoding, None);
+        assert_eq!(layer.compression, None);
+        assert!(matches!(&layer.data, Some(TiledData::Csv(csv)) if *csv == gids));
+
+        tilemap.layers = vec![TiledLayerKind::Tiles(layer)];
+        assert_eq!(tiles(&reload(&tilemap)), tiles(&tilemap));
+    }
+
+    #[test]
+    fn truncated_layer_data_is_an_error() {
+        let mut bytes: Vec<u8> = [113u32, 114]
+            .iter()
+            .flat_map(|gid| gid.to_le_bytes())
+            .collect();
+        bytes.pop();
+        let data = TiledData::Encoded(base64::engine::general_purpose::STANDARD.encode(bytes));
+        assert!(matches!(
+            decode_data(&data, Some("base64"), None),
+            Err(TiledError::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn unknown_fields_and_layers_are_kept() {
+        let group = json!({
+            "type": "group",
+            "id": 7,
+            "name": "decoration",
+            "layers": [{ "type": "imagelayer", "id": 8, "image": "sky.png" }],
+        });
+        let layer: TiledLayerKind = serde_json::from_value(group.clone()).unwrap();
+        assert_eq!(serde_json::to_value(&layer).unwrap(), group);
+
+        let object = json!({
+            "id": 1,
+            "name": "",
+            "type": "door",
+            "x": 110.0,
+            "y": 220.0,
+            "rotation": 0,
+            "point": true,
+            "properties": [{ "name": "target", "type": "object", "value": 2 }],
+        });
+        let mut tiled_object: TiledObject = serde_json::from_value(object.clone()).unwrap();
+        assert_eq!(serde_json::to_value(&tiled_object).unwrap(), object);
+        // changing a value keeps the property's type
+        tiled_object
+            .properties
+            .set("target", TiledPropertyValue::Int(3));
+        assert_eq!(
+            serde_json::to_value(&tiled_object).unwrap()["properties"][0]["type"],
+            "object"
+        );
+    }
+
+    #[test]
+    fn the_class_key_of_tiled_1_9_is_kept() {
+        let object = json!({ "id": 1, "name": "", "class": "chest", "x": 0.0, "y": 0.0 });
+        let tiled_object: TiledObject = serde_json::from_value(object.clone()).unwrap();
+        assert_eq!(tiled_object.class(), "chest");
+        assert_eq!(serde_json::to_value(&tiled_object).unwrap(), object);
+    }
 }
@@ -0,0 +1,499 @@
+use bevy::prelude::*;
+
+use super::{
+    game_state::{GameState, GameStateEvent, GameStates},
+    input::Action,
+    isometric::IsometricDirection,
+    map::{select_tile, Battlefield, MapLayout, MapState, Tile},
+    picking::PickState,
+    status_effects::StatusEffect,
+    unit::{Unit, UnitRegistry},
+    vision::{visible_unit_at, Vision},
+    GameEvent, GameSystemSets,
+};
+
+pub struct AbilitiesPlugin;
+
+impl Plugin for AbilitiesPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems((
+            preview_ability.in_set(GameSystemSets::Logic),
+            confirm_ability
+                .run_if(should_confirm_ability)
+                .in_set(GameSystemSets::Logic)
+                .after(select_tile)
+                .before(preview_ability),
+            resolve_abilities.in_set(GameSystemSets::Update),
+            start_turn_abilities.in_set(GameSystemSets::Update),
+        ));
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TargetShape {
+    Single,
+    // straight line away from the caster
+    Line(u32),
+    // widens by one tile to each side per step away from the caster
+    Cone(u32),
+    // every tile within this distance of the target
+    Radius(u32),
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TargetKind {
+    Enemy,
+    Ally,
+    EmptyTile,
+    // line and cone abilities only pick a direction
+    Direction,
+    AnyTile,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum AbilityEffect {
+    Damage(u32),
+    Heal(u32),
+    // applied to every enemy in the area
+    ApplyEffect(StatusEffect),
+    // run along the line, then damage and knock back the first unit in the way
+    Charge(u32),
+    // knock units back by this many tiles
+    Push(u32),
+    // drag units up to this many tiles towards the caster
+    Pull(u32),
+    Teleport,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Ability {
+    pub name: &'static str,
+    pub cost: u32,
+    // in turns of the unit
+    pub cooldown: u32,
+    pub range: u32,
+    pub shape: TargetShape,
+    pub target: TargetKind,
+    pub effect: AbilityEffect,
+}
+
+struct AbilitySlot {
+    ability: Ability,
+    cooldown: u32,
+}
+
+#[derive(Component)]
+pub struct Abilities {
+    pub energy: u32,
+    pub max_energy: u32,
+    slots: Vec<AbilitySlot>,
+}
+
+impl Abilities {
+    pub fn new(max_energy: u32, abilities: Vec<Ability>) -> Self {
+        Abilities {
+            energy: max_energy,
+            max_energy,
+            slots: abilities
+                .into_iter()
+                .map(|ability| AbilitySlot {
+                    ability,
+                    cooldown: 0,
+                })
+                .collect(),
+        }
+    }
+
+    pub fn get(&self, index: usize) -> Option<&Ability> {
+        self.slots.get(index).map(|slot| &slot.ability)
+    }
+
+    pub fn cooldown(&self, index: usize) -> u32 {
+        self.slots.get(index).map_or(0, |slot| slot.cooldown)
+    }
+
+    pub fn can_use(&self, index: usize) -> bool {
+        self.slots
+            .get(index)
+            .is_some_and(|slot| slot.cooldown == 0 && slot.ability.cost <= self.energy)
+    }
+
+    fn use_ability(&mut self, index: usize) {
+        let slot = &mut self.slots[index];
+        self.energy -= slot.ability.cost;
+        slot.cooldown = slot.ability.cooldown;
+    }
+
+    // every unit regains one energy per turn
+    fn start_turn(&mut self) {
+        self.energy = (self.energy + 1).min(self.max_energy);
+        for slot in self.slots.iter_mut() {
+            slot.cooldown = slot.cooldown.saturating_sub(1);
+        }
+    }
+}
+
+fn distance(a: &(i32, i32), b: &(i32, i32)) -> u32 {
+    (a.0 - b.0).unsigned_abs() + (a.1 - b.1).unsigned_abs()
+}
+
+fn step(tile: &(i32, i32), direction: (i32, i32), steps: i32) -> (i32, i32) {
+    (tile.0 + direction.0 * steps, tile.1 + direction.1 * steps)
+}
+
+// Tiles the ability can be aimed at
+pub fn target_tiles(
+    ability: &Ability,
+    caster: &(i32, i32),
+    map_layout: &MapLayout,
+) -> Vec<(i32, i32)> {
+    if ability.target == TargetKind::Direction {
+        return IsometricDirection::all()
+            .iter()
+            .map(|direction| step(caster, direction.to_vec(), 1))
+            .filter(|tile| map_layout.tiles.contains_key(tile))
+            .collect();
+    }
+
+    map_layout
+        .tiles
+        .keys()
+        .filter(|tile| distance(caster, tile) <= ability.range)
+        .copied()
+        .collect()
+}
+
+// Tiles hit by the ability when aimed at the target
+pub fn affected_tiles(
+    ability: &Ability,
+    caster: &(i32, i32),
+    target: &(i32, i32),
+) -> Vec<(i32, i32)> {
+    let direction = IsometricDirection::between(caster, target);
+    match (ability.shape, direction) {
+        (TargetShape::Single, _) => vec![*target],
+        (TargetShape::Line(length), Some(direction)) => (1..=length as i32)
+            .map(|i| step(caster, direction.to_vec(), i))
+            .collect(),
+        (TargetShape::Cone(length), Some(direction)) => {
+            let side = direction.perpendicular().to_vec();
+            (1..=length as i32)
+                .flat_map(|i| {
+                    let center = step(caster, direction.to_vec(), i);
+                    (-(i - 1)..=(i - 1)).map(move |j| step(&center, side, j))
+                })
+                .collect()
+        }
+        (TargetShape::Radius(radius), _) => {
+            let radius = radius as i32;
+            (-radius..=radius)
+                .flat_map(|dx| {
+                    let remaining = radius - dx.abs();
+                    (-remaining..=remaining).map(move |dy| (target.0 + dx, target.1 + dy))
+                })
+                .collect()
+        }
+        // lines and cones need a straight direction
+        _ => vec![],
+    }
+}
+
+pub fn is_valid_target(
+    ability: &Ability,
+    caster: &Unit,
+    target: &(i32, i32),
+    map_layout: &MapLayout,
+    vision: &Vision,
+    unit_registry: &UnitRegistry,
+    units: &Query<&Unit>,
+) -> bool {
+    if !target_tiles(ability, &caster.tile(), map_layout).contains(target) {
+        return false;
+    }
+    // units can only be pushed and pulled in straight lines
+    if matches!(
+        ability.effect,
+        AbilityEffect::Push(_) | AbilityEffect::Pull(_)
+    ) && IsometricDirection::between(&caster.tile(), target).is_none()
+    {
+        return false;
+    }
+
+    let target_unit = visible_unit_at(target, caster.owner, vision, unit_registry, units)
+        .and_then(|entity| units.get(entity).ok());
+    match ability.target {
+        TargetKind::Enemy => target_unit.is_some_and(|unit| unit.owner != caster.owner),
+        TargetKind::Ally => target_unit.is_some_and(|unit| unit.owner == caster.owner),
+        TargetKind::EmptyTile => target_unit.is_none() && vision.can_see(caster.owner, target),
+        TargetKind::Direction | TargetKind::AnyTile => true,
+    }
+}
+
+fn preview_ability(
+    battlefield: Battlefield,
+    pick_state: Res<PickState>,
+    tiles: Query<&Tile>,
+    game_state: Res<GameState>,
+    units: Query<&Unit>,
+    abilities: Query<&Abilities>,
+    mut map_state: ResMut<MapState>,
+) {
+    let Some((caster, index)) = map_state.ability_targeting else {
+        return;
+    };
+    let (Ok(unit), Some(ability)) = (
+        units.get(caster),
+        abilities
+            .get(caster)
+            .ok()
+            .and_then(|abilities| abilities.get(index)),
+    ) else {
+        map_state.clear_selection();
+        return;
+    };
+    // the turn ended some other way
+    if game_state.current_unit().map(|(_, entity)| entity) != Some(caster) {
+        map_state.clear_selection();
+        return;
+    }
+
+    let range = target_tiles(ability, &unit.tile(), &battlefield.map_layout);
+    let area = match pick_state.selected.map(|tile| tiles.get(tile)) {
+        Some(Ok(hovered))
+            if is_valid_target(
+                ability,
+                unit,
+                &(hovered.x, hovered.y),
+                &battlefield.map_layout,
+                &battlefield.vision,
+                &battlefield.unit_registry,
+                &units,
+            ) =>
+        {
+            affected_tiles(ability, &unit.tile(), &(hovered.x, hovered.y))
+        }
+        _ => vec![],
+    };
+    map_state.preview_ability(range, area);
+}
+
+fn should_confirm_ability(
+    actions: Res<Input<Action>>,
+    map_state: Res<MapState>,
+    game_state: Res<GameState>,
+) -> bool {
+    actions.just_pressed(Action::Confirm)
+        && !map_state.unit_moving
+        && map_state.ability_targeting.is_some()
+        && matches!(game_state.state, GameStates::Turn(_, _))
+}
+
+fn confirm_ability(
+    battlefield: Battlefield,
+    pick_state: Res<PickState>,
+    tiles: Query<&Tile>,
+    units: Query<&Unit>,
+    abilities: Query<&Abilities>,
+    mut map_state: ResMut<MapState>,
+    mut event_writer: EventWriter<GameEvent>,
+) {
+    let Some((caster, index)) = map_state.ability_targeting else {
+        return;
+    };
+    // clicks on the ui don't hit any tile
+    let Some(Ok(tile)) = pick_state.selected.map(|tile| tiles.get(tile)) else {
+        return;
+    };
+    // clicking a tile that is no valid target cancels the ability
+    map_state.clear_selection();
+
+    let (Ok(unit), Some(ability)) = (
+        units.get(caster),
+        abilities
+            .get(caster)
+            .ok()
+            .and_then(|abilities| abilities.get(index)),
+    ) else {
+        return;
+    };
+    let target = (tile.x, tile.y);
+    if is_valid_target(
+        ability,
+        unit,
+        &target,
+        &battlefield.map_layout,
+        &battlefield.vision,
+        &battlefield.unit_registry,
+        &units,
+    ) {
+        event_writer.send(GameEvent::UseAbility(caster, index, target));
+    }
+}
+
+fn start_turn_abilities(
+    mut event_reader: EventReader<GameEvent>,
+    mut abilities: Query<&mut Abilities>,
+) {
+    for event in event_reader.iter() {
+        if let GameEvent::TurnStarted(entity) = event {
+            if let Ok(mut abilities) = abilities.get_mut(*entity) {
+                abilities.start_turn();
+            }
+        }
+    }
+}
+
+// Ground units can only be moved onto free tiles that are not higher than where they stand
+fn can_be_moved_to(
+    from: &(i32, i32),
+    to: &(i32, i32),
+    map_layout: &MapLayout,
+    unit_registry: &UnitRegistry,
+) -> bool {
+    match (map_layout.tiles.get(from), map_layout.tiles.get(to)) {
+        (Some(from_height), Some(to_height)) => {
+            to_height <= from_height
+                && !map_layout.blocked.contains(to)
+                && !unit_registry.units.contains_key(to)
+        }
+        _ => false,
+    }
+}
+
+fn relocate_unit(
+    entity: Entity,
+    unit: &mut Unit,
+    to: (i32, i32),
+    map_layout: &MapLayout,
+    unit_registry: &mut UnitRegistry,
+) {
+    let Some(height) = map_layout.tiles.get(&to) else {
+        warn!("can't move a unit to {to:?}, the tile is not part of the map");
+        return;
+    };
+    unit_registry.units.remove(&unit.tile());
+    unit.x = to.0 as f32;
+    unit.y = to.1 as f32;
+    unit.z = *height as f32;
+    unit_registry.units.insert(to, entity);
+}
+
+fn resolve_abilities(
+    mut event_reader: EventReader<GameEvent>,
+    mut game_events: EventWriter<GameEvent>,
+    mut game_state_events: EventWriter<GameStateEvent>,
+    map_layout: Res<MapLayout>,
+    mut unit_registry: ResMut<UnitRegistry>,
+    mut units: Query<(&mut Unit, &mut Abilities)>,
+) {
+    for event in event_reader.iter() {
+        let GameEvent::UseAbility(caster, index, target) = event else {
+            continue;
+        };
+        let Ok((mut caster_unit, mut abilities)) = units.get_mut(*caster) else {
+            continue;
+        };
+        if !abilities.can_use(*index) {
+            continue;
+        }
+        let ability = *abilities.get(*index).unwrap();
+        abilities.use_ability(*index);
+
+        let location = caster_unit.tile();
+        let owner = caster_unit.owner;
+        let affected = affected_tiles(&ability, &location, target);
+        let units_hit: Vec<Entity> = affected
+            .iter()
+            .filter_map(|tile| unit_registry.units.get(tile))
+            .copied()
+            .filter(|entity| entity != caster)
+            .collect();
+
+        match ability.effect {
+            AbilityEffect::Damage(damage) => {
+                for entity in units_hit {
+                    game_events.send(GameEvent::Damage(entity, damage));
+                }
+            }
+            AbilityEffect::Heal(heal) => {
+                for entity in units_hit {
+                    if let Ok((mut unit, _)) = units.get_mut(entity) {
+                        if unit.owner == owner {
+                            unit.health = (unit.health + heal).min(unit.max_health);
+                        }
+                    }
+                }
+            }
+            AbilityEffect::ApplyEffect(effect) => {
+                for entity in units_hit {
+                    if units.get(entity).is_ok_and(|(unit, _)| unit.owner != owner) {
+                        game_events.send(GameEvent::ApplyEffect(entity, effect));
+                    }
+                }
+            }
+            AbilityEffect::Charge(damage) => {
+                let mut destination = location;
+                for tile in affected.iter() {
+                    if let Some(entity) = unit_registry.units.get(tile) {
+                        game_events.send(GameEvent::Damage(*entity, damage));
+                        if let Some(direction) = IsometricDirection::between(&location, tile) {
+                            game_events.send(GameEvent::Knockback(*entity, direction, 1));
+                        }
+                        break;
+                    }
+                    if !can_be_moved_to(&destination, tile, &map_layout, &unit_registry) {
+                        break;
+                    }
+                    destination = *tile;
+                }
+                relocate_unit(
+                    *caster,
+                    &mut caster_unit,
+                    destination,
+                    &map_layout,
+                    &mut unit_registry,
+                );
+            }
+            AbilityEffect::Push(tiles) => {
+                for entity in units_hit {
+                    let Ok((unit, _)) = units.get(entity) else {
+                        continue;
+                    };
+                    if let Some(direction) = IsometricDirection::between(&location, &unit.tile()) {
+                        game_events.send(GameEvent::Knockback(entity, direction, tiles));
+                    }
+                }
+            }
+            AbilityEffect::Pull(tiles) => {
+                for entity in units_hit {
+                    let Ok((unit, _)) = units.get(entity) else {
+                        continue;
+                    };
+                    let tile = unit.tile();
+                    let Some(direction) = IsometricDirection::between(&tile, &location) else {
+                        continue;
+                    };
+                    // pulled units stop right in front of the caster
+                    let tiles = tiles.min(distance(&tile, &location).saturating_sub(1));
+                    game_events.send(GameEvent::Knockback(entity, direction, tiles));
+                }
+            }
+            AbilityEffect::Teleport => {
+                if map_layout.tiles.contains_key(target)
+                    && !map_layout.blocked.contains(target)
+                    && !unit_registry.units.contains_key(target)
+                {
+                    relocate_unit(
+                        *caster,
+                        &mut caster_unit,
+                        *target,
+                        &map_layout,
+                        &mut unit_registry,
+                    );
+                }
+            }
+        }
+
+        game_state_events.send(GameStateEvent::UsedAbility);
+    }
+}
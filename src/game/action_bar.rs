@@ -0,0 +1,259 @@
+use bevy::prelude::*;
+
+use crate::{game_config::GameAssets, AppState};
+
+use super::{
+    abilities::Abilities,
+    game_state::{GameState, GameStateEvent, Participant, TurnAction},
+    input::{update_actions, Action},
+    map::MapState,
+    GameSystemSets,
+};
+
+// labels name the default bindings
+const ACTIONS: [(TurnAction, &str, Action); 3] = [
+    (TurnAction::Skip, "Skip (S)", Action::Skip),
+    (TurnAction::Defend, "Defend (D)", Action::Defend),
+    (TurnAction::Delay, "Delay (W)", Action::Delay),
+];
+
+const ABILITY_SLOTS: usize = 3;
+
+const BUTTON_COLOR: Color = Color::rgb(0.2, 0.2, 0.2);
+const BUTTON_HOVER_COLOR: Color = Color::rgb(0.35, 0.35, 0.35);
+const DISABLED_TEXT_COLOR: Color = Color::rgb(0.5, 0.5, 0.5);
+
+pub struct ActionBarPlugin;
+
+impl Plugin for ActionBarPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems((
+            create_action_bar.in_schedule(OnEnter(AppState::Game)),
+            destroy_action_bar.in_schedule(OnExit(AppState::Game)),
+            action_input
+                .in_set(GameSystemSets::Input)
+                .after(update_actions),
+            update_action_bar.in_set(GameSystemSets::Render),
+            highlight_buttons.in_set(GameSystemSets::Render),
+        ));
+    }
+}
+
+#[derive(Component)]
+struct ActionBar;
+
+#[derive(Component)]
+struct ActionButton(TurnAction);
+
+// ability slot of the current unit
+#[derive(Component)]
+struct AbilityButton(usize);
+
+#[derive(Component)]
+struct EnergyText;
+
+fn spawn_button(
+    parent: &mut ChildBuilder,
+    label: &str,
+    marker: impl Component,
+    game_assets: &GameAssets,
+) {
+    parent
+        .spawn((
+            ButtonBundle {
+                style: Style {
+                    size: Size::new(Val::Px(140.), Val::Px(50.)),
+                    justify_content: JustifyContent::Center,
+                    align_items: AlignItems::Center,
+                    ..default()
+                },
+                background_color: BUTTON_COLOR.into(),
+                ..default()
+            },
+            marker,
+        ))
+        .with_children(|button| {
+            button.spawn(TextBundle::from_section(
+                label,
+                TextStyle {
+                    font: game_assets.font.clone(),
+                    font_size: 22.,
+                    color: Color::WHITE,
+                },
+            ));
+        });
+}
+
+fn create_action_bar(mut commands: Commands, game_assets: Res<GameAssets>) {
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    size: Size::new(Val::Percent(100.), Val::Px(60.)),
+                    position_type: PositionType::Absolute,
+                    position: UiRect {
+                        bottom: Val::Px(10.),
+                        ..default()
+                    },
+                    justify_content: JustifyContent::Center,
+                    align_items: AlignItems::Center,
+                    gap: Size::width(Val::Px(10.)),
+                    ..default()
+                },
+                visibility: Visibility::Hidden,
+                ..default()
+            },
+            ActionBar,
+        ))
+        .with_children(|parent| {
+            for (action, label, _) in ACTIONS {
+                spawn_button(parent, label, ActionButton(action), &game_assets);
+            }
+
+            parent.spawn((
+                TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font: game_assets.font.clone(),
+                        font_size: 22.,
+                        color: Color::rgb(0.4, 0.8, 1.0),
+                    },
+                ),
+                EnergyText,
+            ));
+            // labels are filled in from the current unit's abilities
+            for index in 0..ABILITY_SLOTS {
+                spawn_button(parent, "", AbilityButton(index), &game_assets);
+            }
+        });
+}
+
+fn destroy_action_bar(mut commands: Commands, query: Query<Entity, With<ActionBar>>) {
+    if let Ok(action_bar) = query.get_single() {
+        commands.entity(action_bar).despawn_recursive();
+    }
+}
+
+fn can_act(game_state: &GameState, map_state: &MapState) -> bool {
+    let Some((participant, _)) = game_state.current_unit() else {
+        return false;
+    };
+    game_state.participants[participant] == Participant::Me && !map_state.unit_moving
+}
+
+fn action_input(
+    actions: Res<Input<Action>>,
+    buttons: Query<(&Interaction, &ActionButton), Changed<Interaction>>,
+    ability_buttons: Query<(&Interaction, &AbilityButton), Changed<Interaction>>,
+    abilities: Query<&Abilities>,
+    game_state: Res<GameState>,
+    mut map_state: ResMut<MapState>,
+    mut event_writer: EventWriter<GameStateEvent>,
+) {
+    if !can_act(&game_state, &map_state) {
+        return;
+    }
+
+    let mut action = ACTIONS
+        .iter()
+        .find(|(_, _, input)| actions.just_pressed(*input))
+        .map(|(action, _, _)| *action);
+    for (interaction, button) in buttons.iter() {
+        if *interaction == Interaction::Clicked {
+            action = Some(button.0);
+        }
+    }
+
+    if let Some(action) = action {
+        map_state.clear_selection();
+        event_writer.send(GameStateEvent::TurnAction(action));
+        return;
+    }
+
+    let mut ability =
+        (0..ABILITY_SLOTS).find(|index| actions.just_pressed(Action::Ability(*index)));
+    for (interaction, button) in ability_buttons.iter() {
+        if *interaction == Interaction::Clicked {
+            ability = Some(button.0);
+        }
+    }
+
+    // the ability gets aimed and confirmed on the map
+    if let (Some(index), Some((_, entity))) = (ability, game_state.current_unit()) {
+        if abilities
+            .get(entity)
+            .is_ok_and(|abilities| abilities.can_use(index))
+        {
+            map_state.clear_selection();
+            map_state.ability_targeting = Some((entity, index));
+        }
+    }
+}
+
+type ButtonQuery<'w, 's> = Query<
+    'w,
+    's,
+    (&'static Interaction, &'static mut BackgroundColor),
+    Or<(With<ActionButton>, With<AbilityButton>)>,
+>;
+
+fn highlight_buttons(mut buttons: ButtonQuery) {
+    for (interaction, mut color) in buttons.iter_mut() {
+        *color = match interaction {
+            Interaction::None => BUTTON_COLOR.into(),
+            _ => BUTTON_HOVER_COLOR.into(),
+        };
+    }
+}
+
+fn update_action_bar(
+    game_state: Res<GameState>,
+    map_state: Res<MapState>,
+    mut action_bar: Query<&mut Visibility, With<ActionBar>>,
+    ability_buttons: Query<(&AbilityButton, &Children)>,
+    mut energy_text: Query<&mut Text, With<EnergyText>>,
+    mut texts: Query<&mut Text, Without<EnergyText>>,
+    abilities: Query<&Abilities>,
+) {
+    if let Ok(mut visibility) = action_bar.get_single_mut() {
+        *visibility = if can_act(&game_state, &map_state) {
+            Visibility::Inherited
+        } else {
+            Visibility::Hidden
+        };
+    }
+
+    let Some(abilities) = game_state
+        .current_unit()
+        .and_then(|(_, entity)| abilities.get(entity).ok())
+    else {
+        return;
+    };
+    if let Ok(mut text) = energy_text.get_single_mut() {
+        text.sections[0].value = format!("Energy {}/{}", abilities.energy, abilities.max_energy);
+    }
+    for (button, children) in ability_buttons.iter() {
+        let Some(mut text) = children
+            .first()
+            .and_then(|child| texts.get_mut(*child).ok())
+        else {
+            continue;
+        };
+        let section = &mut text.sections[0];
+        let Some(ability) = abilities.get(button.0) else {
+            section.value = String::new();
+            continue;
+        };
+
+        let key = button.0 + 1;
+        section.value = match abilities.cooldown(button.0) {
+            0 => format!("{} ({}) {}E", ability.name, key, ability.cost),
+            cooldown => format!("{} ({}) {}T", ability.name, key, cooldown),
+        };
+        section.style.color = if abilities.can_use(button.0) {
+            Color::WHITE
+        } else {
+            DISABLED_TEXT_COLOR
+        };
+    }
+}
@@ -0,0 +1,119 @@
+use bevy::prelude::*;
+
+use super::{
+    combat::find_attack_targets,
+    game_state::{GameState, GameStateEvent, GameStates, TurnAction},
+    map::{can_end_move_on, find_unit_paths, to_path, Battlefield, MapState},
+    unit::Unit,
+    GameEvent, GameSystemSets,
+};
+
+pub struct AiPlugin;
+
+impl Plugin for AiPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system(take_ai_turn.in_set(GameSystemSets::Update));
+    }
+}
+
+// Bots only know what their own units see, same as the player
+fn take_ai_turn(
+    battlefield: Battlefield,
+    mut event_reader: EventReader<GameEvent>,
+    mut game_events: EventWriter<GameEvent>,
+    mut game_state_events: EventWriter<GameStateEvent>,
+    game_state: Res<GameState>,
+    mut map_state: ResMut<MapState>,
+    mut units: ParamSet<(Query<&Unit>, Query<&mut Unit>)>,
+) {
+    for event in event_reader.iter() {
+        let GameEvent::AiTurn(entity) = event else {
+            continue;
+        };
+        let GameStates::Turn(_, did_move) = game_state.state else {
+            continue;
+        };
+
+        let path = {
+            let units = units.p0();
+            let Ok(unit) = units.get(*entity) else {
+                continue;
+            };
+            let location = unit.tile();
+
+            // attack the weakest target in sight
+            let targets = find_attack_targets(
+                location,
+                unit,
+                &battlefield.map_layout,
+                &battlefield.unit_registry,
+                &units,
+                &battlefield.vision,
+            );
+            let target = targets
+                .iter()
+                .filter(|(_, in_sight)| **in_sight)
+                .filter_map(|(tile, _)| battlefield.unit_registry.units.get(tile))
+                .min_by_key(|target| units.get(**target).map_or(u32::MAX, |unit| unit.health));
+            if let Some(target) = target {
+                game_events.send(GameEvent::Attack(*entity, *target));
+                continue;
+            }
+            if did_move {
+                game_state_events.send(GameStateEvent::TurnAction(TurnAction::Skip));
+                continue;
+            }
+
+            // otherwise walk towards the closest enemy in sight
+            let distance = |a: &(i32, i32), b: &(i32, i32)| (a.0 - b.0).abs() + (a.1 - b.1).abs();
+            let goal = units
+                .iter()
+                .filter(|other| {
+                    other.owner != unit.owner && battlefield.vision.can_see_unit(unit.owner, other)
+                })
+                .map(|other| other.tile())
+                .min_by_key(|tile| distance(&location, tile));
+            let Some(goal) = goal else {
+                game_state_events.send(GameStateEvent::TurnAction(TurnAction::Skip));
+                continue;
+            };
+
+            let paths = find_unit_paths(
+                unit.travel_distance,
+                location,
+                &battlefield.map_layout,
+                &battlefield.unit_registry,
+                unit,
+                &units,
+                &battlefield.vision,
+            );
+            let destination = paths
+                .keys()
+                .filter(|tile| {
+                    can_end_move_on(
+                        tile,
+                        *entity,
+                        unit.owner,
+                        &battlefield.map_layout,
+                        &battlefield.vision,
+                        &battlefield.unit_registry,
+                        &units,
+                    )
+                })
+                .min_by_key(|tile| distance(tile, &goal))
+                .copied();
+            destination
+                .filter(|destination| *destination != location)
+                .and_then(|destination| to_path(paths, location, destination))
+        };
+
+        let Some(path) = path else {
+            game_state_events.send(GameStateEvent::TurnAction(TurnAction::Skip));
+            continue;
+        };
+        if let Ok(mut unit) = units.p1().get_mut(*entity) {
+            unit.move_path(path);
+            map_state.unit_moving = true;
+        }
+    }
+}
@@ -1,86 +1,190 @@
+use std::collections::HashMap;
+
 use bevy::{
-    prelude::{apply_system_buffers, Component, IntoSystemConfig, Plugin, Query, Rect, Res, Vec2},
+    prelude::{
+        apply_system_buffers, warn, Component, Entity, EventWriter, IntoSystemConfig, Plugin,
+        Query, Rect, Res,
+    },
     sprite::Sprite,
-    time::{Time, Timer},
+    time::Time,
 };
 
+use crate::assets::animation_sheet::{AnimationClip, AnimationSheet};
+
 use super::GameSystemSets;
 
 #[derive(Clone)]
 pub struct Animation {
     repeating: bool,
-    timer: Timer,
-    frames: Vec<Rect>,
+    frames: Vec<Frame>,
 }
 
-impl Animation {
-    pub fn new(
-        frame_duration_seconds: f32,
-        frame_w: u32,
-        frame_h: u32,
-        extrusion_x: u32,
-        extrusion_y: u32,
-        frames: Vec<(u32, u32)>,
-        repeating: bool,
-    ) -> Self {
-        let frames: Vec<Rect> = frames
-            .iter()
-            .map(|frame| {
-                let top_left = Vec2::new(
-                    (frame.0 * (frame_w + extrusion_x)) as f32,
-                    (frame.1 * (frame_h + extrusion_y)) as f32,
-                );
-                let bot_right = Vec2::new(top_left.x + frame_w as f32, top_left.y + frame_h as f32);
-                Rect {
-                    min: top_left,
-                    max: bot_right,
-                }
-            })
-            .collect();
+#[derive(Clone)]
+struct Frame {
+    rect: Rect,
+    seconds: f32,
+    event: Option<String>,
+}
 
+impl Animation {
+    pub fn from_clip(sheet: &AnimationSheet, clip: &AnimationClip) -> Self {
         Animation {
-            timer: Timer::from_seconds(frame_duration_seconds, bevy::time::TimerMode::Repeating),
-            frames,
-            repeating,
+            repeating: clip.repeating,
+            frames: clip
+                .frames
+                .iter()
+                .map(|frame| Frame {
+                    rect: sheet.frame_rect(frame),
+                    // validated by the loader
+                    seconds: clip.frame_seconds(frame).unwrap_or_default(),
+                    event: frame.event.clone(),
+                })
+                .collect(),
         }
     }
 }
 
+// Sent when a frame with an event attached is shown, e.g. a footstep or the moment a blow lands
+pub struct AnimationEvent {
+    pub entity: Entity,
+    pub name: String,
+}
+
+// A non-repeating animation played its last frame
+pub struct AnimationFinished {
+    pub entity: Entity,
+    pub state: String,
+}
+
+struct AnimationState {
+    animation: Animation,
+    // entered once the animation ended, only for non-repeating animations
+    next: Option<String>,
+}
+
+// Plays one of its named states at a time
 #[derive(Component)]
 pub struct Animatable {
-    current_animation: Animation,
-    next_animation: Option<Animation>,
+    states: HashMap<String, AnimationState>,
+    state: String,
+    // the current state's animation
+    animation: Animation,
     current_frame: usize,
+    // seconds the current frame has been shown
+    elapsed: f32,
+    // the events of the current frame were sent
+    frame_shown: bool,
     active: bool,
 }
 
 impl Animatable {
-    pub fn from_anim(animation: Animation) -> Self {
+    pub fn new(state: &str, animation: Animation) -> Self {
         Animatable {
-            current_animation: animation,
+            states: HashMap::new(),
+            state: String::new(),
+            animation: animation.clone(),
             current_frame: 0,
+            elapsed: 0.,
+            frame_shown: false,
             active: true,
-            next_animation: None,
         }
+        .with_state(state, animation)
+        .entering(state)
+    }
+
+    // Every clip of the sheet becomes a state, None if the sheet has no clip to start with
+    pub fn from_sheet(sheet: &AnimationSheet, initial: &str) -> Option<Self> {
+        let mut animatable = Animatable::new(
+            initial,
+            Animation::from_clip(sheet, sheet.clips.get(initial)?),
+        );
+        for (name, clip) in &sheet.clips {
+            animatable = animatable.with_state(name, Animation::from_clip(sheet, clip));
+        }
+        for (name, clip) in &sheet.clips {
+            if let Some(next) = &clip.next {
+                animatable = animatable.with_transition(name, next);
+            }
+        }
+        Some(animatable)
+    }
+
+    pub fn with_state(mut self, name: &str, animation: Animation) -> Self {
+        self.states.insert(
+            name.to_string(),
+            AnimationState {
+                animation,
+                next: None,
+            },
+        );
+        self
+    }
+
+    // Once the animation of one state ended, the other one plays
+    pub fn with_transition(mut self, from: &str, to: &str) -> Self {
+        match self.states.get_mut(from) {
+            Some(state) => state.next = Some(to.to_string()),
+            None => warn!("no animation state '{from}' to transition from"),
+        }
+        self
+    }
+
+    fn entering(mut self, state: &str) -> Self {
+        self.enter(state);
+        self
+    }
+
+    // false if there is no such state
+    fn enter(&mut self, state: &str) -> bool {
+        let Some(animation_state) = self.states.get(state) else {
+            warn!("unknown animation state '{state}'");
+            return false;
+        };
+        self.animation = animation_state.animation.clone();
+        self.state = state.to_string();
+        self.current_frame = 0;
+        self.elapsed = 0.;
+        self.frame_shown = false;
+        self.active = true;
+        true
     }
 
-    pub fn stop(&mut self, instant: bool) {
-        if instant {
-            self.active = false;
-        } else {
-            self.current_animation.repeating = false;
+    // Switches right away. A repeating state that is already playing keeps going.
+    // false if there is no such state
+    pub fn play(&mut self, state: &str) -> bool {
+        if self.state == state && self.animation.repeating && self.active {
+            return true;
         }
+        self.enter(state)
     }
 
-    pub fn play(&mut self, anim: Animation, force: bool) {
-        if force {
-            self.current_animation = anim;
-            self.current_frame = 0;
-            self.active = true;
-        } else {
-            self.next_animation = Some(anim);
-            self.current_animation.repeating = false;
+    fn frame(&self) -> &Frame {
+        &self.animation.frames[self.current_frame]
+    }
+
+    // Moves on to the next frame, returns the state whose animation just finished
+    fn advance(&mut self) -> Option<String> {
+        self.frame_shown = false;
+        if self.current_frame + 1 < self.animation.frames.len() {
+            self.current_frame += 1;
+            return None;
+        }
+
+        let finished = (!self.animation.repeating).then(|| self.state.clone());
+        let next = self
+            .states
+            .get(&self.state)
+            .and_then(|state| state.next.clone());
+        match next {
+            Some(next) if self.enter(&next) => (),
+            _ if self.animation.repeating => self.current_frame = 0,
+            // stays on the last frame
+            _ => {
+                self.frame_shown = true;
+                self.active = false;
+            }
         }
+        finished
     }
 }
 
@@ -88,36 +192,110 @@ pub struct AnimatorPlugin;
 
 impl Plugin for AnimatorPlugin {
     fn build(&self, app: &mut bevy::prelude::App) {
+        app.add_event::<AnimationEvent>();
+        app.add_event::<AnimationFinished>();
         app.add_system(update_animations.in_set(GameSystemSets::Render));
         app.add_system(apply_system_buffers.before(update_animations));
     }
 }
 
-pub fn update_animations(mut animatables: Query<(&mut Animatable, &mut Sprite)>, time: Res<Time>) {
-    for (mut animatable, mut sprite) in animatables.iter_mut() {
-        sprite.rect = Some(animatable.current_animation.frames[animatable.current_frame]);
+fn send_frame_event(
+    animatable: &mut Animatable,
+    entity: Entity,
+    events: &mut EventWriter<AnimationEvent>,
+) {
+    if animatable.frame_shown {
+        return;
+    }
+    animatable.frame_shown = true;
+    if let Some(name) = animatable.frame().event.clone() {
+        events.send(AnimationEvent { entity, name });
+    }
+}
 
+pub fn update_animations(
+    mut animatables: Query<(&mut Animatable, &mut Sprite, Entity)>,
+    time: Res<Time>,
+    mut events: EventWriter<AnimationEvent>,
+    mut finished: EventWriter<AnimationFinished>,
+) {
+    for (mut animatable, mut sprite, entity) in animatables.iter_mut() {
         if !animatable.active {
-            return;
+            continue;
         }
+        send_frame_event(&mut animatable, entity, &mut events);
 
-        if animatable
-            .current_animation
-            .timer
-            .tick(time.delta())
-            .just_finished()
-        {
-            if animatable.current_frame >= animatable.current_animation.frames.len() - 1 {
-                if animatable.current_animation.repeating {
-                    animatable.current_frame = 0;
-                }
-                if let Some(next) = std::mem::replace(&mut animatable.next_animation, None) {
-                    animatable.current_animation = next;
-                    animatable.current_frame = 0;
-                }
-            } else {
-                animatable.current_frame = animatable.current_frame + 1;
+        // a long frame may pass several short ones
+        animatable.elapsed += time.delta_seconds();
+        while animatable.active && animatable.elapsed >= animatable.frame().seconds {
+            animatable.elapsed -= animatable.frame().seconds;
+            if let Some(state) = animatable.advance() {
+                finished.send(AnimationFinished { entity, state });
             }
+            send_frame_event(&mut animatable, entity, &mut events);
+        }
+
+        sprite.rect = Some(animatable.frame().rect);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use bevy::{
+        ecs::event::Events,
+        prelude::{Schedule, Vec2, World},
+    };
+
+    use super::*;
+
+    fn frame(event: Option<&str>) -> Frame {
+        Frame {
+            rect: Rect::from_corners(Vec2::ZERO, Vec2::ONE),
+            seconds: 0.1,
+            event: event.map(str::to_string),
         }
     }
+
+    #[test]
+    fn frame_events_are_sent_once_the_frame_shows() {
+        let attack = Animation {
+            repeating: false,
+            frames: vec![frame(None), frame(Some("hit")), frame(None)],
+        };
+        let mut world = World::new();
+        world.init_resource::<Events<AnimationEvent>>();
+        world.init_resource::<Events<AnimationFinished>>();
+        world.init_resource::<Time>();
+        let entity = world
+            .spawn((Animatable::new("attack", attack), Sprite::default()))
+            .id();
+        let mut schedule = Schedule::new();
+        schedule.add_system(update_animations);
+
+        let start = Instant::now();
+        let mut events_after = |seconds: f32| {
+            world
+                .resource_mut::<Time>()
+                .update_with_instant(start + Duration::from_secs_f32(seconds));
+            schedule.run(&mut world);
+            world
+                .resource_mut::<Events<AnimationEvent>>()
+                .drain()
+                .map(|event| (event.entity, event.name))
+                .collect::<Vec<_>>()
+        };
+
+        assert!(events_after(0.).is_empty());
+        assert!(events_after(0.05).is_empty());
+        assert_eq!(events_after(0.15), vec![(entity, "hit".to_string())]);
+        assert!(events_after(0.35).is_empty());
+        let finished: Vec<String> = world
+            .resource_mut::<Events<AnimationFinished>>()
+            .drain()
+            .map(|finished| finished.state)
+            .collect();
+        assert_eq!(finished, vec!["attack".to_string()]);
+    }
 }
@@ -0,0 +1,270 @@
+use std::collections::HashMap;
+
+use bevy::prelude::{Entity, Query, Res, Resource};
+
+use crate::util::rng::Rng;
+
+use super::{
+    isometric::IsometricDirection,
+    map::MapLayout,
+    status_effects::{EffectKind, StatusEffects},
+    unit::{Unit, UnitRegistry},
+    vision::Vision,
+};
+
+// How far above its tile a unit looks from / gets looked at
+const EYE_HEIGHT: f32 = 0.5;
+// Damage for being knocked into a wall or another unit. Both units take it
+pub const COLLISION_DAMAGE: u32 = 1;
+// Damage per layer a unit gets knocked down
+pub const FALL_DAMAGE: u32 = 2;
+// Extra damage per layer the attacker stands above its target, less damage from below
+const HEIGHT_DAMAGE: i32 = 1;
+// Ranged attacks lose accuracy per tile beyond the first and per layer they shoot upwards
+const ACCURACY_LOSS: u32 = 10;
+const MIN_HIT_CHANCE: u32 = 50;
+
+// Rolls whether attacks hit
+#[derive(Resource)]
+pub struct CombatRng(pub Rng);
+
+pub struct Strike {
+    // before shields
+    pub damage: u32,
+    // by the target's shield
+    pub absorbed: u32,
+    // percent
+    pub hit_chance: u32,
+    // part of the damage that comes from standing higher or lower than the target
+    pub height_bonus: i32,
+}
+
+impl Strike {
+    pub fn dealt(&self) -> u32 {
+        self.damage - self.absorbed
+    }
+
+    pub fn expected_damage(&self) -> f32 {
+        self.dealt() as f32 * self.hit_chance as f32 / 100.
+    }
+}
+
+// What happens when one unit attacks another, used both to resolve the attack and to preview it
+pub struct CombatForecast {
+    pub attack: Strike,
+    // the target strikes back if it survives and the attacker is in its range
+    pub counter: Option<Strike>,
+    // a hit kills the target, so there's no counterattack unless the attack misses
+    pub lethal: bool,
+}
+
+fn strike(
+    attacker: &Unit,
+    from: &(i32, i32),
+    defender: &Unit,
+    defender_effects: &StatusEffects,
+    to: &(i32, i32),
+    map_layout: &Res<MapLayout>,
+) -> Strike {
+    let height = |tile: &(i32, i32)| {
+        map_layout
+            .tiles
+            .get(tile)
+            .map_or(0, |height| *height as i32)
+    };
+    let layers_above = height(from) - height(to);
+    let height_bonus = layers_above * HEIGHT_DAMAGE;
+    let damage = (attacker.attack as i32 + height_bonus - defender.defense() as i32).max(1) as u32;
+
+    let distance = (to.0 - from.0).unsigned_abs() + (to.1 - from.1).unsigned_abs();
+    let hit_chance = match distance {
+        0 | 1 => 100,
+        _ => {
+            let penalty = distance - 1 + (-layers_above).max(0) as u32;
+            100u32
+                .saturating_sub(penalty * ACCURACY_LOSS)
+                .max(MIN_HIT_CHANCE)
+        }
+    };
+
+    Strike {
+        damage,
+        absorbed: defender_effects.shield().min(damage),
+        hit_chance,
+        height_bonus,
+    }
+}
+
+pub fn forecast_attack(
+    (attacker, attacker_effects): (&Unit, &StatusEffects),
+    (target, target_effects): (&Unit, &StatusEffects),
+    map_layout: &Res<MapLayout>,
+) -> CombatForecast {
+    let (from, to) = (attacker.tile(), target.tile());
+    let attack = strike(attacker, &from, target, target_effects, &to, map_layout);
+    let lethal = attack.dealt() >= target.health;
+
+    let distance = (to.0 - from.0).unsigned_abs() + (to.1 - from.1).unsigned_abs();
+    let can_counter = !target_effects.has(EffectKind::Stun)
+        && distance <= attack_range(target, &to, &from, map_layout)
+        && has_line_of_sight(&to, &from, map_layout);
+    let counter =
+        can_counter.then(|| strike(target, &to, attacker, attacker_effects, &from, map_layout));
+
+    CombatForecast {
+        attack,
+        counter,
+        lethal,
+    }
+}
+
+// Ranged units shooting from higher ground reach one tile further per layer
+pub fn attack_range(
+    unit: &Unit,
+    from: &(i32, i32),
+    to: &(i32, i32),
+    map_layout: &Res<MapLayout>,
+) -> u32 {
+    if unit.attack_range <= 1 {
+        return unit.attack_range;
+    }
+
+    let height_difference = match (map_layout.tiles.get(from), map_layout.tiles.get(to)) {
+        (Some(from), Some(to)) => from.saturating_sub(*to),
+        _ => 0,
+    };
+    unit.attack_range + height_difference
+}
+
+// Walks along the line between both tile centers. Any tile higher than the line at that point
+// blocks the view. Missing tiles (gaps) never block
+pub fn has_line_of_sight(from: &(i32, i32), to: &(i32, i32), map_layout: &Res<MapLayout>) -> bool {
+    let tile_height = |tile: &(i32, i32)| map_layout.tiles.get(tile).map(|height| *height as f32);
+    let (Some(from_height), Some(to_height)) = (tile_height(from), tile_height(to)) else {
+        return false;
+    };
+    let (from_height, to_height) = (from_height + EYE_HEIGHT, to_height + EYE_HEIGHT);
+
+    let (dx, dy) = ((to.0 - from.0) as f32, (to.1 - from.1) as f32);
+    // sample at least twice per tile so no tile along the line gets skipped
+    let samples = 2 * ((to.0 - from.0).abs() + (to.1 - from.1).abs());
+    for i in 1..samples {
+        let progress = i as f32 / samples as f32;
+        let tile = (
+            (from.0 as f32 + progress * dx).round() as i32,
+            (from.1 as f32 + progress * dy).round() as i32,
+        );
+        if tile == *from || tile == *to {
+            continue;
+        }
+
+        let line_height = (1. - progress) * from_height + progress * to_height;
+        if let Some(height) = tile_height(&tile) {
+            if height > line_height {
+                return false;
+            }
+        }
+    }
+
+    true
+}
+
+// tile => is the target in line of sight
+pub type AttackTargets = HashMap<(i32, i32), bool>;
+
+pub fn find_attack_targets(
+    location: (i32, i32),
+    unit: &Unit,
+    map_layout: &Res<MapLayout>,
+    unit_registry: &Res<UnitRegistry>,
+    units: &Query<&Unit>,
+    vision: &Vision,
+) -> AttackTargets {
+    let mut targets = HashMap::new();
+    for (tile, entity) in unit_registry.units.iter() {
+        let Ok(other) = units.get(*entity) else {
+            continue;
+        };
+        if other.owner == unit.owner || !vision.can_see_unit(unit.owner, other) {
+            continue;
+        }
+
+        let distance = (tile.0 - location.0).unsigned_abs() + (tile.1 - location.1).unsigned_abs();
+        if distance > attack_range(unit, &location, tile, map_layout) {
+            continue;
+        }
+
+        targets.insert(*tile, has_line_of_sight(&location, tile, map_layout));
+    }
+    targets
+}
+
+pub enum KnockbackStop {
+    // moved the full distance
+    Distance,
+    // ran into a higher tile
+    Wall,
+    Unit(Entity),
+    // the next tile is a gap or off the map. Ground units fall to their death, flying units stop
+    Gap((i32, i32)),
+}
+
+pub struct Knockback {
+    pub destination: (i32, i32),
+    pub stop: KnockbackStop,
+    // layers the unit dropped down on its way, flying units never fall
+    pub fallen_layers: u32,
+}
+
+impl Knockback {
+    // damage dealt to the knocked back unit, if it survives the knockback at all
+    pub fn damage(&self) -> u32 {
+        let collision = match self.stop {
+            KnockbackStop::Wall | KnockbackStop::Unit(_) => COLLISION_DAMAGE,
+            _ => 0,
+        };
+        collision + self.fallen_layers * FALL_DAMAGE
+    }
+}
+
+// Moves the unit tile by tile until something stops it. Units can be knocked down layers, but not up
+pub fn knockback(
+    from: (i32, i32),
+    direction: IsometricDirection,
+    distance: u32,
+    unit: &Unit,
+    map_layout: &MapLayout,
+    unit_registry: &UnitRegistry,
+) -> Knockback {
+    let (dx, dy) = direction.to_vec();
+    let mut knockback = Knockback {
+        destination: from,
+        stop: KnockbackStop::Distance,
+        fallen_layers: 0,
+    };
+
+    for _ in 0..distance {
+        let current = knockback.destination;
+        let next = (current.0 + dx, current.1 + dy);
+        let (Some(current_height), Some(next_height)) =
+            (map_layout.tiles.get(&current), map_layout.tiles.get(&next))
+        else {
+            knockback.stop = KnockbackStop::Gap(next);
+            break;
+        };
+        if next_height > current_height || map_layout.blocked.contains(&next) {
+            knockback.stop = KnockbackStop::Wall;
+            break;
+        }
+        if let Some(other) = unit_registry.units.get(&next) {
+            knockback.stop = KnockbackStop::Unit(*other);
+            break;
+        }
+
+        if !unit.is_air {
+            knockback.fallen_layers += current_height - next_height;
+        }
+        knockback.destination = next;
+    }
+    knockback
+}
@@ -0,0 +1,309 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+
+use crate::{game_config::GameAssets, AppState};
+
+use super::{
+    animation::{update_animations, Animatable, AnimationEvent, AnimationFinished},
+    isometric::IsometricDirection,
+    unit::Unit,
+    GameSystemSets,
+};
+
+const DAMAGE_COLOR: Color = Color::rgb(1.0, 0.3, 0.2);
+const MISS_COLOR: Color = Color::rgb(0.9, 0.9, 0.9);
+const FLASH_COLOR: Color = Color::rgb(1.0, 0.2, 0.2);
+const FLASH_SECONDS: f32 = 0.25;
+const TEXT_SECONDS: f32 = 1.;
+// pixels per second
+const TEXT_RISE_SPEED: f32 = 60.;
+// above the unit's head and in front of the map
+const TEXT_OFFSET: Vec3 = Vec3::new(0., 90., 500.);
+const CORPSE_FADE_SECONDS: f32 = 1.;
+// frame event of the attack animations
+const HIT_EVENT: &str = "hit";
+
+pub struct CombatFeedbackPlugin;
+
+impl Plugin for CombatFeedbackPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<CombatFeedback>();
+        app.init_resource::<PendingHits>();
+        app.add_systems((
+            clear_pending_hits.in_schedule(OnExit(AppState::Game)),
+            show_feedback.in_set(GameSystemSets::Render),
+            land_hits
+                .in_set(GameSystemSets::Render)
+                .after(show_feedback)
+                .after(update_animations),
+            update_floating_texts.in_set(GameSystemSets::Render),
+            update_hit_flashes
+                .in_set(GameSystemSets::Render)
+                .after(land_hits),
+            update_corpses
+                .in_set(GameSystemSets::Render)
+                .after(land_hits),
+        ));
+    }
+}
+
+// Sent by the rules, only changes what the units look like
+pub enum CombatFeedback {
+    // attacker, tile of the target. The damage or miss sent right after it shows once the blow lands
+    Attack(Entity, (i32, i32)),
+    Damage(Entity, u32),
+    Miss(Entity),
+}
+
+// target, damage or None for a miss
+type Hit = (Entity, Option<u32>);
+
+// Hits waiting for the attack animation of the attacker to reach its hit frame
+#[derive(Resource, Default)]
+struct PendingHits(HashMap<Entity, Vec<Hit>>);
+
+impl PendingHits {
+    fn targets(&self, entity: Entity) -> bool {
+        self.0
+            .values()
+            .any(|hits| hits.iter().any(|(target, _)| *target == entity))
+    }
+}
+
+#[derive(Component)]
+struct FloatingText(Timer);
+
+#[derive(Component)]
+struct HitFlash {
+    // the sprite's color before the flash
+    color: Color,
+    timer: Timer,
+}
+
+// A dead unit, despawned once its death animation played and it faded out
+#[derive(Component)]
+pub struct Corpse {
+    death_state: String,
+    started: bool,
+    fade: Option<Timer>,
+}
+
+impl Corpse {
+    pub fn new(death_state: String) -> Self {
+        Corpse {
+            death_state,
+            started: false,
+            fade: None,
+        }
+    }
+}
+
+fn spawn_floating_text(
+    commands: &mut Commands,
+    game_assets: &GameAssets,
+    text: String,
+    color: Color,
+    position: Vec3,
+) {
+    commands.spawn((
+        Text2dBundle {
+            text: Text::from_section(
+                text,
+                TextStyle {
+                    font: game_assets.font.clone(),
+                    font_size: 40.,
+                    color,
+                },
+            ),
+            transform: Transform::from_translation(position + TEXT_OFFSET),
+            ..default()
+        },
+        FloatingText(Timer::from_seconds(TEXT_SECONDS, TimerMode::Once)),
+    ));
+}
+
+type HitSprites<'w, 's> = Query<
+    'w,
+    's,
+    (
+        &'static Sprite,
+        &'static GlobalTransform,
+        &'static Visibility,
+        Option<&'static mut HitFlash>,
+    ),
+>;
+
+fn clear_pending_hits(mut pending_hits: ResMut<PendingHits>) {
+    pending_hits.0.clear();
+}
+
+fn show_hit(
+    (target, damage): Hit,
+    commands: &mut Commands,
+    game_assets: &GameAssets,
+    sprites: &mut HitSprites,
+) {
+    let Ok((sprite, transform, visibility, flash)) = sprites.get_mut(target) else {
+        return;
+    };
+    // nobody saw it happen
+    if *visibility == Visibility::Hidden {
+        return;
+    }
+    let Some(damage) = damage else {
+        spawn_floating_text(
+            commands,
+            game_assets,
+            "Miss".to_string(),
+            MISS_COLOR,
+            transform.translation(),
+        );
+        return;
+    };
+    spawn_floating_text(
+        commands,
+        game_assets,
+        format!("-{damage}"),
+        DAMAGE_COLOR,
+        transform.translation(),
+    );
+    let timer = Timer::from_seconds(FLASH_SECONDS, TimerMode::Once);
+    match flash {
+        Some(mut flash) => flash.timer = timer,
+        None => {
+            commands.entity(target).insert(HitFlash {
+                color: sprite.color,
+                timer,
+            });
+        }
+    }
+}
+
+fn show_feedback(
+    mut commands: Commands,
+    mut event_reader: EventReader<CombatFeedback>,
+    game_assets: Res<GameAssets>,
+    mut units: Query<(&mut Unit, &mut Animatable)>,
+    mut sprites: HitSprites,
+    mut pending_hits: ResMut<PendingHits>,
+) {
+    // the attacker whose blow the next damage or miss belongs to
+    let mut attacker = None;
+    for event in event_reader.iter() {
+        let hit = match event {
+            CombatFeedback::Attack(entity, target) => {
+                attacker = None;
+                let Ok((mut attacker_unit, mut animatable)) = units.get_mut(*entity) else {
+                    continue;
+                };
+                if let Some(facing) = IsometricDirection::towards(&attacker_unit.tile(), target) {
+                    attacker_unit.facing = facing;
+                }
+                // returns to idle by itself
+                if animatable.play(&attacker_unit.attack_state()) {
+                    attacker = Some(*entity);
+                }
+                continue;
+            }
+            CombatFeedback::Damage(entity, damage) => (*entity, Some(*damage)),
+            CombatFeedback::Miss(entity) => (*entity, None),
+        };
+        match attacker.take() {
+            Some(attacker) => pending_hits.0.entry(attacker).or_default().push(hit),
+            None => show_hit(hit, &mut commands, &game_assets, &mut sprites),
+        }
+    }
+}
+
+// Shows the hits once the attacker's blow lands, or once its animation ended without one
+fn land_hits(
+    mut commands: Commands,
+    mut frame_events: EventReader<AnimationEvent>,
+    mut finished: EventReader<AnimationFinished>,
+    game_assets: Res<GameAssets>,
+    mut sprites: HitSprites,
+    mut pending_hits: ResMut<PendingHits>,
+) {
+    let landed = frame_events
+        .iter()
+        .filter(|event| event.name == HIT_EVENT)
+        .map(|event| event.entity)
+        .chain(finished.iter().map(|finished| finished.entity));
+    for attacker in landed.collect::<Vec<_>>() {
+        for hit in pending_hits.0.remove(&attacker).unwrap_or_default() {
+            show_hit(hit, &mut commands, &game_assets, &mut sprites);
+        }
+    }
+}
+
+fn update_floating_texts(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut texts: Query<(Entity, &mut FloatingText, &mut Transform, &mut Text)>,
+) {
+    for (entity, mut floating_text, mut transform, mut text) in texts.iter_mut() {
+        if floating_text.0.tick(time.delta()).finished() {
+            commands.entity(entity).despawn_recursive();
+            continue;
+        }
+        transform.translation.y += TEXT_RISE_SPEED * time.delta_seconds();
+        text.sections[0]
+            .style
+            .color
+            .set_a(floating_text.0.percent_left());
+    }
+}
+
+fn update_hit_flashes(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut flashes: Query<(Entity, &mut HitFlash, &mut Sprite)>,
+) {
+    for (entity, mut flash, mut sprite) in flashes.iter_mut() {
+        if flash.timer.tick(time.delta()).finished() {
+            sprite.color = flash.color;
+            commands.entity(entity).remove::<HitFlash>();
+            continue;
+        }
+        // fades from the flash color back to the sprite's own color
+        let left = flash.timer.percent_left();
+        let (from, to) = (FLASH_COLOR.as_rgba_f32(), flash.color.as_rgba_f32());
+        let mix = |channel: usize| from[channel] * left + to[channel] * (1. - left);
+        sprite.color = Color::rgba(mix(0), mix(1), mix(2), to[3]);
+    }
+}
+
+fn update_corpses(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut finished: EventReader<AnimationFinished>,
+    mut corpses: Query<(Entity, &mut Corpse, &mut Animatable, &mut Sprite)>,
+    pending_hits: Res<PendingHits>,
+) {
+    for AnimationFinished { entity, state } in finished.iter() {
+        // ignores animations that ended before the death animation started
+        if let Ok((_, mut corpse, _, _)) = corpses.get_mut(*entity) {
+            if *state != corpse.death_state {
+                continue;
+            }
+            corpse.fade = Some(Timer::from_seconds(CORPSE_FADE_SECONDS, TimerMode::Once));
+        }
+    }
+
+    for (entity, mut corpse, mut animatable, mut sprite) in corpses.iter_mut() {
+        // dies once the blow that killed it landed
+        if !corpse.started && !pending_hits.targets(entity) {
+            corpse.started = true;
+            animatable.play(&corpse.death_state);
+        }
+        let Some(fade) = &mut corpse.fade else {
+            continue;
+        };
+        if fade.tick(time.delta()).finished() {
+            commands.entity(entity).despawn_recursive();
+            continue;
+        }
+        sprite.color.set_a(fade.percent_left());
+    }
+}
@@ -0,0 +1,549 @@
+use std::{fs, path::Path};
+
+use bevy::prelude::*;
+
+use crate::{
+    assets::types::{TiledGid, TiledMap, TiledObject, TiledPropertyValue, TiledSet},
+    game_config::{GameAssets, GameConfig, MapChoice},
+    AppState,
+};
+
+use super::{
+    game_state::GameState,
+    map::{editor_position, spawn_map, Map, MapLayout, Tile},
+    map_generator::map_path,
+    map_objects::{object_position, object_tile, participant_color, spawn_objects, MapObject},
+    map_validation::validate_map,
+    picking::PickState,
+};
+
+const TOOLS: [(EditorTool, KeyCode); 5] = [
+    (EditorTool::Paint, KeyCode::Key1),
+    (EditorTool::Raise, KeyCode::Key2),
+    (EditorTool::Lower, KeyCode::Key3),
+    (EditorTool::Zone, KeyCode::Key4),
+    (EditorTool::Object, KeyCode::Key5),
+];
+
+// spawn points are placed with the zone tool
+const OBJECT_CLASSES: [&str; 5] = ["capture_point", "chest", "door", "trap", "trigger"];
+
+const MAX_RADIUS: u32 = 5;
+
+pub struct EditorPlugin;
+
+impl Plugin for EditorPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems((
+            setup_editor.in_schedule(OnEnter(AppState::Editor)),
+            destroy_editor.in_schedule(OnExit(AppState::Editor)),
+        ));
+        app.add_systems(
+            (
+                select_tool,
+                edit_map,
+                save_edited_map,
+                leave_editor,
+                rebuild_edited_map,
+                update_editor_tint,
+                update_editor_panel,
+            )
+                .chain()
+                .in_set(OnUpdate(AppState::Editor)),
+        );
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum EditorTool {
+    Paint,
+    // stacks a copy of the top tile on the column
+    Raise,
+    Lower,
+    // deployment zone of the selected participant
+    Zone,
+    // places or removes an object of the selected class
+    Object,
+}
+
+#[derive(Resource)]
+struct Editor {
+    // working copy, the map asset only changes when saving
+    tilemap: TiledMap,
+    tool: EditorTool,
+    // gid and name of every tile in the map's tilesets
+    palette: Vec<(u32, String)>,
+    brush: usize,
+    object_class: usize,
+    participant: usize,
+    radius: u32,
+    status: String,
+    unsaved: bool,
+    rebuild: bool,
+    // Esc was pressed once with unsaved changes
+    leaving: bool,
+}
+
+impl Editor {
+    fn gid_at(&self, tile: (i32, i32), layer: u32) -> Option<TiledGid> {
+        self.tilemap
+            .tile_layers()
+            .nth(layer as usize)?
+            .gid_at(&editor_position(tile.0, tile.1, layer))
+    }
+
+    fn top_layer(&self, tile: (i32, i32)) -> Option<u32> {
+        (0..self.tilemap.tile_layers().count() as u32)
+            .rev()
+            .find(|layer| self.gid_at(tile, *layer).is_some())
+    }
+
+    // Adds a tile layer when building on top of the highest one
+    fn set_tile(
+        &mut self,
+        tile: (i32, i32),
+        layer: u32,
+        gid: Option<TiledGid>,
+    ) -> Result<(), String> {
+        let position = editor_position(tile.0, tile.1, layer);
+        let inside = (0..self.tilemap.width as i32).contains(&position.0)
+            && (0..self.tilemap.height as i32).contains(&position.1);
+        if !self.tilemap.infinite && !inside {
+            return Err(format!("{tile:?} at height {layer} is outside of the map"));
+        }
+
+        if layer as usize >= self.tilemap.tile_layers().count() {
+            self.tilemap.add_tile_layer();
+        }
+        if let Some(layer) = self.tilemap.tile_layers_mut().nth(layer as usize) {
+            layer.set_gid(position, gid);
+        }
+        Ok(())
+    }
+
+    fn add_object(&mut self, class: &str, tile: (i32, i32), properties: &[(&str, i64)]) {
+        let layer = self.top_layer(tile).unwrap_or(0);
+        let (x, y) = object_position(tile, layer, self.tilemap.tileheight);
+        let mut object = TiledObject::point(class, x, y);
+        // the object stays on this tile when the height changes later
+        object
+            .properties
+            .set("layer", TiledPropertyValue::Int(layer as i64));
+        for (name, value) in properties {
+            object.properties.set(name, TiledPropertyValue::Int(*value));
+        }
+        self.tilemap.add_object(object);
+    }
+
+    fn paint(&mut self, tile: (i32, i32), layer: u32) -> Result<String, String> {
+        let Some((gid, name)) = self.palette.get(self.brush).cloned() else {
+            return Err("the map's tilesets have no tiles to paint with".to_string());
+        };
+        self.set_tile(tile, layer, Some(TiledGid(gid)))?;
+        Ok(format!("Painted {name} on {tile:?}"))
+    }
+
+    fn raise(&mut self, tile: (i32, i32)) -> Result<String, String> {
+        let layer = self.top_layer(tile).unwrap_or(0);
+        let gid = self.gid_at(tile, layer);
+        self.set_tile(tile, layer + 1, gid)?;
+        Ok(format!("Raised {tile:?} to height {}", layer + 1))
+    }
+
+    fn lower(&mut self, tile: (i32, i32)) -> Result<String, String> {
+        let layer = self.top_layer(tile).unwrap_or(0);
+        if layer == 0 {
+            return Err(format!("{tile:?} is already on the ground"));
+        }
+        // a column with a gap below keeps its top tile
+        if self.gid_at(tile, layer - 1).is_none() {
+            let gid = self.gid_at(tile, layer);
+            self.set_tile(tile, layer - 1, gid)?;
+        }
+        self.set_tile(tile, layer, None)?;
+        Ok(format!("Lowered {tile:?} to height {}", layer - 1))
+    }
+
+    fn place_zone(&mut self, tile: (i32, i32)) -> Result<String, String> {
+        let participant = self.participant as i64;
+        self.tilemap.remove_objects(|object| {
+            object.class() == "spawn_point" && object.properties.int("player") == Some(participant)
+        });
+        self.add_object(
+            "spawn_point",
+            tile,
+            &[("player", participant), ("radius", self.radius as i64)],
+        );
+        Ok(format!(
+            "Moved the zone of player {participant} to {tile:?}"
+        ))
+    }
+
+    fn toggle_object(&mut self, tile: (i32, i32)) -> Result<String, String> {
+        let class = OBJECT_CLASSES[self.object_class];
+        let existing: Vec<u32> = self
+            .tilemap
+            .objects()
+            .filter(|object| object.class() == class && object_tile(object, &self.tilemap) == tile)
+            .map(|object| object.id)
+            .collect();
+        if existing.is_empty() {
+            self.add_object(class, tile, &[]);
+            Ok(format!("Placed a {class} on {tile:?}"))
+        } else {
+            self.tilemap
+                .remove_objects(|object| existing.contains(&object.id));
+            Ok(format!("Removed the {class} from {tile:?}"))
+        }
+    }
+
+    fn set_radius(&mut self, radius: u32) {
+        self.radius = radius.min(MAX_RADIUS);
+        let participant = self.participant as i64;
+        let radius = self.radius as i64;
+        let mut changed = false;
+        for object in self.tilemap.objects_mut() {
+            if object.class() == "spawn_point"
+                && object.properties.int("player") == Some(participant)
+            {
+                object
+                    .properties
+                    .set("radius", TiledPropertyValue::Int(radius));
+                changed = true;
+            }
+        }
+        if changed {
+            self.edited(format!("Zone radius of player {participant} is {radius}"));
+        }
+    }
+
+    fn edited(&mut self, status: String) {
+        self.status = status;
+        self.unsaved = true;
+        self.rebuild = true;
+        self.leaving = false;
+    }
+}
+
+#[derive(Component)]
+struct EditorPanel;
+
+// Every tile of the map's tilesets that has a texture
+fn palette(
+    tilemap: &TiledMap,
+    game_assets: &GameAssets,
+    tilesets: &Assets<TiledSet>,
+) -> Vec<(u32, String)> {
+    let mut palette = vec![];
+    for (tileset_ref, handle) in tilemap.tilesets.iter().zip(&game_assets.tilesets) {
+        let Some(tileset) = tilesets.get(handle) else {
+            continue;
+        };
+        let ids: Vec<u32> = if tileset.image_path.is_some() {
+            (0..tileset.tilecount).collect()
+        } else {
+            tileset.tiles.iter().map(|tile| tile.id).collect()
+        };
+        palette.extend(
+            ids.into_iter()
+                .map(|id| (tileset_ref.firstgid + id, format!("{} #{id}", tileset.name)))
+                .filter(|(gid, _)| game_assets.tiles.contains_key(gid)),
+        );
+    }
+    palette
+}
+
+fn setup_editor(
+    mut commands: Commands,
+    game_assets: Res<GameAssets>,
+    tilemaps: Res<Assets<TiledMap>>,
+    tilesets: Res<Assets<TiledSet>>,
+) {
+    let tilemap = tilemaps.get(&game_assets.map).unwrap().clone();
+
+    commands.spawn((
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font: game_assets.font.clone(),
+                font_size: 20.,
+                color: Color::WHITE,
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            position: UiRect {
+                left: Val::Px(10.),
+                top: Val::Px(10.),
+                ..default()
+            },
+            ..default()
+        }),
+        EditorPanel,
+    ));
+
+    commands.insert_resource(Editor {
+        palette: palette(&tilemap, &game_assets, &tilesets),
+        tilemap,
+        tool: EditorTool::Paint,
+        brush: 0,
+        object_class: 0,
+        participant: 0,
+        radius: 1,
+        status: String::new(),
+        unsaved: false,
+        rebuild: false,
+        leaving: false,
+    });
+}
+
+fn destroy_editor(mut commands: Commands, panels: Query<Entity, With<EditorPanel>>) {
+    for panel in panels.iter() {
+        commands.entity(panel).despawn_recursive();
+    }
+    commands.remove_resource::<Editor>();
+}
+
+fn select_tool(
+    keyboard: Res<Input<KeyCode>>,
+    game_state: Res<GameState>,
+    mut editor: ResMut<Editor>,
+) {
+    for (tool, key) in TOOLS {
+        if keyboard.just_pressed(key) {
+            editor.tool = tool;
+        }
+    }
+
+    let brushes = editor.palette.len().max(1);
+    if keyboard.just_pressed(KeyCode::Q) {
+        editor.brush = (editor.brush + brushes - 1) % brushes;
+    }
+    if keyboard.just_pressed(KeyCode::E) {
+        editor.brush = (editor.brush + 1) % brushes;
+    }
+    if keyboard.just_pressed(KeyCode::Tab) {
+        editor.object_class = (editor.object_class + 1) % OBJECT_CLASSES.len();
+    }
+    if keyboard.just_pressed(KeyCode::P) {
+        editor.participant = (editor.participant + 1) % game_state.participants.len().max(1);
+    }
+
+    if keyboard.any_just_pressed([KeyCode::Equals, KeyCode::NumpadAdd]) {
+        let radius = editor.radius + 1;
+        editor.set_radius(radius);
+    }
+    if keyboard.any_just_pressed([KeyCode::Minus, KeyCode::NumpadSubtract]) {
+        let radius = editor.radius.saturating_sub(1);
+        editor.set_radius(radius);
+    }
+}
+
+fn edit_map(
+    mouse: Res<Input<MouseButton>>,
+    pick_state: Res<PickState>,
+    tiles: Query<&Tile>,
+    mut editor: ResMut<Editor>,
+) {
+    let Some(tile) = pick_state
+        .selected
+        .and_then(|entity| tiles.get(entity).ok())
+    else {
+        return;
+    };
+    let (position, layer) = ((tile.x, tile.y), tile.z as u32);
+
+    // right click takes the tile under the cursor as brush
+    if mouse.just_pressed(MouseButton::Right) {
+        let gid = editor.gid_at(position, layer).map(|gid| gid.id());
+        if let Some(brush) = editor
+            .palette
+            .iter()
+            .position(|(palette_gid, _)| Some(*palette_gid) == gid)
+        {
+            editor.brush = brush;
+        }
+        return;
+    }
+    if !mouse.just_pressed(MouseButton::Left) {
+        return;
+    }
+
+    let result = match editor.tool {
+        EditorTool::Paint => editor.paint(position, layer),
+        EditorTool::Raise => editor.raise(position),
+        EditorTool::Lower => editor.lower(position),
+        EditorTool::Zone => editor.place_zone(position),
+        EditorTool::Object => editor.toggle_object(position),
+    };
+    match result {
+        Ok(status) => editor.edited(status),
+        Err(error) => editor.status = format!("Can't do that: {error}"),
+    }
+}
+
+// Ctrl+S writes the map back to its file, generated maps get a file of their own
+fn save_edited_map(
+    keyboard: Res<Input<KeyCode>>,
+    mut editor: ResMut<Editor>,
+    game_assets: Res<GameAssets>,
+    mut tilemaps: ResMut<Assets<TiledMap>>,
+    tilesets: Res<Assets<TiledSet>>,
+    mut game_config: ResMut<GameConfig>,
+    game_state: Res<GameState>,
+) {
+    let ctrl = keyboard.any_pressed([KeyCode::LControl, KeyCode::RControl]);
+    if !ctrl || !keyboard.just_pressed(KeyCode::S) {
+        return;
+    }
+
+    let path = match &game_config.map {
+        MapChoice::File(path) => Path::new(path).to_path_buf(),
+        MapChoice::Generated(seed) => map_path(*seed),
+    };
+    editor.tilemap.encode();
+    let saved = serde_json::to_string_pretty(&editor.tilemap)
+        .map_err(|error| error.to_string())
+        .and_then(|json| {
+            fs::write(Path::new("assets").join(&path), json).map_err(|error| error.to_string())
+        });
+    if let Err(error) = saved {
+        editor.status = format!("Can't save the map: {error}");
+        return;
+    }
+
+    // matches started from the menu play the edited map from now on
+    if let Some(tilemap) = tilemaps.get_mut(&game_assets.map) {
+        *tilemap = editor.tilemap.clone();
+    }
+    game_config.map = MapChoice::File(path.to_string_lossy().to_string());
+
+    let map_tilesets: Vec<(u32, &TiledSet)> = editor
+        .tilemap
+        .tilesets
+        .iter()
+        .zip(&game_assets.tilesets)
+        .filter_map(|(tileset_ref, handle)| Some((tileset_ref.firstgid, tilesets.get(handle)?)))
+        .collect();
+    let errors = validate_map(
+        &editor.tilemap,
+        &map_tilesets,
+        game_state.participants.len(),
+        game_state.units_per_participant,
+    );
+    editor.unsaved = false;
+    editor.status = match errors.first() {
+        None => format!("Saved {}", path.display()),
+        Some(error) => format!(
+            "Saved {}, but it has {} problem(s): {error}",
+            path.display(),
+            errors.len()
+        ),
+    };
+}
+
+fn leave_editor(
+    keyboard: Res<Input<KeyCode>>,
+    mut editor: ResMut<Editor>,
+    mut next_state: ResMut<NextState<AppState>>,
+) {
+    if !keyboard.just_pressed(KeyCode::Escape) {
+        return;
+    }
+    if editor.unsaved && !editor.leaving {
+        editor.leaving = true;
+        editor.status = "Unsaved changes, press Esc again to leave anyway".to_string();
+        return;
+    }
+    next_state.set(AppState::Menu);
+}
+
+fn rebuild_edited_map(
+    mut commands: Commands,
+    mut editor: ResMut<Editor>,
+    game_assets: Res<GameAssets>,
+    maps: Query<Entity, With<Map>>,
+    objects: Query<Entity, With<MapObject>>,
+    mut map_layout: ResMut<MapLayout>,
+) {
+    if !editor.rebuild {
+        return;
+    }
+    editor.rebuild = false;
+
+    for entity in maps.iter().chain(objects.iter()) {
+        commands.entity(entity).despawn_recursive();
+    }
+    spawn_map(
+        &mut commands,
+        &game_assets,
+        &editor.tilemap,
+        &mut map_layout,
+    );
+    spawn_objects(
+        &mut commands,
+        &game_assets,
+        &editor.tilemap,
+        &mut map_layout,
+    );
+}
+
+fn update_editor_tint(
+    pick_state: Res<PickState>,
+    map_layout: Res<MapLayout>,
+    mut tiles: Query<(
+        Option<&mut Sprite>,
+        Option<&mut TextureAtlasSprite>,
+        &Tile,
+        Entity,
+    )>,
+) {
+    for (sprite, atlas_sprite, tile, entity) in tiles.iter_mut() {
+        let mut color = map_layout
+            .deployment_zones
+            .iter()
+            .find(|(_, zone)| zone.contains(&(tile.x, tile.y)))
+            .map(|(participant, _)| participant_color(Some(*participant)))
+            .unwrap_or(Color::WHITE);
+
+        if pick_state.selected == Some(entity) {
+            color = Color::rgb(1.2 * color.r(), 1.2 * color.g(), 1.2 * color.b());
+        }
+
+        let color = color.with_a(tile.opacity);
+        if let Some(mut sprite) = sprite {
+            sprite.color = color;
+        }
+        if let Some(mut sprite) = atlas_sprite {
+            sprite.color = color;
+        }
+    }
+}
+
+fn update_editor_panel(editor: Res<Editor>, mut panels: Query<&mut Text, With<EditorPanel>>) {
+    if !editor.is_changed() {
+        return;
+    }
+
+    let brush = editor
+        .palette
+        .get(editor.brush)
+        .map_or("none", |(_, name)| name.as_str());
+    let unsaved = if editor.unsaved { " (unsaved)" } else { "" };
+    let text = format!(
+        "Tool: {:?} (1-5)\n\
+         Tile: {brush} (Q/E, right click picks)\n\
+         Object: {} (Tab)\n\
+         Zone: player {}, radius {} (P, +/-)\n\
+         Ctrl+S save{unsaved}, Esc back to menu\n\
+         {}",
+        editor.tool,
+        OBJECT_CLASSES[editor.object_class],
+        editor.participant,
+        editor.radius,
+        editor.status,
+    );
+    for mut panel in panels.iter_mut() {
+        panel.sections[0].value = text.clone();
+    }
+}
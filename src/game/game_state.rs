@@ -1,14 +1,34 @@
 use std::collections::HashMap;
 
-use bevy::prelude::{Entity, EventReader, EventWriter, IntoSystemConfig, Plugin, ResMut, Resource};
+use bevy::prelude::{
+    Entity, EventReader, EventWriter, IntoSystemConfig, Plugin, Query, ResMut, Resource,
+};
 
-use super::{GameEvent, GameSystemSets};
+use super::{
+    status_effects::{EffectKind, StatusEffects},
+    unit::{Unit, UnitKind},
+    GameEvent, GameSystemSets,
+};
 
 pub struct GameStatePlugin;
 
 pub enum GameStateEvent {
     SpawnedUnit(Entity),
-    MovedUnit(Entity),
+    MovedUnit,
+    Attacked,
+    UsedAbility,
+    UnitDied(Entity),
+    TurnAction(TurnAction),
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TurnAction {
+    // end the turn without doing anything
+    Skip,
+    // end the turn with bonus defense until the unit's next turn
+    Defend,
+    // act again later in the current round
+    Delay,
 }
 
 #[derive(PartialEq)]
@@ -17,6 +37,8 @@ pub enum GameStates {
     Placing(usize, u32),
     // turn, did move
     Turn(usize, bool),
+    // no unit is left to take a turn
+    Ended,
 }
 
 #[derive(PartialEq)]
@@ -30,8 +52,28 @@ pub struct GameState {
     pub state: GameStates,
     pub participants: Vec<Participant>,
     pub turn_order: Vec<Option<(usize, Entity)>>,
+    pub round: u32,
     units: HashMap<usize, Vec<Entity>>,
-    units_per_participant: u32,
+    // units that already delayed their turn this round
+    delayed: Vec<Entity>,
+    // the turn order before the first delay of this round, restored when the round ends
+    round_order: Option<Vec<Option<(usize, Entity)>>>,
+    pub units_per_participant: u32,
+}
+
+impl Default for GameState {
+    fn default() -> Self {
+        GameState {
+            participants: vec![Participant::Me, Participant::Bot],
+            state: GameStates::Placing(0, 0),
+            units_per_participant: 3,
+            turn_order: vec![],
+            round: 0,
+            units: HashMap::new(),
+            delayed: vec![],
+            round_order: None,
+        }
+    }
 }
 
 impl GameState {
@@ -51,7 +93,7 @@ impl GameState {
             turn += 1;
             player_id = 0;
             if turn >= self.units_per_participant {
-                self.end_place_phase();
+                self.end_place_phase(event_writer);
                 return;
             }
         }
@@ -59,38 +101,190 @@ impl GameState {
         self.state = GameStates::Placing(player_id, turn);
         if let Participant::Bot = self.participants[player_id] {
             // do ai action here
-            event_writer.send(GameEvent::PlaceAiUnit(player_id));
+            event_writer.send(GameEvent::PlaceAiUnit(
+                player_id,
+                UnitKind::for_placing_round(turn),
+            ));
         }
     }
 
-    fn end_place_phase(&mut self) {
+    fn end_place_phase(&mut self, event_writer: &mut EventWriter<GameEvent>) {
         let all_units: Vec<Vec<(usize, Entity)>> = (0..self.participants.len())
             .map(|participant| {
                 let units = self.units.get(&participant).cloned();
-                let units =
-                    units.map(|units| units.into_iter().map(|unit| (participant, unit)).collect());
-                units
+                units.map(|units| units.into_iter().map(|unit| (participant, unit)).collect())
             })
             .collect::<Option<_>>()
             .unwrap_or(vec![]);
         let mut all_units: Vec<(usize, Entity)> = all_units.into_iter().flatten().collect();
         all_units.sort_unstable();
-        self.turn_order = all_units.into_iter().map(|entity| Some(entity)).collect();
+        self.turn_order = all_units.into_iter().map(Some).collect();
         self.state = GameStates::Turn(0, false);
+        self.start_turn(event_writer);
+    }
+
+    // the participant playing on this machine
+    pub fn local_participant(&self) -> Option<usize> {
+        self.participants
+            .iter()
+            .position(|participant| *participant == Participant::Me)
+    }
+
+    pub fn current_unit(&self) -> Option<(usize, Entity)> {
+        let GameStates::Turn(current_turn, _) = self.state else {
+            return None;
+        };
+        self.turn_order[current_turn]
+    }
+
+    // after moving the unit may still attack before its turn ends
+    fn moved(&mut self, event_writer: &mut EventWriter<GameEvent>) {
+        let GameStates::Turn(current_turn, _) = self.state else {
+            return;
+        };
+        self.state = GameStates::Turn(current_turn, true);
+        if let Some((participant, entity)) = self.current_unit() {
+            if let Participant::Bot = self.participants[participant] {
+                event_writer.send(GameEvent::AiTurn(entity));
+            }
+        }
     }
 
-    fn next_turn(&mut self) {
+    fn remove_unit(
+        &mut self,
+        entity: Entity,
+        event_writer: &mut EventWriter<GameEvent>,
+        effects: &mut Query<&mut StatusEffects>,
+    ) {
+        let was_current = self
+            .current_unit()
+            .is_some_and(|(_, current)| current == entity);
+
+        for turn in self
+            .turn_order
+            .iter_mut()
+            .chain(self.round_order.iter_mut().flatten())
+        {
+            if matches!(turn, Some((_, unit)) if *unit == entity) {
+                *turn = None;
+            }
+        }
+        for units in self.units.values_mut() {
+            units.retain(|unit| *unit != entity);
+        }
+
+        // a unit dying on its own turn (e.g. to poison) must not block the game
+        if was_current {
+            self.next_turn(event_writer, effects);
+        }
+    }
+
+    fn next_turn(
+        &mut self,
+        event_writer: &mut EventWriter<GameEvent>,
+        effects: &mut Query<&mut StatusEffects>,
+    ) {
         let GameStates::Turn(mut current_turn, _) = self.state else {
             return;
         };
 
+        if let Some((_, entity)) = self.turn_order[current_turn] {
+            end_turn_effects(entity, event_writer, effects);
+        }
+
+        // every unit died, e.g. the last two to a counterattack
+        if self.turn_order.iter().all(|turn| turn.is_none()) {
+            self.state = GameStates::Ended;
+            return;
+        }
+
         loop {
             current_turn = (current_turn + 1) % self.turn_order.len();
-            if self.turn_order[current_turn].is_some() {
-                break;
+            if current_turn == 0 {
+                self.round += 1;
+                self.delayed.clear();
+                // delays only last for the round they were made in
+                if let Some(order) = self.round_order.take() {
+                    self.turn_order = order;
+                }
+                for mut unit_effects in effects.iter_mut() {
+                    unit_effects.end_round();
+                }
+            }
+            let Some((_, entity)) = self.turn_order[current_turn] else {
+                continue;
+            };
+
+            // stunned units lose their turn
+            if effects
+                .get(entity)
+                .is_ok_and(|unit_effects| unit_effects.has(EffectKind::Stun))
+            {
+                end_turn_effects(entity, event_writer, effects);
+                continue;
             }
+            break;
         }
         self.state = GameStates::Turn(current_turn, false);
+        self.start_turn(event_writer);
+    }
+
+    // Moves the current unit behind the last unit of this round. Every unit can delay once per round
+    fn delay(
+        &mut self,
+        event_writer: &mut EventWriter<GameEvent>,
+        effects: &mut Query<&mut StatusEffects>,
+    ) {
+        let GameStates::Turn(current_turn, false) = self.state else {
+            return;
+        };
+        let Some((_, entity)) = self.turn_order[current_turn] else {
+            return;
+        };
+        let Some(last_turn) = self.turn_order.iter().rposition(|turn| turn.is_some()) else {
+            return;
+        };
+        if current_turn == last_turn || self.delayed.contains(&entity) {
+            return;
+        }
+
+        if self.round_order.is_none() {
+            self.round_order = Some(self.turn_order.clone());
+        }
+        let turn = self.turn_order.remove(current_turn);
+        self.turn_order.insert(last_turn, turn);
+        self.delayed.push(entity);
+
+        // the unit after the delayed one has moved up into the current slot
+        if self.turn_order[current_turn].is_some() {
+            self.state = GameStates::Turn(current_turn, false);
+            self.start_turn(event_writer);
+        } else {
+            self.next_turn(event_writer, effects);
+        }
+    }
+
+    fn start_turn(&mut self, event_writer: &mut EventWriter<GameEvent>) {
+        if let Some((participant, entity)) = self.current_unit() {
+            event_writer.send(GameEvent::TurnStarted(entity));
+            if let Participant::Bot = self.participants[participant] {
+                event_writer.send(GameEvent::AiTurn(entity));
+            }
+        }
+    }
+}
+
+fn end_turn_effects(
+    entity: Entity,
+    event_writer: &mut EventWriter<GameEvent>,
+    effects: &mut Query<&mut StatusEffects>,
+) {
+    let Ok(mut unit_effects) = effects.get_mut(entity) else {
+        return;
+    };
+    let poison_damage = unit_effects.end_turn();
+    if poison_damage > 0 {
+        event_writer.send(GameEvent::Damage(entity, poison_damage));
     }
 }
 
@@ -98,30 +292,45 @@ impl Plugin for GameStatePlugin {
     fn build(&self, app: &mut bevy::prelude::App) {
         app.add_event::<GameStateEvent>();
 
-        app.insert_resource(GameState {
-            participants: vec![Participant::Me, Participant::Bot],
-            state: GameStates::Placing(0, 0),
-            units_per_participant: 3,
-            turn_order: vec![],
-            units: HashMap::new(),
-        });
+        app.insert_resource(GameState::default());
 
         app.add_systems((update_game_state.in_set(GameSystemSets::Logic),));
     }
 }
 
-fn update_game_state(
+pub fn update_game_state(
     mut game_state: ResMut<GameState>,
     mut game_events: EventReader<GameStateEvent>,
     mut event_writer: EventWriter<GameEvent>,
+    mut units: Query<&mut Unit>,
+    mut effects: Query<&mut StatusEffects>,
 ) {
     for event in game_events.iter() {
         match event {
-            GameStateEvent::SpawnedUnit(entity) => {
-                game_state.place(entity.clone(), &mut event_writer)
+            GameStateEvent::SpawnedUnit(entity) => game_state.place(*entity, &mut event_writer),
+            GameStateEvent::MovedUnit => {
+                game_state.moved(&mut event_writer);
+            }
+            GameStateEvent::Attacked | GameStateEvent::UsedAbility => {
+                game_state.next_turn(&mut event_writer, &mut effects);
+            }
+            GameStateEvent::UnitDied(entity) => {
+                game_state.remove_unit(*entity, &mut event_writer, &mut effects);
             }
-            GameStateEvent::MovedUnit(_) => {
-                game_state.next_turn();
+            GameStateEvent::TurnAction(action) => {
+                let Some((_, entity)) = game_state.current_unit() else {
+                    continue;
+                };
+                match action {
+                    TurnAction::Skip => game_state.next_turn(&mut event_writer, &mut effects),
+                    TurnAction::Defend => {
+                        if let Ok(mut unit) = units.get_mut(entity) {
+                            unit.defending = true;
+                        }
+                        game_state.next_turn(&mut event_writer, &mut effects);
+                    }
+                    TurnAction::Delay => game_state.delay(&mut event_writer, &mut effects),
+                }
             }
         }
     }
@@ -0,0 +1,436 @@
+use bevy::{ecs::system::SystemParam, prelude::*, window::PrimaryWindow};
+
+use crate::{game_config::GameAssets, AppState};
+
+use super::{
+    combat::{forecast_attack, CombatForecast, Strike},
+    game_state::{GameState, GameStates, Participant},
+    map::{MapLayout, MapState, Tile},
+    map_objects::participant_color,
+    picking::PickState,
+    status_effects::{EffectDuration, StatusEffects},
+    unit::Unit,
+    vision::Vision,
+    GameSystemSets,
+};
+
+const PANEL_COLOR: Color = Color::rgba(0.1, 0.1, 0.1, 0.8);
+const ENTRY_COLOR: Color = Color::rgb(0.2, 0.2, 0.2);
+const CURRENT_ENTRY_COLOR: Color = Color::rgb(0.45, 0.45, 0.45);
+const FORECAST_COLOR: Color = Color::rgb(1.0, 0.8, 0.5);
+const TEXT_SIZE: f32 = 20.;
+// between the mouse and the tooltip's top left corner
+const TOOLTIP_OFFSET: f32 = 16.;
+
+pub struct InfoPanelPlugin;
+
+impl Plugin for InfoPanelPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems((
+            create_info_panel.in_schedule(OnEnter(AppState::Game)),
+            destroy_info_panel.in_schedule(OnExit(AppState::Game)),
+            update_info_panel.in_set(GameSystemSets::Render),
+            update_forecast.in_set(GameSystemSets::Render),
+            update_turn_order.in_set(GameSystemSets::Render),
+            update_tooltip
+                .in_set(GameSystemSets::Render)
+                .after(update_turn_order),
+        ));
+    }
+}
+
+// Everything spawned by this plugin
+#[derive(Component)]
+struct InfoPanel;
+
+#[derive(Component)]
+struct SidePanel;
+
+#[derive(Component)]
+struct UnitInfo;
+
+#[derive(Component)]
+struct TileInfo;
+
+// outcome of attacking the hovered unit
+#[derive(Component)]
+struct ForecastInfo;
+
+#[derive(Component)]
+struct TurnOrderBar;
+
+// index into the turn order
+#[derive(Component)]
+struct TurnOrderEntry(usize);
+
+#[derive(Component)]
+struct Tooltip;
+
+fn text_style(game_assets: &GameAssets, color: Color) -> TextStyle {
+    TextStyle {
+        font: game_assets.font.clone(),
+        font_size: TEXT_SIZE,
+        color,
+    }
+}
+
+fn create_info_panel(mut commands: Commands, game_assets: Res<GameAssets>) {
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    position: UiRect {
+                        right: Val::Px(10.),
+                        top: Val::Px(60.),
+                        ..default()
+                    },
+                    size: Size::width(Val::Px(260.)),
+                    flex_direction: FlexDirection::Column,
+                    padding: UiRect::all(Val::Px(8.)),
+                    gap: Size::height(Val::Px(12.)),
+                    ..default()
+                },
+                background_color: PANEL_COLOR.into(),
+                visibility: Visibility::Hidden,
+                ..default()
+            },
+            SidePanel,
+            InfoPanel,
+        ))
+        .with_children(|panel| {
+            panel.spawn((
+                TextBundle::from_section("", text_style(&game_assets, Color::WHITE)),
+                UnitInfo,
+            ));
+            panel.spawn((
+                TextBundle::from_section("", text_style(&game_assets, Color::WHITE)),
+                TileInfo,
+            ));
+            panel.spawn((
+                TextBundle::from_section("", text_style(&game_assets, FORECAST_COLOR)),
+                ForecastInfo,
+            ));
+        });
+
+    // filled in once the turn order is known
+    commands.spawn((
+        NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    top: Val::Px(10.),
+                    ..default()
+                },
+                size: Size::width(Val::Percent(100.)),
+                justify_content: JustifyContent::Center,
+                gap: Size::width(Val::Px(4.)),
+                ..default()
+            },
+            ..default()
+        },
+        TurnOrderBar,
+        InfoPanel,
+    ));
+
+    commands.spawn((
+        TextBundle::from_section("", text_style(&game_assets, Color::WHITE))
+            .with_style(Style {
+                position_type: PositionType::Absolute,
+                padding: UiRect::all(Val::Px(6.)),
+                ..default()
+            })
+            .with_background_color(PANEL_COLOR),
+        Tooltip,
+        InfoPanel,
+    ));
+}
+
+fn destroy_info_panel(mut commands: Commands, panels: Query<Entity, With<InfoPanel>>) {
+    for panel in panels.iter() {
+        commands.entity(panel).despawn_recursive();
+    }
+}
+
+fn owner_name(game_state: &GameState, owner: usize) -> String {
+    match game_state.participants.get(owner) {
+        Some(Participant::Me) => format!("Player {} (you)", owner + 1),
+        Some(Participant::Bot) => format!("Player {} (bot)", owner + 1),
+        None => format!("Player {}", owner + 1),
+    }
+}
+
+fn describe_unit(unit: &Unit, effects: Option<&StatusEffects>, game_state: &GameState) -> String {
+    let mut lines = vec![
+        unit.kind.name().to_string(),
+        owner_name(game_state, unit.owner),
+        format!("HP {}/{}", unit.health, unit.max_health),
+        format!(
+            "Attack {}  Range {}  Defense {}",
+            unit.attack,
+            unit.attack_range,
+            unit.defense()
+        ),
+        match unit.is_air {
+            true => format!("Moves {} tiles, flying", unit.travel_distance),
+            false => format!("Moves {} tiles", unit.travel_distance),
+        },
+    ];
+    if unit.defending {
+        lines.push("Defending".to_string());
+    }
+    for effect in effects.into_iter().flat_map(|effects| effects.iter()) {
+        let (icon, _) = effect.kind.icon();
+        let duration = match effect.duration {
+            EffectDuration::Turns(1) => "1 turn".to_string(),
+            EffectDuration::Turns(turns) => format!("{turns} turns"),
+            EffectDuration::Rounds(1) => "1 round".to_string(),
+            EffectDuration::Rounds(rounds) => format!("{rounds} rounds"),
+        };
+        lines.push(format!("{icon} {} ({duration})", effect.kind.name()));
+    }
+    lines.join("\n")
+}
+
+fn describe_tile(tile: &(i32, i32), map_layout: &MapLayout, game_state: &GameState) -> String {
+    let Some(height) = map_layout.tiles.get(tile) else {
+        return String::new();
+    };
+    let mut lines = vec![
+        format!("Tile {}, {}", tile.0, tile.1),
+        format!("Height {height}"),
+    ];
+    if map_layout.blocked.contains(tile) {
+        lines.push("Blocked".to_string());
+    }
+    if let GameStates::Placing(participant, _) = game_state.state {
+        if map_layout.can_deploy(participant, tile) {
+            lines.push("Deployment zone".to_string());
+        }
+    }
+    lines.join("\n")
+}
+
+#[derive(SystemParam)]
+struct SidePanelQuery<'w, 's> {
+    panel: Query<'w, 's, &'static mut Visibility, With<SidePanel>>,
+    unit_info: Query<'w, 's, &'static mut Text, (With<UnitInfo>, Without<TileInfo>)>,
+    tile_info: Query<'w, 's, &'static mut Text, (With<TileInfo>, Without<UnitInfo>)>,
+}
+
+// The hovered unit, or the one whose action is being aimed
+fn update_info_panel(
+    pick_state: Res<PickState>,
+    map_state: Res<MapState>,
+    map_layout: Res<MapLayout>,
+    game_state: Res<GameState>,
+    tiles: Query<&Tile>,
+    units: Query<(&Unit, Option<&StatusEffects>)>,
+    mut side_panel: SidePanelQuery,
+) {
+    let unit = pick_state
+        .unit
+        .or(map_state.selected_unit())
+        .and_then(|entity| units.get(entity).ok());
+    let tile = pick_state
+        .selected
+        .and_then(|entity| tiles.get(entity).ok())
+        .map(|tile| (tile.x, tile.y));
+
+    if let Ok(mut text) = side_panel.unit_info.get_single_mut() {
+        text.sections[0].value = unit
+            .map(|(unit, effects)| describe_unit(unit, effects, &game_state))
+            .unwrap_or_default();
+    }
+    if let Ok(mut text) = side_panel.tile_info.get_single_mut() {
+        text.sections[0].value = tile
+            .map(|tile| describe_tile(&tile, &map_layout, &game_state))
+            .unwrap_or_default();
+    }
+
+    if let Ok(mut visibility) = side_panel.panel.get_single_mut() {
+        *visibility = if unit.is_some() || tile.is_some() {
+            Visibility::Inherited
+        } else {
+            Visibility::Hidden
+        };
+    }
+}
+
+fn describe_strike(strike: &Strike) -> String {
+    let mut damage = format!("{} damage", strike.dealt());
+    if strike.absorbed > 0 {
+        damage += &format!(" ({} absorbed)", strike.absorbed);
+    }
+    match strike.height_bonus {
+        0 => {}
+        bonus if bonus > 0 => damage += &format!(", +{bonus} from higher ground"),
+        bonus => damage += &format!(", {bonus} from lower ground"),
+    }
+    format!(
+        "{damage}\n{}% to hit, {:.1} expected",
+        strike.hit_chance,
+        strike.expected_damage()
+    )
+}
+
+fn describe_forecast(forecast: &CombatForecast, target: &Unit) -> String {
+    let mut lines = vec!["Attack".to_string(), describe_strike(&forecast.attack)];
+    lines.push(if forecast.lethal {
+        "Defeats the target".to_string()
+    } else {
+        format!(
+            "Target HP {} -> {}",
+            target.health,
+            target.health - forecast.attack.dealt()
+        )
+    });
+    match &forecast.counter {
+        Some(counter) => {
+            lines.push(match forecast.lethal {
+                true => "Counterattack, only on a miss".to_string(),
+                false => "Counterattack".to_string(),
+            });
+            lines.push(describe_strike(counter));
+        }
+        None => lines.push("No counterattack".to_string()),
+    }
+    lines.join("\n")
+}
+
+// Shown while aiming an attack at a unit
+fn update_forecast(
+    pick_state: Res<PickState>,
+    map_state: Res<MapState>,
+    map_layout: Res<MapLayout>,
+    tiles: Query<&Tile>,
+    units: Query<(&Unit, &StatusEffects)>,
+    mut forecast_info: Query<&mut Text, With<ForecastInfo>>,
+) {
+    let Ok(mut text) = forecast_info.get_single_mut() else {
+        return;
+    };
+    let attack = pick_state
+        .selected
+        .and_then(|entity| tiles.get(entity).ok())
+        .and_then(|tile| map_state.attacker_of(&(tile.x, tile.y)))
+        .zip(pick_state.unit)
+        .and_then(|(attacker, target)| Some((units.get(attacker).ok()?, units.get(target).ok()?)));
+    text.sections[0].value = match attack {
+        Some((attacker, target)) => {
+            describe_forecast(&forecast_attack(attacker, target, &map_layout), target.0)
+        }
+        None => String::new(),
+    };
+}
+
+// Rebuilt whenever the order or the current turn changes
+fn update_turn_order(
+    mut commands: Commands,
+    game_assets: Res<GameAssets>,
+    game_state: Res<GameState>,
+    units: Query<&Unit>,
+    bar: Query<Entity, With<TurnOrderBar>>,
+    mut entries: Query<(&TurnOrderEntry, &mut BackgroundColor)>,
+    mut shown: Local<Vec<Option<(usize, Entity)>>>,
+) {
+    let current = match game_state.state {
+        GameStates::Turn(turn, _) => Some(turn),
+        GameStates::Placing(_, _) | GameStates::Ended => None,
+    };
+    for (entry, mut color) in entries.iter_mut() {
+        *color = if Some(entry.0) == current {
+            CURRENT_ENTRY_COLOR.into()
+        } else {
+            ENTRY_COLOR.into()
+        };
+    }
+
+    if *shown == game_state.turn_order {
+        return;
+    }
+    let Ok(bar) = bar.get_single() else {
+        return;
+    };
+    *shown = game_state.turn_order.clone();
+
+    commands.entity(bar).despawn_descendants();
+    commands.entity(bar).with_children(|bar| {
+        for (index, turn) in game_state.turn_order.iter().enumerate() {
+            // dead units keep their slot until the round ends
+            let Some((unit, owner)) =
+                turn.and_then(|(owner, unit)| units.get(unit).ok().map(|unit| (unit, owner)))
+            else {
+                continue;
+            };
+            let initial = &unit.kind.name()[..1];
+            bar.spawn((
+                ButtonBundle {
+                    style: Style {
+                        size: Size::new(Val::Px(36.), Val::Px(36.)),
+                        justify_content: JustifyContent::Center,
+                        align_items: AlignItems::Center,
+                        ..default()
+                    },
+                    background_color: ENTRY_COLOR.into(),
+                    ..default()
+                },
+                TurnOrderEntry(index),
+            ))
+            .with_children(|entry| {
+                entry.spawn(TextBundle::from_section(
+                    initial,
+                    text_style(&game_assets, participant_color(Some(owner))),
+                ));
+            });
+        }
+    });
+}
+
+fn update_tooltip(
+    game_state: Res<GameState>,
+    vision: Res<Vision>,
+    entries: Query<(&TurnOrderEntry, &Interaction)>,
+    units: Query<(&Unit, Option<&StatusEffects>)>,
+    primary_window: Query<&Window, With<PrimaryWindow>>,
+    mut tooltip: Query<(&mut Text, &mut Style, &mut Visibility), With<Tooltip>>,
+) {
+    let Ok((mut text, mut style, mut visibility)) = tooltip.get_single_mut() else {
+        return;
+    };
+    let hovered = entries
+        .iter()
+        .find(|(_, interaction)| **interaction != Interaction::None)
+        .and_then(|(entry, _)| game_state.turn_order.get(entry.0).copied().flatten())
+        .and_then(|(_, unit)| units.get(unit).ok());
+    let (Some((unit, effects)), Some(cursor)) = (
+        hovered,
+        primary_window
+            .get_single()
+            .ok()
+            .and_then(|window| Some((window.cursor_position()?, window.height()))),
+    ) else {
+        *visibility = Visibility::Hidden;
+        return;
+    };
+
+    // units out of sight give away nothing but who they are
+    let me = game_state.local_participant();
+    text.sections[0].value = if me.is_some_and(|me| !vision.can_see_unit(me, unit)) {
+        format!(
+            "{}\n{}\nOut of sight",
+            unit.kind.name(),
+            owner_name(&game_state, unit.owner)
+        )
+    } else {
+        describe_unit(unit, effects, &game_state)
+    };
+    // the cursor is measured from the bottom of the window, ui from the top
+    let (position, height) = cursor;
+    style.position = UiRect {
+        left: Val::Px(position.x + TOOLTIP_OFFSET),
+        top: Val::Px(height - position.y + TOOLTIP_OFFSET),
+        ..default()
+    };
+    *visibility = Visibility::Inherited;
+}
@@ -0,0 +1,309 @@
+use std::{collections::HashSet, fs};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::AppState;
+
+use super::{
+    game_state::GameState, isometric::IsometricDirection, map::MapLayout, picking::PickedTile,
+    unit::Unit, GameSystemSets,
+};
+
+// read from the working directory, replaces the default bindings
+const BINDINGS_FILE: &str = "bindings.json";
+// how far the left stick has to be pushed to move the cursor
+const STICK_DEADZONE: f32 = 0.5;
+// seconds a direction has to be held before the cursor keeps moving, and between the moves after that
+const REPEAT_DELAY: f32 = 0.35;
+const REPEAT_INTERVAL: f32 = 0.12;
+
+pub struct InputActionsPlugin;
+
+impl Plugin for InputActionsPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(load_bindings());
+        app.insert_resource(GridCursor::default());
+        app.init_resource::<Input<Action>>();
+        app.add_system(reset_cursor.in_schedule(OnEnter(AppState::Game)));
+        app.add_systems(
+            (update_actions, hide_cursor, move_cursor, cycle_units)
+                .chain()
+                .in_set(GameSystemSets::Input),
+        );
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
+pub enum Action {
+    // pick the unit under the cursor
+    Select,
+    // move, attack, use an ability or place a unit on the tile under the cursor
+    Confirm,
+    Cancel,
+    NextUnit,
+    PreviousUnit,
+    MoveCursor(IsometricDirection),
+    // turn actions of the action bar
+    Skip,
+    Defend,
+    Delay,
+    // aim the ability in this slot of the current unit
+    Ability(usize),
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub enum Binding {
+    Key(KeyCode),
+    Mouse(MouseButton),
+    // on any connected gamepad
+    Gamepad(GamepadButtonType),
+}
+
+// An action may have several bindings and a binding may trigger several actions.
+// bindings.json lists every binding as an [action, binding] pair, e.g.
+// [["Select", {"Key": "Space"}], [{"MoveCursor": "UpRight"}, {"Gamepad": "DPadUp"}],
+//  [{"Ability": 0}, {"Key": "Key1"}], ["Cancel", {"Mouse": "Right"}]]
+#[derive(Resource, Serialize, Deserialize)]
+pub struct InputBindings(Vec<(Action, Binding)>);
+
+impl Default for InputBindings {
+    fn default() -> Self {
+        use Action::*;
+        use Binding::*;
+        use IsometricDirection::*;
+
+        InputBindings(vec![
+            (Select, Mouse(MouseButton::Left)),
+            (Select, Key(KeyCode::Space)),
+            (Select, Key(KeyCode::Return)),
+            (Select, Gamepad(GamepadButtonType::South)),
+            (Confirm, Mouse(MouseButton::Left)),
+            (Confirm, Key(KeyCode::Space)),
+            (Confirm, Key(KeyCode::Return)),
+            (Confirm, Gamepad(GamepadButtonType::South)),
+            (Cancel, Mouse(MouseButton::Right)),
+            (Cancel, Key(KeyCode::Escape)),
+            (Cancel, Gamepad(GamepadButtonType::East)),
+            (NextUnit, Key(KeyCode::Tab)),
+            (NextUnit, Key(KeyCode::E)),
+            (NextUnit, Gamepad(GamepadButtonType::RightTrigger)),
+            (PreviousUnit, Key(KeyCode::Q)),
+            (PreviousUnit, Gamepad(GamepadButtonType::LeftTrigger)),
+            // the arrows are turned clockwise by 45 degrees to fit the grid
+            (MoveCursor(UpRight), Key(KeyCode::Up)),
+            (MoveCursor(DownRight), Key(KeyCode::Right)),
+            (MoveCursor(DownLeft), Key(KeyCode::Down)),
+            (MoveCursor(UpLeft), Key(KeyCode::Left)),
+            (MoveCursor(UpRight), Gamepad(GamepadButtonType::DPadUp)),
+            (MoveCursor(DownRight), Gamepad(GamepadButtonType::DPadRight)),
+            (MoveCursor(DownLeft), Gamepad(GamepadButtonType::DPadDown)),
+            (MoveCursor(UpLeft), Gamepad(GamepadButtonType::DPadLeft)),
+            (Skip, Key(KeyCode::S)),
+            (Defend, Key(KeyCode::D)),
+            (Delay, Key(KeyCode::W)),
+            (Ability(0), Key(KeyCode::Key1)),
+            (Ability(1), Key(KeyCode::Key2)),
+            (Ability(2), Key(KeyCode::Key3)),
+        ])
+    }
+}
+
+fn load_bindings() -> InputBindings {
+    let Ok(file) = fs::read_to_string(BINDINGS_FILE) else {
+        return InputBindings::default();
+    };
+    serde_json::from_str(&file).unwrap_or_else(|error| {
+        warn!("ignoring {BINDINGS_FILE}: {error}");
+        InputBindings::default()
+    })
+}
+
+// Tile picked with the keyboard or a gamepad, the mouse takes over again once it moves
+#[derive(Resource, Default)]
+pub struct GridCursor {
+    pub tile: Option<(i32, i32)>,
+    pub active: bool,
+    // seconds until a held direction moves the cursor again
+    repeat_in: f32,
+}
+
+impl GridCursor {
+    fn place(&mut self, tile: (i32, i32)) {
+        self.tile = Some(tile);
+        self.active = true;
+    }
+}
+
+fn reset_cursor(mut cursor: ResMut<GridCursor>) {
+    *cursor = GridCursor::default();
+}
+
+pub(super) fn update_actions(
+    bindings: Res<InputBindings>,
+    keyboard: Res<Input<KeyCode>>,
+    mouse: Res<Input<MouseButton>>,
+    gamepads: Res<Gamepads>,
+    gamepad_buttons: Res<Input<GamepadButton>>,
+    gamepad_axes: Res<Axis<GamepadAxis>>,
+    mut actions: ResMut<Input<Action>>,
+) {
+    let is_pressed = |binding: &Binding| match binding {
+        Binding::Key(key) => keyboard.pressed(*key),
+        Binding::Mouse(button) => mouse.pressed(*button),
+        Binding::Gamepad(button) => gamepads
+            .iter()
+            .any(|gamepad| gamepad_buttons.pressed(GamepadButton::new(gamepad, *button))),
+    };
+    let stick = gamepads.iter().find_map(|gamepad| {
+        let axis = |kind| gamepad_axes.get(GamepadAxis::new(gamepad, kind));
+        stick_direction(Vec2::new(
+            axis(GamepadAxisType::LeftStickX)?,
+            axis(GamepadAxisType::LeftStickY)?,
+        ))
+    });
+
+    let held: HashSet<Action> = bindings
+        .0
+        .iter()
+        .filter(|(_, binding)| is_pressed(binding))
+        .map(|(action, _)| *action)
+        .chain(stick.map(Action::MoveCursor))
+        .collect();
+
+    actions.clear();
+    let released: Vec<Action> = actions
+        .get_pressed()
+        .filter(|action| !held.contains(action))
+        .copied()
+        .collect();
+    for action in released {
+        actions.release(action);
+    }
+    for action in held {
+        actions.press(action);
+    }
+}
+
+// The grid direction closest to where the stick points on screen
+fn stick_direction(stick: Vec2) -> Option<IsometricDirection> {
+    if stick.length() < STICK_DEADZONE {
+        return None;
+    }
+    Some(match (stick.x >= 0., stick.y >= 0.) {
+        (true, true) => IsometricDirection::UpRight,
+        (false, true) => IsometricDirection::UpLeft,
+        (true, false) => IsometricDirection::DownRight,
+        (false, false) => IsometricDirection::DownLeft,
+    })
+}
+
+// The mouse takes over again
+fn hide_cursor(mut cursor_moved: EventReader<CursorMoved>, mut cursor: ResMut<GridCursor>) {
+    if cursor_moved.iter().count() > 0 {
+        cursor.active = false;
+    }
+}
+
+fn move_cursor(
+    actions: Res<Input<Action>>,
+    time: Res<Time>,
+    map_layout: Res<MapLayout>,
+    game_state: Res<GameState>,
+    units: Query<&Unit>,
+    mut cursor: ResMut<GridCursor>,
+    picked_tile: PickedTile,
+) {
+    let pressed = IsometricDirection::all()
+        .into_iter()
+        .find(|direction| actions.pressed(Action::MoveCursor(*direction)));
+    let direction = match pressed {
+        Some(direction) if actions.just_pressed(Action::MoveCursor(direction)) => {
+            cursor.repeat_in = REPEAT_DELAY;
+            Some(direction)
+        }
+        Some(direction) => {
+            cursor.repeat_in -= time.delta_seconds();
+            (cursor.repeat_in <= 0.).then(|| {
+                cursor.repeat_in = REPEAT_INTERVAL;
+                direction
+            })
+        }
+        None => None,
+    };
+    let Some(direction) = direction else {
+        return;
+    };
+
+    // start where the mouse was, or at the unit whose turn it is
+    let start = match cursor.tile {
+        Some(tile) if cursor.active => Some(tile),
+        _ => picked_tile
+            .position()
+            .or_else(|| {
+                let (_, unit) = game_state.current_unit()?;
+                units.get(unit).ok().map(|unit| unit.tile())
+            })
+            .or_else(|| map_layout.tiles.keys().min().copied()),
+    };
+    let Some(start) = start else {
+        return;
+    };
+    if !cursor.active {
+        // the first press only shows the cursor
+        cursor.place(start);
+        return;
+    }
+
+    // jumps over gaps in the map
+    let (dx, dy) = direction.to_vec();
+    let mut tile = (start.0 + dx, start.1 + dy);
+    while map_layout.is_within_bounds(&tile) {
+        if map_layout.tiles.contains_key(&tile) {
+            cursor.place(tile);
+            return;
+        }
+        tile = (tile.0 + dx, tile.1 + dy);
+    }
+}
+
+pub(super) fn cycle_units(
+    actions: Res<Input<Action>>,
+    game_state: Res<GameState>,
+    units: Query<&Unit>,
+    mut cursor: ResMut<GridCursor>,
+) {
+    let step = match (
+        actions.just_pressed(Action::NextUnit),
+        actions.just_pressed(Action::PreviousUnit),
+    ) {
+        (true, false) => 1,
+        (false, true) => -1,
+        _ => return,
+    };
+    let Some(me) = game_state.local_participant() else {
+        return;
+    };
+
+    // in reading order, so the cursor doesn't jump around the map
+    let mut tiles: Vec<(i32, i32)> = units
+        .iter()
+        .filter(|unit| unit.owner == me)
+        .map(|unit| unit.tile())
+        .collect();
+    tiles.sort_by_key(|(x, y)| (*y, *x));
+    if tiles.is_empty() {
+        return;
+    }
+
+    let next = match cursor
+        .tile
+        .filter(|_| cursor.active)
+        .and_then(|tile| tiles.iter().position(|unit| *unit == tile))
+    {
+        Some(index) => (index as i32 + step).rem_euclid(tiles.len() as i32) as usize,
+        None if step > 0 => 0,
+        None => tiles.len() - 1,
+    };
+    cursor.place(tiles[next]);
+}
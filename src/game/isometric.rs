@@ -1,4 +1,5 @@
 use bevy::prelude::Vec3;
+use serde::{Deserialize, Serialize};
 
 // Warning! As soon as zb is dependant on tile width / height the unit move code breaks
 pub fn iso_transform(x: f32, y: f32, z: f32, w: f32, h: f32, is_unit: bool) -> Vec3 {
@@ -13,6 +14,7 @@ pub fn iso_transform(x: f32, y: f32, z: f32, w: f32, h: f32, is_unit: bool) -> V
     Vec3::new(xb, yb, zb)
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
 pub enum IsometricDirection {
     UpRight,
     UpLeft,
@@ -30,4 +32,65 @@ impl IsometricDirection {
             _ => None,
         }
     }
+
+    pub fn all() -> [Self; 4] {
+        [Self::UpRight, Self::UpLeft, Self::DownRight, Self::DownLeft]
+    }
+
+    pub fn to_vec(self) -> (i32, i32) {
+        match self {
+            Self::UpRight => (0, -1),
+            Self::UpLeft => (-1, 0),
+            Self::DownRight => (1, 0),
+            Self::DownLeft => (0, 1),
+        }
+    }
+
+    // the direction from one tile to another, if both are on a straight line
+    pub fn between(from: &(i32, i32), to: &(i32, i32)) -> Option<Self> {
+        let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+        if dx != 0 && dy != 0 {
+            return None;
+        }
+        Self::from_vec((dx.signum(), dy.signum()))
+    }
+
+    // the direction closest to the line from one tile to another
+    pub fn towards(from: &(i32, i32), to: &(i32, i32)) -> Option<Self> {
+        let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+        if dx.abs() >= dy.abs() {
+            Self::from_vec((dx.signum(), 0))
+        } else {
+            Self::from_vec((0, dy.signum()))
+        }
+    }
+
+    // used in the names of directional animation states
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::UpRight => "up_right",
+            Self::UpLeft => "up_left",
+            Self::DownRight => "down_right",
+            Self::DownLeft => "down_left",
+        }
+    }
+
+    pub fn opposite(&self) -> Self {
+        match self {
+            Self::UpRight => Self::DownLeft,
+            Self::UpLeft => Self::DownRight,
+            Self::DownRight => Self::UpLeft,
+            Self::DownLeft => Self::UpRight,
+        }
+    }
+
+    // rotated by 90 degrees
+    pub fn perpendicular(&self) -> Self {
+        match self {
+            Self::UpRight => Self::DownRight,
+            Self::UpLeft => Self::UpRight,
+            Self::DownRight => Self::DownLeft,
+            Self::DownLeft => Self::UpLeft,
+        }
+    }
 }
@@ -1,16 +1,25 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
-use bevy::prelude::*;
+use bevy::{ecs::system::SystemParam, prelude::*};
 
 use crate::{
-    assets::types::TiledMap, game_config::GameAssets, util::collisions::Triangle, AppState,
+    assets::types::{TiledMap, TiledSet},
+    game_config::{GameAssets, TileTexture},
+    loading::{tile_textures, TiledAssets},
+    AppState,
 };
 
 use super::{
-    game_state::{GameState, GameStates, Participant},
+    combat::{find_attack_targets, AttackTargets},
+    game_state::{
+        update_game_state, GameState, GameStateEvent, GameStates, Participant, TurnAction,
+    },
+    input::Action,
     isometric::iso_transform,
-    picking::{PickState, Pickable},
-    unit::{Unit, UnitRegistry},
+    map_validation::validate_map,
+    picking::{tile_pickables, PickState},
+    unit::{Unit, UnitKind, UnitRegistry},
+    vision::{visible_unit_at, Vision},
     GameEvent, GameSystemSets,
 };
 
@@ -20,14 +29,19 @@ impl Plugin for MapPlugin {
     fn build(&self, app: &mut App) {
         app.insert_resource(MapState::default());
         app.insert_resource(MapLayout::default());
+        app.add_event::<MapReloaded>();
 
         app.add_systems((
             create_map.in_schedule(OnEnter(AppState::Game)),
             destroy_map.in_schedule(OnExit(AppState::Game)),
+            create_map.in_schedule(OnEnter(AppState::Editor)),
+            destroy_map.in_schedule(OnExit(AppState::Editor)),
+            reload_map.in_set(GameSystemSets::Update),
             update_tint.in_set(GameSystemSets::Render),
             clear_tile_selection
                 .run_if(should_clear_tile_selection)
-                .in_set(GameSystemSets::Logic),
+                .in_set(GameSystemSets::Logic)
+                .after(select_attack_targets),
             select_tile
                 .run_if(should_select_tile)
                 .in_set(GameSystemSets::Logic),
@@ -35,6 +49,15 @@ impl Plugin for MapPlugin {
                 .run_if(should_confirm_move)
                 .in_set(GameSystemSets::Logic)
                 .before(select_tile),
+            confirm_attack
+                .run_if(should_confirm_attack)
+                .in_set(GameSystemSets::Logic)
+                .before(confirm_move),
+            select_attack_targets
+                .run_if(should_select_attack_targets)
+                .in_set(GameSystemSets::Logic)
+                .after(select_tile)
+                .after(update_game_state),
             place_unit
                 .run_if(should_place_unit)
                 .in_set(GameSystemSets::Logic),
@@ -47,11 +70,13 @@ pub struct Tile {
     pub x: i32,
     pub y: i32,
     pub z: i32,
+    // from the Tiled layer
+    pub opacity: f32,
 }
 
 impl Tile {
-    fn new(x: i32, y: i32, z: i32) -> Self {
-        Tile { x, y, z }
+    fn new(x: i32, y: i32, z: i32, opacity: f32) -> Self {
+        Tile { x, y, z, opacity }
     }
 }
 
@@ -61,16 +86,107 @@ pub struct Map;
 #[derive(Resource, Default)]
 pub struct MapLayout {
     pub tiles: HashMap<(i32, i32), u32>,
+    // smallest and largest x / y of any tile
+    bounds: ((i32, i32), (i32, i32)),
+    // tiles nothing can enter, e.g. water or closed doors
+    pub blocked: HashSet<(i32, i32)>,
+    // participant => tiles the participant may place units on
+    pub deployment_zones: HashMap<usize, HashSet<(i32, i32)>>,
+}
+
+impl MapLayout {
+    fn update_bounds(&mut self) {
+        let xs = self.tiles.keys().map(|tile| tile.0);
+        let ys = self.tiles.keys().map(|tile| tile.1);
+        self.bounds = (
+            (xs.clone().min().unwrap_or(0), ys.clone().min().unwrap_or(0)),
+            (xs.max().unwrap_or(0), ys.max().unwrap_or(0)),
+        );
+    }
+
+    // flying units may cross gaps inside the map, but never leave it
+    pub fn is_within_bounds(&self, tile: &(i32, i32)) -> bool {
+        let ((min_x, min_y), (max_x, max_y)) = self.bounds;
+        (min_x..=max_x).contains(&tile.0) && (min_y..=max_y).contains(&tile.1)
+    }
+
+    // without a deployment zone a participant may place units anywhere
+    pub fn can_deploy(&self, participant: usize, tile: &(i32, i32)) -> bool {
+        self.tiles.contains_key(tile)
+            && !self.blocked.contains(tile)
+            && self
+                .deployment_zones
+                .get(&participant)
+                .is_none_or(|zone| zone.contains(tile))
+    }
+}
+
+// Where everything is and what each participant can see
+#[derive(SystemParam)]
+pub struct Battlefield<'w> {
+    pub map_layout: Res<'w, MapLayout>,
+    pub unit_registry: Res<'w, UnitRegistry>,
+    pub vision: Res<'w, Vision>,
 }
 
 #[derive(Resource, Default)]
 pub struct MapState {
     tile_tints: HashMap<(i32, i32), Color>,
 
-    unit_move_selection: Option<(Entity, HashMap<(i32, i32), (i32, i32)>)>,
+    unit_move_selection: Option<(Entity, UnitPaths)>,
+    attack_selection: Option<(Entity, AttackTargets)>,
+    // caster, ability slot
+    pub ability_targeting: Option<(Entity, usize)>,
     pub unit_moving: bool,
 }
 
+impl MapState {
+    pub fn clear_selection(&mut self) {
+        self.unit_move_selection = None;
+        self.attack_selection = None;
+        self.ability_targeting = None;
+        self.tile_tints.clear();
+    }
+
+    // the unit whose move, attack or ability is being aimed
+    pub fn selected_unit(&self) -> Option<Entity> {
+        self.unit_move_selection
+            .as_ref()
+            .map(|(unit, _)| *unit)
+            .or(self.attack_selection.as_ref().map(|(unit, _)| *unit))
+            .or(self.ability_targeting.map(|(unit, _)| unit))
+    }
+
+    // the attacking unit, if the tile holds a target it can see
+    pub fn attacker_of(&self, tile: &(i32, i32)) -> Option<Entity> {
+        let (attacker, targets) = self.attack_selection.as_ref()?;
+        (targets.get(tile) == Some(&true)).then_some(*attacker)
+    }
+
+    // range: tiles the ability can be aimed at, area: tiles hit at the hovered target
+    pub fn preview_ability(&mut self, range: Vec<(i32, i32)>, area: Vec<(i32, i32)>) {
+        self.tile_tints.clear();
+        for tile in range {
+            self.tile_tints.insert(tile, Color::rgb(1.0, 1.0, 0.6));
+        }
+        for tile in area {
+            self.tile_tints.insert(tile, Color::rgb(1.0, 0.6, 0.3));
+        }
+    }
+
+    fn select_attack_targets(&mut self, unit: Entity, targets: AttackTargets) {
+        for (tile, in_sight) in targets.iter() {
+            let color = if *in_sight {
+                Color::rgb(1.0, 0.5, 0.5)
+            } else {
+                Color::rgb(0.5, 0.5, 0.6)
+            };
+            self.tile_tints.insert(*tile, color);
+        }
+        self.attack_selection = Some((unit, targets));
+    }
+}
+
 pub fn create_map(
     mut commands: Commands,
     game_assets: Res<GameAssets>,
@@ -78,65 +194,86 @@ pub fn create_map(
     mut map_layout: ResMut<MapLayout>,
 ) {
     let tilemap = tilemaps.get(&game_assets.map).unwrap();
-    let (tile_w, tile_h) = (tilemap.tilewidth as f32, tilemap.tileheight as f32);
+    spawn_map(&mut commands, &game_assets, tilemap, &mut map_layout);
+}
 
-    let pickable = Pickable {
-        triangles: vec![
-            Triangle::new(
-                Vec2::new(116., 0.),
-                Vec2::new(-116., 0.),
-                Vec2::new(0., 45.),
-            ),
-            Triangle::new(
-                Vec2::new(116., 0.),
-                Vec2::new(-116., 0.),
-                Vec2::new(-116., -116.),
-            ),
-            Triangle::new(
-                Vec2::new(-116., -116.),
-                Vec2::new(116., -116.),
-                Vec2::new(116., 0.),
-            ),
-            Triangle::new(
-                Vec2::new(116., -116.),
-                Vec2::new(-116., -116.),
-                Vec2::new(0., -170.),
-            ),
-        ],
-    };
+pub(super) fn spawn_map(
+    commands: &mut Commands,
+    game_assets: &GameAssets,
+    tilemap: &TiledMap,
+    map_layout: &mut MapLayout,
+) {
+    map_layout.tiles.clear();
+    map_layout.blocked.clear();
+    let (tile_w, tile_h) = (tilemap.tilewidth as f32, tilemap.tileheight as f32);
+    // gid of the highest tile at every position
+    let mut top_tiles = HashMap::new();
 
     let mut tiles: Vec<Entity> = vec![];
-    for (layer_id, layer) in tilemap.layers.iter().enumerate() {
+    for (layer_id, layer) in tilemap.tile_layers().enumerate() {
         let layer_id = layer_id as u32;
         let layer_id_f = layer_id as f32;
+        // hidden layers still count as a height level
+        if !layer.visible {
+            continue;
+        }
+        let offset = Vec3::new(layer.offsetx, -layer.offsety, 0.);
 
-        for editor_x in 0..layer.width {
-            for editor_y in 0..layer.height {
-                let id = layer.data[(editor_x + editor_y * layer.width) as usize] as usize;
-                if id == 0 {
-                    continue;
-                }
-
-                let (x, y) = correct_editor_transform(editor_x, editor_y, layer_id);
-                map_layout.tiles.insert((x, y), layer_id);
-                let tile = commands
-                    .spawn((
-                        SpriteBundle {
-                            texture: game_assets.tiles[id - 1].clone(),
-                            transform: Transform::default().with_translation(iso_transform(
-                                x as f32, y as f32, layer_id_f, tile_w, tile_h, false,
-                            )),
-                            ..default()
-                        },
-                        pickable.clone(),
-                        Tile::new(x, y, layer_id as i32),
-                    ))
-                    .id();
-                tiles.push(tile);
-            }
+        for ((editor_x, editor_y), gid) in layer.tiles() {
+            let Some(texture) = game_assets.tiles.get(&gid.id()) else {
+                warn!("layer '{}' uses unknown tile {}", layer.name, gid.id());
+                continue;
+            };
+
+            let (x, y) = correct_editor_transform(editor_x, editor_y, layer_id);
+            map_layout.tiles.insert((x, y), layer_id);
+            top_tiles.insert((x, y), gid.id());
+            let transform = Transform::default().with_translation(
+                iso_transform(x as f32, y as f32, layer_id_f, tile_w, tile_h, false) + offset,
+            );
+            let mut tile = match texture {
+                TileTexture::Image(texture) => commands.spawn(SpriteBundle {
+                    sprite: Sprite {
+                        flip_x: gid.flip_x(),
+                        flip_y: gid.flip_y(),
+                        ..default()
+                    },
+                    texture: texture.clone(),
+                    transform,
+                    ..default()
+                }),
+                TileTexture::Atlas(atlas, index) => commands.spawn(SpriteSheetBundle {
+                    sprite: TextureAtlasSprite {
+                        index: *index,
+                        flip_x: gid.flip_x(),
+                        flip_y: gid.flip_y(),
+                        ..default()
+                    },
+                    texture_atlas: atlas.clone(),
+                    transform,
+                    ..default()
+                }),
+            };
+            let pickable = game_assets
+                .tile_pickables
+                .get(&gid.id())
+                .map(|pickable| pickable.flipped(gid.flip_x(), gid.flip_y()))
+                .unwrap_or_default();
+            let tile = tile
+                .insert((pickable, Tile::new(x, y, layer_id as i32, layer.opacity)))
+                .id();
+            tiles.push(tile);
         }
     }
 
+    map_layout.update_bounds();
+    map_layout.blocked.extend(
+        top_tiles
+            .into_iter()
+            .filter(|(_, gid)| game_assets.impassable_tiles.contains(gid))
+            .map(|(tile, _)| tile),
+    );
+
     let mut map = commands.spawn((
         Map,
         SpatialBundle {
@@ -149,31 +286,39 @@ pub fn create_map(
     }
 }
 
-fn should_clear_tile_selection(mouse: Res<Input<MouseButton>>) -> bool {
-    mouse.just_pressed(MouseButton::Right)
+fn should_clear_tile_selection(actions: Res<Input<Action>>) -> bool {
+    actions.just_pressed(Action::Cancel)
 }
 
-fn clear_tile_selection(mut map_state: ResMut<MapState>) {
-    map_state.unit_move_selection = None;
-    map_state.tile_tints.clear();
+// Cancelling the attack after a move declines it and ends the turn
+fn clear_tile_selection(
+    game_state: Res<GameState>,
+    mut map_state: ResMut<MapState>,
+    mut event_writer: EventWriter<GameStateEvent>,
+) {
+    if matches!(game_state.state, GameStates::Turn(_, true)) && map_state.attack_selection.is_some()
+    {
+        event_writer.send(GameStateEvent::TurnAction(TurnAction::Skip));
+    }
+    map_state.clear_selection();
 }
 
 fn should_select_tile(
-    mouse: Res<Input<MouseButton>>,
+    actions: Res<Input<Action>>,
     map_state: Res<MapState>,
     game_state: Res<GameState>,
 ) -> bool {
-    mouse.just_pressed(MouseButton::Left)
+    actions.just_pressed(Action::Select)
         && !map_state.unit_moving
+        && map_state.ability_targeting.is_none()
         && matches!(game_state.state, GameStates::Turn(_, _))
 }
 
-fn select_tile(
+pub fn select_tile(
+    battlefield: Battlefield,
     tiles: Query<&Tile>,
     pick_state: Res<PickState>,
-    unit_registry: Res<UnitRegistry>,
     units: Query<&Unit>,
-    map_layout: Res<MapLayout>,
     game_state: Res<GameState>,
     mut map_state: ResMut<MapState>,
 ) {
@@ -183,14 +328,23 @@ fn select_tile(
     let GameStates::Turn(turn, did_move) = game_state.state else {
         return;
     };
-    let Some(unit) = unit_registry.units.get(&(tile.x, tile.y)) else {
+    let Some(me) = game_state.local_participant() else {
+        return;
+    };
+    let Some(unit) = visible_unit_at(
+        &(tile.x, tile.y),
+        me,
+        &battlefield.vision,
+        &battlefield.unit_registry,
+        &units,
+    ) else {
         return;
     };
-    map_state.tile_tints.clear();
+    map_state.clear_selection();
     let Some((participant, turn_unit)) = game_state.turn_order[turn] else {
         return;
     };
-    if turn_unit != *unit || game_state.participants[participant] != Participant::Me || did_move {
+    if turn_unit != unit || game_state.participants[participant] != Participant::Me || did_move {
         return;
     }
     let unit_comp = units.get(turn_unit).unwrap();
@@ -198,34 +352,137 @@ fn select_tile(
     let paths = find_unit_paths(
         unit_comp.travel_distance,
         (tile.x, tile.y),
-        &map_layout,
-        &unit_registry,
+        &battlefield.map_layout,
+        &battlefield.unit_registry,
         unit_comp,
+        &units,
+        &battlefield.vision,
     );
     for reachable_tile in paths.keys() {
+        if !can_end_move_on(
+            reachable_tile,
+            turn_unit,
+            me,
+            &battlefield.map_layout,
+            &battlefield.vision,
+            &battlefield.unit_registry,
+            &units,
+        ) {
+            continue;
+        }
         let (x, y) = *reachable_tile;
         map_state
             .tile_tints
             .insert((x, y), Color::rgb(0.6, 1.0, 0.6));
     }
-    map_state.unit_move_selection = Some((*unit, paths));
+    map_state.unit_move_selection = Some((unit, paths));
+
+    let targets = find_attack_targets(
+        (tile.x, tile.y),
+        unit_comp,
+        &battlefield.map_layout,
+        &battlefield.unit_registry,
+        &units,
+        &battlefield.vision,
+    );
+    map_state.select_attack_targets(unit, targets);
+}
+
+fn should_select_attack_targets(map_state: Res<MapState>, game_state: Res<GameState>) -> bool {
+    let GameStates::Turn(_, true) = game_state.state else {
+        return false;
+    };
+    let Some((participant, _)) = game_state.current_unit() else {
+        return false;
+    };
+    game_state.participants[participant] == Participant::Me
+        && !map_state.unit_moving
+        && map_state.attack_selection.is_none()
+}
+
+// Shows the targets of the unit that just moved. Ends the turn if there is nothing to attack
+fn select_attack_targets(
+    unit_registry: Res<UnitRegistry>,
+    units: Query<&Unit>,
+    map_layout: Res<MapLayout>,
+    game_state: Res<GameState>,
+    vision: Res<Vision>,
+    mut map_state: ResMut<MapState>,
+    mut event_writer: EventWriter<GameStateEvent>,
+) {
+    let Some((_, entity)) = game_state.current_unit() else {
+        return;
+    };
+    let Ok(unit) = units.get(entity) else {
+        return;
+    };
+
+    let targets = find_attack_targets(
+        unit.tile(),
+        unit,
+        &map_layout,
+        &unit_registry,
+        &units,
+        &vision,
+    );
+    if !targets.values().any(|in_sight| *in_sight) {
+        event_writer.send(GameStateEvent::TurnAction(TurnAction::Skip));
+        return;
+    }
+    map_state.select_attack_targets(entity, targets);
+}
+
+fn should_confirm_attack(
+    actions: Res<Input<Action>>,
+    map_state: Res<MapState>,
+    game_state: Res<GameState>,
+) -> bool {
+    actions.just_pressed(Action::Confirm)
+        && !map_state.unit_moving
+        && map_state.attack_selection.is_some()
+        && matches!(game_state.state, GameStates::Turn(_, _))
+}
+
+fn confirm_attack(
+    tiles: Query<&Tile>,
+    pick_state: Res<PickState>,
+    unit_registry: Res<UnitRegistry>,
+    mut map_state: ResMut<MapState>,
+    mut event_writer: EventWriter<GameEvent>,
+) {
+    let Some(Ok(tile)) = pick_state.selected.map(|tile| tiles.get(tile)) else {
+        return;
+    };
+    let Some((attacker, targets)) = &map_state.attack_selection else {
+        return;
+    };
+    if targets.get(&(tile.x, tile.y)) != Some(&true) {
+        return;
+    }
+    let Some(target) = unit_registry.units.get(&(tile.x, tile.y)) else {
+        return;
+    };
+
+    event_writer.send(GameEvent::Attack(*attacker, *target));
+    map_state.clear_selection();
 }
 
 fn should_confirm_move(
-    mouse: Res<Input<MouseButton>>,
+    actions: Res<Input<Action>>,
     map_state: Res<MapState>,
     game_state: Res<GameState>,
 ) -> bool {
-    mouse.just_pressed(MouseButton::Left)
+    actions.just_pressed(Action::Confirm)
         && !map_state.unit_moving
         && map_state.unit_move_selection.is_some()
         && matches!(game_state.state, GameStates::Turn(_, _))
 }
 
 fn confirm_move(
+    battlefield: Battlefield,
     tiles: Query<&Tile>,
     pick_state: Res<PickState>,
-    mut units: Query<&mut Unit>,
+    mut units: ParamSet<(Query<&Unit>, Query<&mut Unit>)>,
     mut map_state: ResMut<MapState>,
 ) {
     let tile = match pick_state.selected.map(|tile| tiles.get(tile)) {
@@ -233,12 +490,27 @@ fn confirm_move(
         _ => return,
     };
 
-    let (unit, paths) = std::mem::replace(&mut map_state.unit_move_selection, None).unwrap();
-    if !paths.contains_key(&(tile.x, tile.y)) {
-        clear_tile_selection(map_state);
+    let (unit, paths) = map_state.unit_move_selection.take().unwrap();
+    let Ok(owner) = units.p0().get(unit).map(|unit| unit.owner) else {
+        map_state.clear_selection();
+        return;
+    };
+    if !paths.contains_key(&(tile.x, tile.y))
+        || !can_end_move_on(
+            &(tile.x, tile.y),
+            unit,
+            owner,
+            &battlefield.map_layout,
+            &battlefield.vision,
+            &battlefield.unit_registry,
+            &units.p0(),
+        )
+    {
+        map_state.clear_selection();
         return;
     }
 
+    let mut units = units.p1();
     let mut unit = units.get_mut(unit).unwrap();
     if let Some(path) = to_path(paths, (unit.x as i32, unit.y as i32), (tile.x, tile.y)) {
         if path.len() > 1 {
@@ -247,11 +519,11 @@ fn confirm_move(
         }
     }
 
-    clear_tile_selection(map_state);
+    map_state.clear_selection();
 }
 
-fn should_place_unit(mouse: Res<Input<MouseButton>>, game_state: Res<GameState>) -> bool {
-    if !mouse.just_pressed(MouseButton::Left) {
+fn should_place_unit(actions: Res<Input<Action>>, game_state: Res<GameState>) -> bool {
+    if !actions.just_pressed(Action::Confirm) {
         return false;
     }
     let GameStates::Placing(player_id, _) = game_state.state else {
@@ -263,6 +535,7 @@ fn should_place_unit(mouse: Res<Input<MouseButton>>, game_state: Res<GameState>)
 fn place_unit(
     pick_state: Res<PickState>,
     tiles: Query<&Tile>,
+    map_layout: Res<MapLayout>,
     units: Res<UnitRegistry>,
     mut unit_events: EventWriter<GameEvent>,
     game_state: Res<GameState>,
@@ -274,32 +547,83 @@ fn place_unit(
     if units.units.contains_key(&(tile.x, tile.y)) {
         return;
     }
-    let GameStates::Placing(player_id, _) = game_state.state else {
+    let GameStates::Placing(player_id, round) = game_state.state else {
         return;
     };
+    if !map_layout.can_deploy(player_id, &(tile.x, tile.y)) {
+        return;
+    }
 
-    unit_events.send(GameEvent::SpawnUnit(tile.x, tile.y, player_id));
+    unit_events.send(GameEvent::SpawnUnit(
+        tile.x,
+        tile.y,
+        player_id,
+        UnitKind::for_placing_round(round),
+    ));
 }
 
 fn update_tint(
     pick_state: Res<PickState>,
-    mut tiles: Query<(&mut Sprite, &Tile, Entity)>,
+    mut tiles: Query<(
+        Option<&mut Sprite>,
+        Option<&mut TextureAtlasSprite>,
+        &Tile,
+        Entity,
+    )>,
     map_state: Res<MapState>,
+    map_layout: Res<MapLayout>,
+    game_state: Res<GameState>,
+    vision: Res<Vision>,
 ) {
-    for (mut sprite, tile, entity) in tiles.iter_mut() {
+    let me = game_state.local_participant();
+    // the whole map stays lit while placing, so every spot can be picked
+    let fog = matches!(game_state.state, GameStates::Turn(_, _));
+
+    for (sprite, atlas_sprite, tile, entity) in tiles.iter_mut() {
         let mut color = map_state
             .tile_tints
             .get(&(tile.x, tile.y))
-            .map(|color| color.clone())
+            .copied()
             .unwrap_or(Color::WHITE);
 
+        // highlight where units can be placed
+        if let GameStates::Placing(player_id, _) = game_state.state {
+            if me == Some(player_id)
+                && map_layout.deployment_zones.contains_key(&player_id)
+                && map_layout.can_deploy(player_id, &(tile.x, tile.y))
+            {
+                color = Color::rgb(0.7, 1.0, 0.7);
+            }
+        }
+
+        if let (Some(me), true) = (me, fog) {
+            let brightness = if vision.can_see(me, &(tile.x, tile.y)) {
+                1.
+            } else if vision.has_explored(me, &(tile.x, tile.y)) {
+                0.6
+            } else {
+                0.25
+            };
+            color = Color::rgb(
+                brightness * color.r(),
+                brightness * color.g(),
+                brightness * color.b(),
+            );
+        }
+
         if let Some(selected) = pick_state.selected {
             if selected == entity {
                 color = Color::rgb(1.2 * color.r(), 1.2 * color.g(), 1.2 * color.b());
             }
         }
 
-        sprite.color = color;
+        let color = color.with_a(tile.opacity);
+        if let Some(mut sprite) = sprite {
+            sprite.color = color;
+        }
+        if let Some(mut sprite) = atlas_sprite {
+            sprite.color = color;
+        }
     }
 }
 
@@ -309,23 +633,145 @@ pub fn destroy_map(mut command: Commands, query: Query<Entity, With<Map>>) {
     }
 }
 
+// Sent after the map was rebuilt from a changed map or tileset file
+pub struct MapReloaded;
+
+// Changes to the map and tileset files
+#[derive(SystemParam)]
+pub struct MapFileEvents<'w, 's> {
+    map_events: EventReader<'w, 's, AssetEvent<TiledMap>>,
+    tileset_events: EventReader<'w, 's, AssetEvent<TiledSet>>,
+}
+
+// The spawned map and what is known about it
+#[derive(SystemParam)]
+pub struct SpawnedMap<'w, 's> {
+    maps: Query<'w, 's, Entity, With<Map>>,
+    layout: ResMut<'w, MapLayout>,
+    state: ResMut<'w, MapState>,
+    reloaded: EventWriter<'w, MapReloaded>,
+}
+
+// Rebuilds the map whenever Tiled saves the map or one of its tilesets
+pub fn reload_map(
+    mut commands: Commands,
+    mut pending: Local<bool>,
+    mut file_events: MapFileEvents,
+    mut tiled: TiledAssets,
+    mut game_assets: ResMut<GameAssets>,
+    game_state: Res<GameState>,
+    mut spawned_map: SpawnedMap,
+) {
+    for event in file_events.map_events.iter() {
+        if let AssetEvent::Modified { handle } = event {
+            *pending |= *handle == game_assets.map;
+        }
+    }
+    for event in file_events.tileset_events.iter() {
+        if let AssetEvent::Created { handle } | AssetEvent::Modified { handle } = event {
+            *pending |= game_assets.tilesets.contains(handle);
+        }
+    }
+    if !*pending {
+        return;
+    }
+    let Some(tilemap) = tiled.tilemaps.get(&game_assets.map) else {
+        return;
+    };
+
+    // the map may reference other tilesets now, they are loaded as its dependencies
+    let map_tilesets: Vec<(u32, Handle<TiledSet>)> = tilemap
+        .tilesets
+        .iter()
+        .map(|tileset| (tileset.firstgid, tiled.assets.load(tileset.path.as_path())))
+        .collect();
+    game_assets.tilesets = map_tilesets
+        .iter()
+        .map(|(_, handle)| handle.clone())
+        .collect();
+    let Some((tiles, _)) = tile_textures(
+        &map_tilesets,
+        &tiled.tilesets,
+        &tiled.assets,
+        &mut tiled.atlases,
+    ) else {
+        return;
+    };
+    game_assets.tiles = tiles;
+    *pending = false;
+
+    let loaded_tilesets: Vec<(u32, &TiledSet)> = map_tilesets
+        .iter()
+        .filter_map(|(firstgid, handle)| Some((*firstgid, tiled.tilesets.get(handle)?)))
+        .collect();
+    game_assets.tile_pickables = tile_pickables(tilemap, &loaded_tilesets);
+    game_assets.impassable_tiles = impassable_tiles(&loaded_tilesets);
+    for error in validate_map(
+        tilemap,
+        &loaded_tilesets,
+        game_state.participants.len(),
+        game_state.units_per_participant,
+    ) {
+        warn!("reloaded map is invalid: {error}");
+    }
+
+    for map in spawned_map.maps.iter() {
+        commands.entity(map).despawn_recursive();
+    }
+    spawn_map(
+        &mut commands,
+        &game_assets,
+        tilemap,
+        &mut spawned_map.layout,
+    );
+    spawned_map.state.clear_selection();
+    spawned_map.reloaded.send(MapReloaded);
+}
+
 //
 // Util
 
-fn correct_editor_transform(editor_x: u32, editor_y: u32, layer_id: u32) -> (i32, i32) {
+// Extra movement it costs to leave a tile next to an enemy unit
+const DISENGAGE_COST: u32 = 1;
+
+// Gids of the tiles with the impassable property, e.g. water
+pub fn impassable_tiles(map_tilesets: &[(u32, &TiledSet)]) -> HashSet<u32> {
+    map_tilesets
+        .iter()
+        .flat_map(|(firstgid, tileset)| {
+            tileset
+                .tiles
+                .iter()
+                .filter(|tile| tile.properties.bool("impassable").unwrap_or(false))
+                .map(move |tile| firstgid + tile.id)
+        })
+        .collect()
+}
+
+pub fn correct_editor_transform(editor_x: i32, editor_y: i32, layer_id: u32) -> (i32, i32) {
     (
-        editor_x as i32 - 1 + layer_id as i32,
-        editor_y as i32 - 1 + layer_id as i32,
+        editor_x - 1 + layer_id as i32,
+        editor_y - 1 + layer_id as i32,
     )
 }
 
+// Inverse of correct_editor_transform
+pub fn editor_position(x: i32, y: i32, layer_id: u32) -> (i32, i32) {
+    (x + 1 - layer_id as i32, y + 1 - layer_id as i32)
+}
+
+// tile => the tile it is reached from
+pub type UnitPaths = HashMap<(i32, i32), (i32, i32)>;
+
 pub fn find_unit_paths(
     distance: u32,
     location: (i32, i32),
     map_layout: &Res<MapLayout>,
     unit_registry: &Res<UnitRegistry>,
     unit: &Unit,
-) -> HashMap<(i32, i32), (i32, i32)> {
+    units: &Query<&Unit>,
+    vision: &Vision,
+) -> UnitPaths {
     let mut paths = HashMap::new();
     // distance => list<(from_tile, to_tile)>
     let mut check_queue = HashMap::from([(0, vec![(location, location)])]);
@@ -344,41 +790,34 @@ pub fn find_unit_paths(
             // Forget from here --------
 
             let from = to;
-            let (x, y) = from;
-
-            let dest = (x + 1, y);
-            if let Some(cost) = distance_cost_from_to(&from, &dest, map_layout, unit_registry, unit)
-            {
-                let key = i + cost;
-                if let Some(queue) = check_queue.get_mut(&key) {
-                    queue.push((from, dest));
-                }
-            }
-
-            let dest = (x - 1, y);
-            if let Some(cost) = distance_cost_from_to(&from, &dest, map_layout, unit_registry, unit)
-            {
-                let key = i + cost;
-                if let Some(queue) = check_queue.get_mut(&key) {
-                    queue.push((from, dest));
-                }
-            }
-
-            let dest = (x, y + 1);
-            if let Some(cost) = distance_cost_from_to(&from, &dest, map_layout, unit_registry, unit)
-            {
-                let key = i + cost;
-                if let Some(queue) = check_queue.get_mut(&key) {
-                    queue.push((from, dest));
-                }
+            // flying units ignore zones of control
+            let in_zone_of_control = !unit.is_air
+                && is_in_zone_of_control(&from, unit.owner, vision, unit_registry, units);
+            // units have to stop when they walk next to an enemy
+            if from != location && in_zone_of_control {
+                continue;
             }
-
-            let dest = (x, y - 1);
-            if let Some(cost) = distance_cost_from_to(&from, &dest, map_layout, unit_registry, unit)
-            {
-                let key = i + cost;
-                if let Some(queue) = check_queue.get_mut(&key) {
-                    queue.push((from, dest));
+            // disengaging from an enemy costs extra movement
+            let extra_cost = if in_zone_of_control {
+                DISENGAGE_COST
+            } else {
+                0
+            };
+
+            for dest in neighbours(&from) {
+                if let Some(cost) = distance_cost_from_to(
+                    &from,
+                    &dest,
+                    map_layout,
+                    unit_registry,
+                    unit,
+                    units,
+                    vision,
+                ) {
+                    let key = i + cost + extra_cost;
+                    if let Some(queue) = check_queue.get_mut(&key) {
+                        queue.push((from, dest));
+                    }
                 }
             }
         }
@@ -387,33 +826,85 @@ pub fn find_unit_paths(
     paths
 }
 
+// Units can walk through friendly units, but not stop on them. Flying units can't land on gaps
+pub fn can_end_move_on(
+    tile: &(i32, i32),
+    moving_unit: Entity,
+    owner: usize,
+    map_layout: &MapLayout,
+    vision: &Vision,
+    unit_registry: &UnitRegistry,
+    units: &Query<&Unit>,
+) -> bool {
+    if !map_layout.tiles.contains_key(tile) {
+        return false;
+    }
+    match visible_unit_at(tile, owner, vision, unit_registry, units) {
+        Some(entity) => entity == moving_unit,
+        None => true,
+    }
+}
+
+pub fn neighbours(tile: &(i32, i32)) -> [(i32, i32); 4] {
+    let (x, y) = *tile;
+    [(x + 1, y), (x - 1, y), (x, y + 1), (x, y - 1)]
+}
+
+// Enemies hidden in the fog are ignored, the unit runs into them while moving instead
+fn is_enemy_at(
+    tile: &(i32, i32),
+    owner: usize,
+    vision: &Vision,
+    unit_registry: &UnitRegistry,
+    units: &Query<&Unit>,
+) -> bool {
+    visible_unit_at(tile, owner, vision, unit_registry, units)
+        .and_then(|entity| units.get(entity).ok())
+        .is_some_and(|other| other.owner != owner)
+}
+
+fn is_in_zone_of_control(
+    tile: &(i32, i32),
+    owner: usize,
+    vision: &Vision,
+    unit_registry: &UnitRegistry,
+    units: &Query<&Unit>,
+) -> bool {
+    neighbours(tile)
+        .iter()
+        .any(|neighbour| is_enemy_at(neighbour, owner, vision, unit_registry, units))
+}
+
 fn distance_cost_from_to(
     from: &(i32, i32),
     to: &(i32, i32),
     map_layout: &Res<MapLayout>,
     unit_registry: &Res<UnitRegistry>,
     unit: &Unit,
+    units: &Query<&Unit>,
+    vision: &Vision,
 ) -> Option<u32> {
-    if unit_registry.units.contains_key(&to) {
+    if map_layout.blocked.contains(to) {
         return None;
     }
-
-    if map_layout.tiles.get(from) == map_layout.tiles.get(to) {
-        Some(1)
-    } else {
-        if unit.is_air {
-            Some(3)
-        } else {
-            None
+    if unit.is_air {
+        // flying units pass over other units and gaps
+        if !map_layout.is_within_bounds(to) {
+            return None;
         }
+    } else if is_enemy_at(to, unit.owner, vision, unit_registry, units) {
+        return None;
+    }
+
+    match (map_layout.tiles.get(from), map_layout.tiles.get(to)) {
+        (Some(from), Some(to)) if from == to => Some(1),
+        (Some(_), Some(_)) if unit.is_air => Some(3),
+        _ if unit.is_air => Some(1),
+        _ => None,
     }
 }
 
-fn to_path(
-    paths: HashMap<(i32, i32), (i32, i32)>,
-    from: (i32, i32),
-    to: (i32, i32),
-) -> Option<Vec<(i32, i32)>> {
+pub fn to_path(paths: UnitPaths, from: (i32, i32), to: (i32, i32)) -> Option<Vec<(i32, i32)>> {
     let mut path_reversed = vec![to];
     let mut current = to;
     while current != from {
@@ -0,0 +1,257 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    assets::types::{
+        TiledLayer, TiledLayerKind, TiledMap, TiledObject, TiledObjectLayer, TiledProperties,
+        TiledPropertyValue, TiledTilesetRef,
+    },
+    util::rng::Rng,
+};
+
+use super::{map::editor_position, map_objects::object_position};
+
+// game tiles per side, odd so the river runs through the middle column
+const SIZE: i32 = 11;
+const RIVER_X: i32 = SIZE / 2;
+// tile layers, every layer is one height level
+const LAYERS: u32 = 3;
+const TILE_WIDTH: u32 = 232;
+const TILE_HEIGHT: u32 = 110;
+const SPAWN: (i32, i32) = (1, 1);
+const SPAWN_RADIUS: i64 = 1;
+// tries before falling back to a map without obstacles
+const ATTEMPTS: u32 = 20;
+
+// generated maps are stored next to the handmade ones
+const GENERATED_MAP_DIRECTORY: &str = "tilemap";
+const TILESET_SOURCE: &str = "../tileset/prototype/Map/map_tiles.tsj";
+
+// Sketch town tile ids, the four rotations of a tile follow each other (N, E, S, W)
+const GRASS: u32 = 112;
+const TREE: u32 = 336;
+const TREES: u32 = 332;
+const PATH_ALONG_X: u32 = 120;
+const PATH_ALONG_Y: u32 = 123;
+const PATH_CROSSING: u32 = 132;
+const RIVER_ALONG_Y: u32 = 153;
+const BRIDGE_ALONG_X: u32 = 161;
+const BUILDINGS: [u32; 4] = [4, 20, 28, 44];
+const ROOFS: [u32; 4] = [240, 256, 272, 304];
+
+// Game tile => tile id per layer, bottom first
+type Columns = HashMap<(i32, i32), Vec<u32>>;
+
+// Both players get the same map, rotated by 180 degrees around the center
+fn mirrored(tile: (i32, i32)) -> (i32, i32) {
+    (SIZE - 1 - tile.0, SIZE - 1 - tile.1)
+}
+
+// facing of the tile after rotating the map
+fn rotated(id: u32) -> u32 {
+    id - id % 4 + (id % 4 + 2) % 4
+}
+
+pub fn generate_map(seed: u64) -> TiledMap {
+    let mut rng = Rng(seed);
+    let (columns, roads) = (0..ATTEMPTS)
+        .map(|_| generate_columns(&mut rng, true))
+        .find(|(columns, _)| is_connected(columns))
+        .unwrap_or_else(|| generate_columns(&mut rng, false));
+
+    let mut tilemap = TiledMap::new(
+        (SIZE + 1) as u32,
+        (SIZE + 1) as u32,
+        TILE_WIDTH,
+        TILE_HEIGHT,
+    );
+    tilemap.layers = (0..LAYERS)
+        .map(|layer| TiledLayerKind::Tiles(tile_layer(&columns, layer)))
+        .collect();
+    tilemap.tilesets = vec![TiledTilesetRef {
+        firstgid: 1,
+        source: Some(TILESET_SOURCE.to_string()),
+        extra: Default::default(),
+        path: Default::default(),
+    }];
+    tilemap
+        .properties
+        .set("seed", TiledPropertyValue::Int(seed as i64));
+    tilemap
+        .layers
+        .push(TiledLayerKind::Objects(object_layer(&columns, &roads)));
+
+    tilemap
+        .resolve(&map_path(seed))
+        .expect("generated maps only use supported features");
+    // fills in the next layer and object ids
+    tilemap.encode();
+    tilemap
+}
+
+pub(super) fn map_path(seed: u64) -> PathBuf {
+    Path::new(GENERATED_MAP_DIRECTORY).join(format!("generated_{seed}.tmj"))
+}
+
+// Writes the generated map into the asset folder, so it can be edited in Tiled
+pub fn export_map(seed: u64) -> Result<PathBuf, String> {
+    let path = Path::new("assets").join(map_path(seed));
+    let json =
+        serde_json::to_string_pretty(&generate_map(seed)).map_err(|error| error.to_string())?;
+    fs::write(&path, json).map_err(|error| error.to_string())?;
+    Ok(path)
+}
+
+// only the half left of the river is generated, the rest is mirrored
+fn set(columns: &mut Columns, tile: (i32, i32), ids: Vec<u32>) {
+    columns.insert(mirrored(tile), ids.iter().map(|id| rotated(*id)).collect());
+    columns.insert(tile, ids);
+}
+
+// Returns the tile columns and the road crossings
+fn generate_columns(rng: &mut Rng, obstacles: bool) -> (Columns, Vec<(i32, i32)>) {
+    let mut columns: Columns = HashMap::new();
+
+    let road_y = rng.range(2, RIVER_X - 1);
+    let road_x = rng.range(2, RIVER_X - 1);
+    for x in 0..RIVER_X {
+        for y in 0..SIZE {
+            let id = match (x == road_x, y == road_y || y == SIZE - 1 - road_y) {
+                (true, true) => PATH_CROSSING,
+                (true, false) => PATH_ALONG_Y,
+                (false, true) => PATH_ALONG_X,
+                _ if rng.chance(8) => rng.pick(&[TREE, TREES]),
+                _ => GRASS,
+            };
+            set(&mut columns, (x, y), vec![id]);
+        }
+    }
+    for y in 0..=RIVER_X {
+        let id = if y == road_y {
+            BRIDGE_ALONG_X
+        } else {
+            RIVER_ALONG_Y
+        };
+        set(&mut columns, (RIVER_X, y), vec![id]);
+    }
+
+    if obstacles {
+        let free = |columns: &Columns, tile: &(i32, i32)| {
+            columns.get(tile).is_some_and(|ids| ids == &[GRASS])
+                && (tile.0 - SPAWN.0).abs() + (tile.1 - SPAWN.1).abs() > SPAWN_RADIUS as i32 + 1
+        };
+
+        // buildings, some of them with a roof on top
+        for _ in 0..rng.range(2, 4) {
+            let tile = (rng.range(1, RIVER_X - 1), rng.range(1, SIZE - 2));
+            if !free(&columns, &tile) {
+                continue;
+            }
+            let mut ids = vec![GRASS, rng.pick(&BUILDINGS) + rng.range(0, 3) as u32];
+            if rng.chance(50) {
+                ids.push(rng.pick(&ROOFS) + rng.range(0, 3) as u32);
+            }
+            set(&mut columns, tile, ids);
+        }
+
+        // raised plateaus, only flying units get up there
+        for _ in 0..rng.range(1, 2) {
+            let corner = (rng.range(1, RIVER_X - 2), rng.range(1, SIZE - 3));
+            let plateau =
+                [(0, 0), (1, 0), (0, 1), (1, 1)].map(|(dx, dy)| (corner.0 + dx, corner.1 + dy));
+            if plateau.iter().all(|tile| free(&columns, tile)) {
+                for tile in plateau {
+                    set(&mut columns, tile, vec![GRASS, GRASS]);
+                }
+            }
+        }
+    }
+
+    let crossings = vec![(road_x, road_y), mirrored((road_x, road_y))];
+    (columns, crossings)
+}
+
+// Ground units can't change height, so both spawns need to share the ground level.
+// The river can only be crossed on a bridge
+fn is_connected(columns: &Columns) -> bool {
+    let is_river = |id: u32| id - id % 4 == RIVER_ALONG_Y - RIVER_ALONG_Y % 4;
+    let ground = |tile: &(i32, i32)| {
+        columns
+            .get(tile)
+            .is_some_and(|ids| ids.len() == 1 && !is_river(ids[0]))
+    };
+    let mut open = vec![SPAWN];
+    let mut reached = vec![SPAWN];
+    while let Some((x, y)) = open.pop() {
+        for neighbour in [(x + 1, y), (x - 1, y), (x, y + 1), (x, y - 1)] {
+            if ground(&neighbour) && !reached.contains(&neighbour) {
+                reached.push(neighbour);
+                open.push(neighbour);
+            }
+        }
+    }
+    reached.contains(&mirrored(SPAWN))
+}
+
+fn tile_layer(columns: &Columns, layer: u32) -> TiledLayer {
+    let size = (SIZE + 1) as u32;
+    let mut data = vec![0; (size * size) as usize];
+    for (tile, ids) in columns.iter() {
+        let Some(id) = ids.get(layer as usize) else {
+            continue;
+        };
+        let (editor_x, editor_y) = editor_position(tile.0, tile.1, layer);
+        if (0..size as i32).contains(&editor_x) && (0..size as i32).contains(&editor_y) {
+            data[(editor_x + editor_y * size as i32) as usize] = id + 1;
+        }
+    }
+    TiledLayer::new(
+        layer as i32 + 1,
+        &format!("layer{}", layer + 1),
+        size,
+        size,
+        data,
+    )
+}
+
+fn object_layer(columns: &Columns, crossings: &[(i32, i32)]) -> TiledObjectLayer {
+    let mut objects = vec![];
+    let mut add = |class: &str, tile: (i32, i32), properties: &[(&str, i64)]| {
+        let layer = columns[&tile].len() as i64 - 1;
+        let (x, y) = object_position(tile, layer as u32, TILE_HEIGHT);
+        let mut object = TiledObject::point(class, x, y);
+        object.id = objects.len() as u32 + 1;
+        object
+            .properties
+            .set("layer", TiledPropertyValue::Int(layer));
+        for (name, value) in properties {
+            object.properties.set(name, TiledPropertyValue::Int(*value));
+        }
+        objects.push(object);
+    };
+
+    add(
+        "spawn_point",
+        SPAWN,
+        &[("player", 0), ("radius", SPAWN_RADIUS)],
+    );
+    add(
+        "spawn_point",
+        mirrored(SPAWN),
+        &[("player", 1), ("radius", SPAWN_RADIUS)],
+    );
+    for crossing in crossings {
+        add("capture_point", *crossing, &[]);
+    }
+
+    TiledObjectLayer {
+        id: LAYERS as i32 + 1,
+        name: "objects".to_string(),
+        objects,
+        properties: TiledProperties::default(),
+        extra: Default::default(),
+    }
+}
@@ -0,0 +1,429 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+
+use crate::{
+    assets::types::{TiledMap, TiledObject},
+    game_config::GameAssets,
+    AppState,
+};
+
+use super::{
+    isometric::iso_transform,
+    map::{
+        correct_editor_transform, create_map, editor_position, reload_map, MapLayout, MapReloaded,
+    },
+    status_effects::{EffectDuration, EffectKind, StatusEffect},
+    unit::{Unit, UnitRegistry},
+    GameEvent, GameSystemSets,
+};
+
+pub struct MapObjectsPlugin;
+
+impl Plugin for MapObjectsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems((
+            spawn_map_objects
+                .in_schedule(OnEnter(AppState::Game))
+                .after(create_map),
+            despawn_map_objects.in_schedule(OnExit(AppState::Game)),
+            spawn_map_objects
+                .in_schedule(OnEnter(AppState::Editor))
+                .after(create_map),
+            despawn_map_objects.in_schedule(OnExit(AppState::Editor)),
+            reload_map_objects
+                .in_set(GameSystemSets::Update)
+                .after(reload_map),
+            interact_with_objects.in_set(GameSystemSets::Update),
+            update_object_icons.in_set(GameSystemSets::Render),
+        ));
+    }
+}
+
+// Built from the Tiled object class and its custom properties
+#[derive(Clone, PartialEq, Debug)]
+pub enum MapObjectKind {
+    // marks the deployment zone of a participant
+    SpawnPoint {
+        participant: usize,
+        radius: u32,
+    },
+    // belongs to whoever entered it last
+    CapturePoint {
+        owner: Option<usize>,
+    },
+    // picked up by the first unit entering it
+    Chest {
+        heal: u32,
+        effect: Option<StatusEffect>,
+    },
+    // closed doors block the tile
+    Door {
+        open: bool,
+    },
+    // hurts ground units entering it
+    Trap {
+        damage: u32,
+        effect: Option<StatusEffect>,
+        once: bool,
+    },
+    // opens or closes the door with the target object id
+    Trigger {
+        target: Option<u32>,
+        once: bool,
+    },
+}
+
+impl MapObjectKind {
+    pub(super) fn from_object(object: &TiledObject) -> Option<Self> {
+        let effect = object
+            .properties
+            .string("effect")
+            .and_then(EffectKind::from_name)
+            .map(|kind| {
+                // rounds last the same for every unit, no matter when it takes its turn
+                let duration = match object.properties.int("rounds") {
+                    Some(rounds) => EffectDuration::Rounds(rounds as u32),
+                    None => {
+                        EffectDuration::Turns(object.properties.int("turns").unwrap_or(2) as u32)
+                    }
+                };
+                let strength = object.properties.int("strength").unwrap_or(1) as u32;
+                StatusEffect::new(kind, duration, strength)
+            });
+        let once = object.properties.bool("once").unwrap_or(false);
+
+        Some(match object.class() {
+            "spawn_point" => MapObjectKind::SpawnPoint {
+                participant: object.properties.int("player")? as usize,
+                radius: object.properties.int("radius").unwrap_or(1) as u32,
+            },
+            "capture_point" => MapObjectKind::CapturePoint { owner: None },
+            "chest" => MapObjectKind::Chest {
+                heal: object.properties.int("heal").unwrap_or(0) as u32,
+                effect,
+            },
+            "door" => MapObjectKind::Door {
+                open: object.properties.bool("open").unwrap_or(false),
+            },
+            "trap" => MapObjectKind::Trap {
+                damage: object.properties.int("damage").unwrap_or(2) as u32,
+                effect,
+                once,
+            },
+            "trigger" => MapObjectKind::Trigger {
+                target: object.properties.int("target").map(|target| target as u32),
+                once,
+            },
+            _ => return None,
+        })
+    }
+
+    fn icon(&self) -> (&'static str, Color) {
+        match self {
+            MapObjectKind::SpawnPoint { .. } => ("⌂", Color::rgb(0.7, 1.0, 0.7)),
+            MapObjectKind::CapturePoint { owner } => ("⚑", participant_color(*owner)),
+            MapObjectKind::Chest { .. } => ("▣", Color::rgb(1.0, 0.8, 0.3)),
+            MapObjectKind::Door { open: false } => ("▥", Color::rgb(0.6, 0.4, 0.2)),
+            MapObjectKind::Door { open: true } => ("☐", Color::rgb(0.6, 0.4, 0.2)),
+            MapObjectKind::Trap { .. } => ("⚠", Color::rgb(1.0, 0.3, 0.3)),
+            MapObjectKind::Trigger { .. } => ("◎", Color::rgb(0.8, 0.8, 0.8)),
+        }
+    }
+}
+
+pub(super) fn participant_color(participant: Option<usize>) -> Color {
+    match participant {
+        Some(0) => Color::rgb(0.4, 0.7, 1.0),
+        Some(_) => Color::rgb(1.0, 0.4, 0.4),
+        None => Color::WHITE,
+    }
+}
+
+#[derive(Component)]
+pub struct MapObject {
+    // Tiled object id, referenced by triggers
+    pub id: u32,
+    pub tile: (i32, i32),
+    pub kind: MapObjectKind,
+}
+
+// Objects sit on the highest tile at their editor position, unless they set a layer property
+pub(super) fn object_tile(object: &TiledObject, tilemap: &TiledMap) -> (i32, i32) {
+    let tile_size = tilemap.tileheight as f32;
+    let (editor_x, editor_y) = (
+        (object.x / tile_size).floor() as i32,
+        (object.y / tile_size).floor() as i32,
+    );
+
+    let layer = object
+        .properties
+        .int("layer")
+        .map(|layer| layer as u32)
+        .or_else(|| {
+            tilemap
+                .tile_layers()
+                .enumerate()
+                .filter(|(_, layer)| layer.gid_at(&(editor_x, editor_y)).is_some())
+                .map(|(layer_id, _)| layer_id as u32)
+                .last()
+        })
+        .unwrap_or(0);
+    correct_editor_transform(editor_x, editor_y, layer)
+}
+
+// Tiled position of an object in the middle of the tile
+pub(super) fn object_position(tile: (i32, i32), layer: u32, tile_size: u32) -> (f32, f32) {
+    let (editor_x, editor_y) = editor_position(tile.0, tile.1, layer);
+    (
+        (editor_x as f32 + 0.5) * tile_size as f32,
+        (editor_y as f32 + 0.5) * tile_size as f32,
+    )
+}
+
+// Tiles within radius moves of a spawn point
+pub(super) fn spawn_zone(
+    tile: (i32, i32),
+    radius: u32,
+    tiles: &HashMap<(i32, i32), u32>,
+) -> Vec<(i32, i32)> {
+    let radius = radius as i32;
+    (-radius..=radius)
+        .flat_map(|dx| {
+            let remaining = radius - dx.abs();
+            (-remaining..=remaining).map(move |dy| (tile.0 + dx, tile.1 + dy))
+        })
+        .filter(|tile| tiles.contains_key(tile))
+        .collect()
+}
+
+fn spawn_map_objects(
+    mut commands: Commands,
+    game_assets: Res<GameAssets>,
+    tilemaps: Res<Assets<TiledMap>>,
+    mut map_layout: ResMut<MapLayout>,
+) {
+    let tilemap = tilemaps.get(&game_assets.map).unwrap();
+    spawn_objects(&mut commands, &game_assets, tilemap, &mut map_layout);
+}
+
+pub(super) fn spawn_objects(
+    commands: &mut Commands,
+    game_assets: &GameAssets,
+    tilemap: &TiledMap,
+    map_layout: &mut MapLayout,
+) {
+    let (tile_w, tile_h) = (tilemap.tilewidth as f32, tilemap.tileheight as f32);
+    map_layout.deployment_zones.clear();
+
+    for object in tilemap.objects() {
+        let Some(kind) = MapObjectKind::from_object(object) else {
+            warn!(
+                "ignoring map object {} '{}' with unknown class '{}'",
+                object.id,
+                object.name,
+                object.class()
+            );
+            continue;
+        };
+        let tile = object_tile(object, tilemap);
+        let Some(height) = map_layout.tiles.get(&tile).copied() else {
+            warn!("ignoring map object {} placed over a gap", object.id);
+            continue;
+        };
+
+        match kind {
+            MapObjectKind::SpawnPoint {
+                participant,
+                radius,
+            } => {
+                let zone = spawn_zone(tile, radius, &map_layout.tiles);
+                map_layout
+                    .deployment_zones
+                    .entry(participant)
+                    .or_default()
+                    .extend(zone);
+            }
+            MapObjectKind::Door { open: false } => {
+                map_layout.blocked.insert(tile);
+            }
+            _ => {}
+        }
+
+        let (icon, color) = kind.icon();
+        let mut transform = Transform::from_translation(iso_transform(
+            tile.0 as f32,
+            tile.1 as f32,
+            height as f32,
+            tile_w,
+            tile_h,
+            true,
+        ));
+        // units standing on the object are drawn in front of it
+        transform.translation.z -= 0.25;
+        commands.spawn((
+            Text2dBundle {
+                text: Text::from_section(
+                    icon,
+                    TextStyle {
+                        font: game_assets.font.clone(),
+                        font_size: 64.,
+                        color,
+                    },
+                ),
+                transform,
+                ..default()
+            },
+            MapObject {
+                id: object.id,
+                tile,
+                kind,
+            },
+        ));
+    }
+}
+
+fn despawn_map_objects(mut commands: Commands, objects: Query<Entity, With<MapObject>>) {
+    for object in objects.iter() {
+        commands.entity(object).despawn_recursive();
+    }
+}
+
+// Objects start over in their initial state, e.g. captured points are neutral again
+fn reload_map_objects(
+    mut commands: Commands,
+    mut reloaded: EventReader<MapReloaded>,
+    game_assets: Res<GameAssets>,
+    tilemaps: Res<Assets<TiledMap>>,
+    mut map_layout: ResMut<MapLayout>,
+    objects: Query<Entity, With<MapObject>>,
+) {
+    if reloaded.iter().count() == 0 {
+        return;
+    }
+    let Some(tilemap) = tilemaps.get(&game_assets.map) else {
+        return;
+    };
+
+    for object in objects.iter() {
+        commands.entity(object).despawn_recursive();
+    }
+    spawn_objects(&mut commands, &game_assets, tilemap, &mut map_layout);
+}
+
+// tile and whether the unit was moving
+type LastTile = ((i32, i32), bool);
+
+// Objects react to units entering their tile, whether walking, flying or being knocked back.
+// Traps only go off where a unit stops
+fn interact_with_objects(
+    mut commands: Commands,
+    mut last_tiles: Local<HashMap<Entity, LastTile>>,
+    mut units: Query<(Entity, &mut Unit)>,
+    mut objects: Query<(Entity, &mut MapObject)>,
+    unit_registry: Res<UnitRegistry>,
+    mut map_layout: ResMut<MapLayout>,
+    mut event_writer: EventWriter<GameEvent>,
+) {
+    let mut entered = vec![];
+    for (entity, unit) in units.iter() {
+        let (tile, moving) = (unit.tile(), unit.is_moving());
+        let Some((last_tile, was_moving)) = last_tiles.insert(entity, (tile, moving)) else {
+            continue;
+        };
+        let new_tile = last_tile != tile;
+        let stopped = !moving && (new_tile || was_moving);
+        if (new_tile || stopped) && unit.health > 0 {
+            entered.push((entity, tile, new_tile, stopped));
+        }
+    }
+    last_tiles.retain(|entity, _| units.contains(*entity));
+
+    for (entity, tile, new_tile, stopped) in entered {
+        let Ok((_, mut unit)) = units.get_mut(entity) else {
+            continue;
+        };
+        let mut toggled_doors = vec![];
+
+        for (object_entity, mut object) in objects.iter_mut() {
+            let reached = match object.kind {
+                MapObjectKind::Trap { .. } => stopped,
+                _ => new_tile,
+            };
+            if object.tile != tile || !reached {
+                continue;
+            }
+            let remove = match &mut object.kind {
+                MapObjectKind::CapturePoint { owner } => {
+                    if *owner != Some(unit.owner) {
+                        *owner = Some(unit.owner);
+                    }
+                    false
+                }
+                MapObjectKind::Chest { heal, effect } => {
+                    unit.health = (unit.health + *heal).min(unit.max_health);
+                    if let Some(effect) = effect {
+                        event_writer.send(GameEvent::ApplyEffect(entity, *effect));
+                    }
+                    true
+                }
+                MapObjectKind::Trap {
+                    damage,
+                    effect,
+                    once,
+                } => {
+                    // flying units don't set off traps
+                    if unit.is_air {
+                        continue;
+                    }
+                    event_writer.send(GameEvent::Damage(entity, *damage));
+                    if let Some(effect) = effect {
+                        event_writer.send(GameEvent::ApplyEffect(entity, *effect));
+                    }
+                    *once
+                }
+                MapObjectKind::Trigger { target, once } => {
+                    toggled_doors.extend(*target);
+                    *once
+                }
+                MapObjectKind::SpawnPoint { .. } | MapObjectKind::Door { .. } => false,
+            };
+            if remove {
+                commands.entity(object_entity).despawn_recursive();
+            }
+        }
+
+        for (_, mut object) in objects.iter_mut() {
+            if !toggled_doors.contains(&object.id) {
+                continue;
+            }
+            let tile = object.tile;
+            let MapObjectKind::Door { open } = &mut object.kind else {
+                continue;
+            };
+            // doors can't close on a unit, or in the way of a moving one
+            if *open
+                && (unit_registry.units.contains_key(&tile)
+                    || units
+                        .iter()
+                        .any(|(_, other)| other.tiles_ahead().contains(&tile)))
+            {
+                continue;
+            }
+            *open = !*open;
+            if *open {
+                map_layout.blocked.remove(&tile);
+            } else {
+                map_layout.blocked.insert(tile);
+            }
+        }
+    }
+}
+
+fn update_object_icons(mut objects: Query<(&MapObject, &mut Text), Changed<MapObject>>) {
+    for (object, mut text) in objects.iter_mut() {
+        let (icon, color) = object.kind.icon();
+        text.sections[0].value = icon.to_string();
+        text.sections[0].style.color = color;
+    }
+}
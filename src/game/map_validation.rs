@@ -0,0 +1,415 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt, fs,
+    path::Path,
+};
+
+use crate::assets::types::{join_asset_path, TiledMap, TiledSet};
+
+use super::{
+    map::{correct_editor_transform, impassable_tiles, neighbours},
+    map_objects::{object_tile, spawn_zone, MapObjectKind},
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MapError {
+    // the file itself can't be read, only reported by validate_map_file
+    Load(String),
+    LayerSize {
+        layer: String,
+        expected: u32,
+        found: usize,
+    },
+    UnknownTile {
+        layer: String,
+        position: (i32, i32),
+        gid: u32,
+    },
+    NoTiles,
+    NotEnoughDeploymentSpots {
+        participant: usize,
+        needed: u32,
+        found: usize,
+    },
+    // ground units of the two participants can never meet
+    Unreachable {
+        participant: usize,
+        other: usize,
+    },
+}
+
+impl fmt::Display for MapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MapError::Load(reason) => write!(f, "{reason}"),
+            MapError::LayerSize {
+                layer,
+                expected,
+                found,
+            } => write!(
+                f,
+                "layer '{layer}' should have {expected} tiles of data, but has {found}"
+            ),
+            MapError::UnknownTile {
+                layer,
+                position,
+                gid,
+            } => write!(
+                f,
+                "layer '{layer}' uses tile {gid} at {position:?}, which no tileset contains"
+            ),
+            MapError::NoTiles => write!(f, "the map has no tiles"),
+            MapError::NotEnoughDeploymentSpots {
+                participant,
+                needed,
+                found,
+            } => write!(
+                f,
+                "player {participant} needs {needed} deployment spots, but only has {found}"
+            ),
+            MapError::Unreachable { participant, other } => write!(
+                f,
+                "ground units of player {participant} can't reach player {other}"
+            ),
+        }
+    }
+}
+
+// Tilesets with their first gid
+pub fn validate_map(
+    tilemap: &TiledMap,
+    tilesets: &[(u32, &TiledSet)],
+    participants: usize,
+    units_per_participant: u32,
+) -> Vec<MapError> {
+    let mut errors = vec![];
+
+    let impassable_gids = impassable_tiles(tilesets);
+    let mut tiles = HashMap::new();
+    // positions whose highest tile can't be entered
+    let mut impassable = HashSet::new();
+    for (layer_id, layer) in tilemap.tile_layers().enumerate() {
+        for (expected, found) in &layer.data_lengths {
+            if *expected as usize != *found {
+                errors.push(MapError::LayerSize {
+                    layer: layer.name.clone(),
+                    expected: *expected,
+                    found: *found,
+                });
+            }
+        }
+
+        for (position, gid) in layer.tiles() {
+            if !has_tile(tilesets, gid.id()) {
+                errors.push(MapError::UnknownTile {
+                    layer: layer.name.clone(),
+                    position,
+                    gid: gid.id(),
+                });
+                continue;
+            }
+            // same as create_map
+            if layer.visible {
+                let tile = correct_editor_transform(position.0, position.1, layer_id as u32);
+                tiles.insert(tile, layer_id as u32);
+                if impassable_gids.contains(&gid.id()) {
+                    impassable.insert(tile);
+                } else {
+                    impassable.remove(&tile);
+                }
+            }
+        }
+    }
+    if tiles.is_empty() {
+        errors.push(MapError::NoTiles);
+        return errors;
+    }
+
+    let mut zones: HashMap<usize, HashSet<(i32, i32)>> = HashMap::new();
+    let mut closed_doors = HashSet::new();
+    for object in tilemap.objects() {
+        let tile = object_tile(object, tilemap);
+        if !tiles.contains_key(&tile) {
+            continue;
+        }
+        match MapObjectKind::from_object(object) {
+            Some(MapObjectKind::SpawnPoint {
+                participant,
+                radius,
+            }) => zones
+                .entry(participant)
+                .or_default()
+                .extend(spawn_zone(tile, radius, &tiles)),
+            Some(MapObjectKind::Door { open: false }) => {
+                closed_doors.insert(tile);
+            }
+            _ => {}
+        }
+    }
+
+    // without a zone a participant may deploy anywhere
+    let deployment_spots: Vec<HashSet<(i32, i32)>> = (0..participants)
+        .map(|participant| {
+            zones
+                .get(&participant)
+                .unwrap_or(&tiles.keys().copied().collect())
+                .difference(&closed_doors)
+                .filter(|spot| !impassable.contains(spot))
+                .copied()
+                .collect()
+        })
+        .collect();
+    for (participant, spots) in deployment_spots.iter().enumerate() {
+        if spots.len() < units_per_participant as usize {
+            errors.push(MapError::NotEnoughDeploymentSpots {
+                participant,
+                needed: units_per_participant,
+                found: spots.len(),
+            });
+        }
+    }
+
+    // doors may be opened, so they don't split the map
+    let walkable: HashMap<(i32, i32), u32> = tiles
+        .iter()
+        .filter(|(tile, _)| !impassable.contains(tile))
+        .map(|(tile, height)| (*tile, *height))
+        .collect();
+    let regions = ground_regions(&walkable);
+    for participant in 0..participants {
+        for other in participant + 1..participants {
+            let reachable = deployment_spots[participant].iter().any(|spot| {
+                deployment_spots[other]
+                    .iter()
+                    .any(|other_spot| regions[spot] == regions[other_spot])
+            });
+            if !reachable {
+                errors.push(MapError::Unreachable { participant, other });
+            }
+        }
+    }
+
+    errors
+}
+
+fn has_tile(tilesets: &[(u32, &TiledSet)], gid: u32) -> bool {
+    let Some((firstgid, tileset)) = tilesets
+        .iter()
+        .filter(|(firstgid, _)| *firstgid <= gid)
+        .max_by_key(|(firstgid, _)| *firstgid)
+    else {
+        return false;
+    };
+    let id = gid - firstgid;
+    if tileset.image_path.is_some() {
+        id < tileset.tilecount
+    } else {
+        tileset.tiles.iter().any(|tile| tile.id == id)
+    }
+}
+
+// Ground units only walk between neighbouring tiles of the same height
+fn ground_regions(tiles: &HashMap<(i32, i32), u32>) -> HashMap<(i32, i32), usize> {
+    let mut regions = HashMap::new();
+    for (region, start) in tiles.keys().enumerate() {
+        if regions.contains_key(start) {
+            continue;
+        }
+        let mut open = vec![*start];
+        regions.insert(*start, region);
+        while let Some(tile) = open.pop() {
+            for neighbour in neighbours(&tile) {
+                if tiles.get(&neighbour) == tiles.get(&tile) && !regions.contains_key(&neighbour) {
+                    regions.insert(neighbour, region);
+                    open.push(neighbour);
+                }
+            }
+        }
+    }
+    regions
+}
+
+// Validates a map inside the asset folder without starting the game
+pub fn validate_map_file(
+    path: &str,
+    participants: usize,
+    units_per_participant: u32,
+) -> Vec<MapError> {
+    let assets = Path::new("assets");
+    let read = |path: &Path| {
+        fs::read(assets.join(path))
+            .map_err(|error| MapError::Load(format!("can't read {}: {error}", path.display())))
+    };
+    let parse_error = |path: &Path, error: &dyn fmt::Display| {
+        MapError::Load(format!("{}: {error}", path.display()))
+    };
+
+    let map_path = join_asset_path(Path::new(""), path);
+    let tilemap = read(&map_path).and_then(|bytes| {
+        let mut tilemap: TiledMap =
+            serde_json::from_slice(&bytes).map_err(|error| parse_error(&map_path, &error))?;
+        tilemap
+            .resolve(&map_path)
+            .map_err(|error| parse_error(&map_path, &error))?;
+        Ok(tilemap)
+    });
+    let tilemap = match tilemap {
+        Ok(tilemap) => tilemap,
+        Err(error) => return vec![error],
+    };
+
+    let mut tilesets = vec![];
+    for tileset_ref in &tilemap.tilesets {
+        let tileset = read(&tileset_ref.path).and_then(|bytes| {
+            let mut tileset: TiledSet = serde_json::from_slice(&bytes)
+                .map_err(|error| parse_error(&tileset_ref.path, &error))?;
+            tileset
+                .resolve(&tileset_ref.path)
+                .map_err(|error| parse_error(&tileset_ref.path, &error))?;
+            Ok(tileset)
+        });
+        match tileset {
+            Ok(tileset) => tilesets.push((tileset_ref.firstgid, tileset)),
+            Err(error) => return vec![error],
+        }
+    }
+
+    let tilesets: Vec<(u32, &TiledSet)> = tilesets
+        .iter()
+        .map(|(firstgid, tileset)| (*firstgid, tileset))
+        .collect();
+    validate_map(&tilemap, &tilesets, participants, units_per_participant)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::assets::types::{TiledLayer, TiledLayerKind, TiledObject, TiledPropertyValue};
+
+    use super::*;
+
+    const TILE_SIZE: u32 = 32;
+    const GROUND: u32 = 1;
+    // impassable, like the river
+    const WATER: u32 = 2;
+
+    // two tile atlas, the second tile is impassable
+    fn tileset() -> TiledSet {
+        let mut tileset: TiledSet = serde_json::from_value(json!({
+            "name": "test",
+            "tilecount": 2,
+            "tilewidth": 64,
+            "tileheight": 32,
+            "image": "tiles.png",
+            "columns": 2,
+            "tiles": [{
+                "id": 1,
+                "properties": [{ "name": "impassable", "type": "bool", "value": true }],
+            }],
+        }))
+        .unwrap();
+        tileset.resolve(Path::new("tiles.tsj")).unwrap();
+        tileset
+    }
+
+    // one layer per list of rows
+    fn map(layers: &[Vec<Vec<u32>>]) -> TiledMap {
+        let (height, width) = (layers[0].len() as u32, layers[0][0].len() as u32);
+        let mut tilemap = TiledMap::new(width, height, TILE_SIZE * 2, TILE_SIZE);
+        for (id, rows) in layers.iter().enumerate() {
+            let data = rows.concat();
+            let layer = TiledLayer::new(id as i32 + 1, &format!("layer {id}"), width, height, data);
+            tilemap.layers.push(TiledLayerKind::Tiles(layer));
+        }
+        tilemap.resolve(Path::new("test.tmj")).unwrap();
+        tilemap
+    }
+
+    // spawn point on an editor position of the first layer
+    fn add_spawn_point(tilemap: &mut TiledMap, participant: i64, position: (i32, i32)) {
+        let center = |coordinate: i32| (coordinate as f32 + 0.5) * TILE_SIZE as f32;
+        let mut object = TiledObject::point("spawn_point", center(position.0), center(position.1));
+        object
+            .properties
+            .set("player", TiledPropertyValue::Int(participant));
+        object.properties.set("radius", TiledPropertyValue::Int(0));
+        tilemap.add_object(object);
+    }
+
+    fn validate(tilemap: &TiledMap, units_per_participant: u32) -> Vec<MapError> {
+        let tileset = tileset();
+        validate_map(tilemap, &[(1, &tileset)], 2, units_per_participant)
+    }
+
+    #[test]
+    fn open_maps_are_valid() {
+        let tilemap = map(&[vec![vec![GROUND; 3]; 3]]);
+        assert_eq!(validate(&tilemap, 2), vec![]);
+    }
+
+    #[test]
+    fn layers_with_missing_data_are_errors() {
+        let mut tilemap = map(&[vec![vec![GROUND; 3]; 3]]);
+        let layer = TiledLayer::new(2, "short", 3, 3, vec![GROUND; 8]);
+        tilemap.layers.push(TiledLayerKind::Tiles(layer));
+        tilemap.resolve(Path::new("test.tmj")).unwrap();
+        assert_eq!(
+            validate(&tilemap, 1),
+            vec![MapError::LayerSize {
+                layer: "short".to_string(),
+                expected: 9,
+                found: 8,
+            }]
+        );
+    }
+
+    #[test]
+    fn tiles_outside_the_tilesets_are_errors() {
+        let tilemap = map(&[vec![vec![GROUND, GROUND], vec![GROUND, 7]]]);
+        assert_eq!(
+            validate(&tilemap, 1),
+            vec![MapError::UnknownTile {
+                layer: "layer 0".to_string(),
+                position: (1, 1),
+                gid: 7,
+            }]
+        );
+    }
+
+    #[test]
+    fn spawn_zones_need_a_spot_for_every_unit() {
+        let mut tilemap = map(&[vec![vec![GROUND, WATER, GROUND]]]);
+        add_spawn_point(&mut tilemap, 0, (0, 0));
+        add_spawn_point(&mut tilemap, 1, (1, 0));
+        let errors = validate(&tilemap, 1);
+        assert_eq!(
+            errors[0],
+            MapError::NotEnoughDeploymentSpots {
+                participant: 1,
+                needed: 1,
+                found: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn impassable_tiles_split_the_map() {
+        let mut tilemap = map(&[vec![vec![GROUND, WATER, GROUND]; 2]]);
+        add_spawn_point(&mut tilemap, 0, (0, 0));
+        add_spawn_point(&mut tilemap, 1, (2, 1));
+        assert_eq!(
+            validate(&tilemap, 1),
+            vec![MapError::Unreachable {
+                participant: 0,
+                other: 1,
+            }]
+        );
+
+        // a bridge connects both sides
+        let mut tilemap = map(&[vec![vec![GROUND, WATER, GROUND], vec![GROUND; 3]]]);
+        add_spawn_point(&mut tilemap, 0, (0, 0));
+        add_spawn_point(&mut tilemap, 1, (2, 0));
+        assert_eq!(validate(&tilemap, 1), vec![]);
+    }
+}
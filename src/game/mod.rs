@@ -3,16 +3,43 @@ use bevy::prelude::*;
 use crate::AppState;
 
 use self::{
-    animation::AnimatorPlugin, game_state::GameStatePlugin, map::MapPlugin, picking::PickingPlugin,
-    unit::UnitPlugin,
+    abilities::AbilitiesPlugin,
+    action_bar::ActionBarPlugin,
+    ai::AiPlugin,
+    animation::AnimatorPlugin,
+    combat_feedback::CombatFeedbackPlugin,
+    editor::EditorPlugin,
+    game_state::GameStatePlugin,
+    info_panel::InfoPanelPlugin,
+    input::InputActionsPlugin,
+    isometric::IsometricDirection,
+    map::MapPlugin,
+    map_objects::MapObjectsPlugin,
+    picking::PickingPlugin,
+    status_effects::{StatusEffect, StatusEffectsPlugin},
+    unit::{UnitKind, UnitPlugin},
+    vision::VisionPlugin,
 };
 
+mod abilities;
+mod action_bar;
+mod ai;
 mod animation;
+mod combat;
+mod combat_feedback;
+mod editor;
 pub mod game_state;
-mod isometric;
+mod info_panel;
+mod input;
+pub mod isometric;
 pub mod map;
+pub mod map_generator;
+mod map_objects;
+pub mod map_validation;
 pub mod picking;
+mod status_effects;
 mod unit;
+mod vision;
 
 #[derive(SystemSet, Debug, Clone, Copy, Eq, PartialEq, Hash)]
 enum GameSystemSets {
@@ -23,9 +50,20 @@ enum GameSystemSets {
 }
 
 pub enum GameEvent {
-    // x, y, player
-    SpawnUnit(i32, i32, usize),
-    PlaceAiUnit(usize),
+    // x, y, player, kind
+    SpawnUnit(i32, i32, usize, UnitKind),
+    PlaceAiUnit(usize, UnitKind),
+    AiTurn(Entity),
+    TurnStarted(Entity),
+    // attacker, target
+    Attack(Entity, Entity),
+    // damage that ignores defense and shields
+    Damage(Entity, u32),
+    ApplyEffect(Entity, StatusEffect),
+    // unit, direction, tiles. Pulling knocks the unit back towards the puller
+    Knockback(Entity, IsometricDirection, u32),
+    // caster, ability slot, target tile
+    UseAbility(Entity, usize, (i32, i32)),
 }
 
 pub struct GamePlugin;
@@ -54,6 +92,16 @@ impl Plugin for GamePlugin {
         app.add_plugin(MapPlugin);
         app.add_plugin(GameStatePlugin);
         app.add_plugin(UnitPlugin);
+        app.add_plugin(CombatFeedbackPlugin);
+        app.add_plugin(InputActionsPlugin);
         app.add_plugin(PickingPlugin);
+        app.add_plugin(ActionBarPlugin);
+        app.add_plugin(InfoPanelPlugin);
+        app.add_plugin(VisionPlugin);
+        app.add_plugin(AiPlugin);
+        app.add_plugin(StatusEffectsPlugin);
+        app.add_plugin(AbilitiesPlugin);
+        app.add_plugin(MapObjectsPlugin);
+        app.add_plugin(EditorPlugin);
     }
 }
@@ -1,21 +1,199 @@
+use std::{collections::HashMap, f32::consts::TAU};
+
 use bevy::{
+    ecs::system::SystemParam,
     prelude::{
-        Camera, Commands, Component, Entity, GlobalTransform, IntoSystemConfig, Plugin, Query,
-        ResMut, Resource, Vec2, With,
+        Assets, Camera, Changed, Component, Entity, GlobalTransform, Handle, Image,
+        IntoSystemConfigs, OnUpdate, Plugin, Query, Rect, RemovedComponents, Res, ResMut, Resource,
+        Sprite, TextureAtlas, TextureAtlasSprite, Vec2, Visibility, With, Without,
     },
-    render::camera::RenderTarget,
+    render::{camera::RenderTarget, render_resource::TextureFormat},
+    ui::Interaction,
     window::{PrimaryWindow, Window},
 };
 
-use crate::util::collisions::Triangle;
+use crate::{
+    assets::types::{TiledMap, TiledPoint, TiledSet, TiledShape},
+    game_config::GameAssets,
+    util::collisions::Triangle,
+    AppState,
+};
+
+use super::{
+    input::{cycle_units, GridCursor},
+    map::Tile,
+    unit::{Unit, UnitRegistry},
+    GameSystemSets,
+};
+
+// pixels more transparent than this can't be picked
+const ALPHA_THRESHOLD: u8 = 25;
+// corners of the polygon ellipse collision shapes are turned into
+const ELLIPSE_CORNERS: usize = 16;
 
-use super::GameSystemSets;
+pub const TILE_PRIORITY: i32 = 0;
+pub const UNIT_PRIORITY: i32 = 1;
 
 // Components
 
-#[derive(Component, Clone)]
+#[derive(Component, Clone, Default)]
 pub struct Pickable {
+    // relative to the sprite's center, without any the whole sprite can be picked
     pub triangles: Vec<Triangle>,
+    // transparent pixels of the sprite can't be picked
+    pub alpha_mask: bool,
+    // wins over anything with a lower priority, even if that is drawn in front
+    pub priority: i32,
+}
+
+impl Pickable {
+    // Silhouette of an isometric block one layer high, standing on the bottom of the image
+    pub fn block(tile_width: f32, tile_height: f32, image_height: f32) -> Self {
+        let (half_width, half_height) = (tile_width / 2., tile_height / 2.);
+        // flat tiles have no sides
+        let depth = (image_height - tile_height).clamp(0., tile_height);
+        let bottom = -image_height / 2.;
+        let lower = bottom + half_height;
+        let upper = lower + depth;
+        let top = upper + half_height;
+
+        Pickable {
+            triangles: vec![
+                Triangle::new(
+                    Vec2::new(-half_width, lower),
+                    Vec2::new(half_width, lower),
+                    Vec2::new(0., bottom),
+                ),
+                Triangle::new(
+                    Vec2::new(-half_width, lower),
+                    Vec2::new(half_width, lower),
+                    Vec2::new(half_width, upper),
+                ),
+                Triangle::new(
+                    Vec2::new(-half_width, lower),
+                    Vec2::new(half_width, upper),
+                    Vec2::new(-half_width, upper),
+                ),
+                Triangle::new(
+                    Vec2::new(-half_width, upper),
+                    Vec2::new(half_width, upper),
+                    Vec2::new(0., top),
+                ),
+            ],
+            alpha_mask: false,
+            priority: TILE_PRIORITY,
+        }
+    }
+
+    // Collision shapes from Tiled, which measures from the top left corner of the image
+    pub fn from_shapes(shapes: &[TiledShape], image_size: Vec2) -> Self {
+        let to_local = |x: f32, y: f32| Vec2::new(x - image_size.x / 2., image_size.y / 2. - y);
+
+        let mut triangles = vec![];
+        for shape in shapes {
+            let corners: Vec<Vec2> = match &shape.polygon {
+                Some(points) => points
+                    .iter()
+                    .map(|TiledPoint { x, y }| to_local(shape.x + x, shape.y + y))
+                    .collect(),
+                None if shape.ellipse => {
+                    let radius = Vec2::new(shape.width, shape.height) / 2.;
+                    (0..ELLIPSE_CORNERS)
+                        .map(|corner| {
+                            let angle = corner as f32 / ELLIPSE_CORNERS as f32 * TAU;
+                            to_local(
+                                shape.x + radius.x * (1. + angle.cos()),
+                                shape.y + radius.y * (1. + angle.sin()),
+                            )
+                        })
+                        .collect()
+                }
+                None => vec![
+                    to_local(shape.x, shape.y),
+                    to_local(shape.x + shape.width, shape.y),
+                    to_local(shape.x + shape.width, shape.y + shape.height),
+                    to_local(shape.x, shape.y + shape.height),
+                ],
+            };
+            // a fan only covers convex polygons, which collision shapes usually are
+            for i in 1..corners.len().saturating_sub(1) {
+                triangles.push(Triangle::new(corners[0], corners[i], corners[i + 1]));
+            }
+        }
+
+        Pickable {
+            triangles,
+            alpha_mask: false,
+            priority: TILE_PRIORITY,
+        }
+    }
+
+    pub fn flipped(&self, flip_x: bool, flip_y: bool) -> Self {
+        let flip = |point: Vec2| {
+            Vec2::new(
+                if flip_x { -point.x } else { point.x },
+                if flip_y { -point.y } else { point.y },
+            )
+        };
+        Pickable {
+            triangles: self
+                .triangles
+                .iter()
+                .map(|triangle| {
+                    Triangle::new(flip(triangle.p1), flip(triangle.p2), flip(triangle.p3))
+                })
+                .collect(),
+            ..self.clone()
+        }
+    }
+
+    fn contains(&self, point: &Vec2) -> bool {
+        self.triangles
+            .iter()
+            .any(|triangle| triangle.contains(point))
+    }
+}
+
+// Pick shapes of every tile by gid. Tiles use their collision shapes, or else the block they show.
+// Tilesets or tiles with the alpha_picking property are picked by their pixels as well
+pub fn tile_pickables(
+    tilemap: &TiledMap,
+    map_tilesets: &[(u32, &TiledSet)],
+) -> HashMap<u32, Pickable> {
+    let (tile_width, tile_height) = (tilemap.tilewidth as f32, tilemap.tileheight as f32);
+
+    let mut pickables = HashMap::new();
+    for (firstgid, tileset) in map_tilesets {
+        let ids: Vec<u32> = if tileset.image_path.is_some() {
+            (0..tileset.tilecount).collect()
+        } else {
+            tileset.tiles.iter().map(|tile| tile.id).collect()
+        };
+        let tileset_alpha = tileset.properties.bool("alpha_picking").unwrap_or(false);
+
+        for id in ids {
+            let tile = tileset.tiles.iter().find(|tile| tile.id == id);
+            let image_size = match tile {
+                Some(tile) if tile.imagewidth > 0 && tile.imageheight > 0 => {
+                    Vec2::new(tile.imagewidth as f32, tile.imageheight as f32)
+                }
+                _ => Vec2::new(tileset.tilewidth as f32, tileset.tileheight as f32),
+            };
+
+            let shapes = tile
+                .and_then(|tile| tile.objectgroup.as_ref())
+                .filter(|group| !group.objects.is_empty());
+            let mut pickable = match shapes {
+                Some(group) => Pickable::from_shapes(&group.objects, image_size),
+                None => Pickable::block(tile_width, tile_height, image_size.y),
+            };
+            pickable.alpha_mask = tile
+                .and_then(|tile| tile.properties.bool("alpha_picking"))
+                .unwrap_or(tileset_alpha);
+            pickables.insert(firstgid + id, pickable);
+        }
+    }
+    pickables
 }
 
 #[derive(Component, Default)]
@@ -25,7 +203,111 @@ pub struct PickCamera;
 
 #[derive(Resource, Default)]
 pub struct PickState {
+    // the tile under the cursor, or the tile of the unit under the cursor
     pub selected: Option<Entity>,
+    pub unit: Option<Entity>,
+}
+
+// Where the selected tile is on the map
+#[derive(SystemParam)]
+pub struct PickedTile<'w, 's> {
+    pick_state: Res<'w, PickState>,
+    tiles: Query<'w, 's, &'static Tile>,
+}
+
+impl<'w, 's> PickedTile<'w, 's> {
+    pub fn position(&self) -> Option<(i32, i32)> {
+        let tile = self.tiles.get(self.pick_state.selected?).ok()?;
+        Some((tile.x, tile.y))
+    }
+}
+
+// Tiles by where they are drawn, so only the few tiles near the cursor have to be tested
+#[derive(Resource, Default)]
+pub struct PickIndex {
+    // isometric grid position of the tile's center => tiles of every layer drawn there
+    cells: HashMap<(i32, i32), Vec<Entity>>,
+    // top tile of every map position
+    top_tiles: HashMap<(i32, i32), (i32, Entity)>,
+    // width and height of a tile's top face
+    tile_size: Vec2,
+    // how far the pick shapes reach from their tile's center
+    reach: Rect,
+    // tiles picked by their whole sprite, tested every time
+    unindexed: Vec<Entity>,
+}
+
+impl PickIndex {
+    pub fn new<'a>(
+        tile_size: Vec2,
+        tiles: impl Iterator<Item = (Entity, &'a Tile, &'a Pickable, &'a GlobalTransform)>,
+    ) -> Self {
+        let mut index = PickIndex {
+            tile_size,
+            ..Default::default()
+        };
+        let mut reach: Option<Rect> = None;
+        for (entity, tile, pickable, transform) in tiles {
+            let top = index
+                .top_tiles
+                .entry((tile.x, tile.y))
+                .or_insert((tile.z, entity));
+            if tile.z >= top.0 {
+                *top = (tile.z, entity);
+            }
+
+            if pickable.triangles.is_empty() {
+                index.unindexed.push(entity);
+                continue;
+            }
+            let center = transform.translation().truncate();
+            for triangle in &pickable.triangles {
+                for point in [triangle.p1, triangle.p2, triangle.p3] {
+                    let offset = transform.transform_point(point.extend(0.)).truncate() - center;
+                    reach = Some(match reach {
+                        Some(reach) => reach.union_point(offset),
+                        None => Rect::from_corners(offset, offset),
+                    });
+                }
+            }
+            let cell = index.cell(center).round();
+            index
+                .cells
+                .entry((cell.x as i32, cell.y as i32))
+                .or_default()
+                .push(entity);
+        }
+        index.reach = reach.unwrap_or_default();
+        index
+    }
+
+    // Inverse of the isometric projection without the height, every layer shares the grid
+    fn cell(&self, position: Vec2) -> Vec2 {
+        Vec2::new(
+            2. * position.x / self.tile_size.x,
+            -2. * position.y / self.tile_size.y,
+        )
+    }
+
+    // Tiles whose pick shape may contain the position
+    pub fn candidates(&self, position: &Vec2) -> impl Iterator<Item = Entity> + '_ {
+        let (a, b) = (
+            self.cell(*position - self.reach.max),
+            self.cell(*position - self.reach.min),
+        );
+        // centers were rounded to the closest cell
+        let (min, max) = (a.min(b).floor(), a.max(b).ceil());
+        (min.x as i32..=max.x as i32)
+            .flat_map(move |u| (min.y as i32..=max.y as i32).map(move |v| (u, v)))
+            .filter_map(|cell| self.cells.get(&cell))
+            .flatten()
+            .chain(self.unindexed.iter())
+            .copied()
+    }
+
+    pub fn top_tile(&self, tile: &(i32, i32)) -> Option<Entity> {
+        self.top_tiles.get(tile).map(|(_, entity)| *entity)
+    }
 }
 
 // Plugin
@@ -35,55 +317,448 @@ pub struct PickingPlugin;
 impl Plugin for PickingPlugin {
     fn build(&self, app: &mut bevy::prelude::App) {
         app.insert_resource(PickState::default());
-        app.add_system(pick_input.in_set(GameSystemSets::Input));
+        app.insert_resource(PickIndex::default());
+        app.add_systems(
+            (update_pick_index, pick_input)
+                .chain()
+                .after(cycle_units)
+                .in_set(GameSystemSets::Input),
+        );
+        app.add_systems(
+            (update_pick_index, pick_input)
+                .chain()
+                .in_set(OnUpdate(AppState::Editor)),
+        );
+    }
+}
+
+// Rebuilt whenever tiles are spawned, moved or despawned
+fn update_pick_index(
+    mut pick_index: ResMut<PickIndex>,
+    changed: Query<(), (With<Tile>, Changed<GlobalTransform>)>,
+    mut removed: RemovedComponents<Tile>,
+    tiles: Query<(Entity, &Tile, &Pickable, &GlobalTransform)>,
+    game_assets: Res<GameAssets>,
+    tilemaps: Res<Assets<TiledMap>>,
+) {
+    if changed.is_empty() && removed.iter().count() == 0 {
+        return;
+    }
+    let Some(tilemap) = tilemaps.get(&game_assets.map) else {
+        return;
+    };
+    let tile_size = Vec2::new(tilemap.tilewidth as f32, tilemap.tileheight as f32);
+    *pick_index = PickIndex::new(tile_size, tiles.iter());
+}
+
+type PickableQuery<'w, 's> = Query<
+    'w,
+    's,
+    (
+        &'static Pickable,
+        &'static GlobalTransform,
+        Option<&'static Visibility>,
+        Option<&'static Sprite>,
+        Option<&'static Handle<Image>>,
+        Option<&'static TextureAtlasSprite>,
+        Option<&'static Handle<TextureAtlas>>,
+        Entity,
+    ),
+>;
+
+// Where the mouse points in the world
+#[derive(SystemParam)]
+struct Pointer<'w, 's> {
+    camera: Query<'w, 's, (&'static Camera, &'static GlobalTransform)>,
+    primary_window: Query<'w, 's, &'static Window, With<PrimaryWindow>>,
+}
+
+impl Pointer<'_, '_> {
+    fn world_position(&self) -> Option<Vec2> {
+        let (camera, camera_transform) = self.camera.single();
+        // fuck off bevy docs
+        let window = match camera.target {
+            RenderTarget::Window(bevy::window::WindowRef::Primary) => self.primary_window.single(),
+            // Ignore this
+            // RenderTarget::Window(bevy::window::WindowRef::Entity(entity)) => windows.get(entity),
+            _ => return None,
+        };
+        window
+            .cursor_position()
+            .and_then(|cursor_pos| camera.viewport_to_world_2d(camera_transform, cursor_pos))
+    }
+}
+
+// Everything that can be picked and the images needed to check their pixels
+#[derive(SystemParam)]
+struct PickTargets<'w, 's> {
+    pickables: PickableQuery<'w, 's>,
+    pick_index: Res<'w, PickIndex>,
+    // units and anything else that isn't part of the map
+    others: Query<'w, 's, Entity, (With<Pickable>, Without<Tile>)>,
+    images: Res<'w, Assets<Image>>,
+    atlases: Res<'w, Assets<TextureAtlas>>,
+}
+
+impl PickTargets<'_, '_> {
+    fn pick(&self, world_pos: &Vec2) -> Option<Entity> {
+        let candidates = self
+            .pick_index
+            .candidates(world_pos)
+            .chain(self.others.iter());
+        pick_nearst(
+            candidates,
+            &self.pickables,
+            &self.images,
+            &self.atlases,
+            world_pos,
+        )
     }
 }
 
 fn pick_input(
-    camera: Query<(&Camera, &GlobalTransform)>,
-    primary_window: Query<&Window, With<PrimaryWindow>>,
-    pickables: Query<(&Pickable, &GlobalTransform, Entity)>,
+    pointer: Pointer,
+    targets: PickTargets,
+    units: Query<&Unit>,
+    unit_registry: Res<UnitRegistry>,
+    cursor: Res<GridCursor>,
+    ui_elements: Query<&Interaction>,
     mut pick_state: ResMut<PickState>,
 ) {
-    let (camera, camera_transform) = camera.single();
-    // fuck off bevy docs
-    let window = match camera.target {
-        RenderTarget::Window(bevy::window::WindowRef::Primary) => primary_window.single(),
-        // Ignore this
-        // RenderTarget::Window(bevy::window::WindowRef::Entity(entity)) => windows.get(entity),
-        _ => return,
-    };
+    // keyboard and gamepad pick whole tiles
+    if let Some(tile) = cursor.tile.filter(|_| cursor.active) {
+        pick_state.selected = targets.pick_index.top_tile(&tile);
+        // hidden units can't be picked with the mouse either
+        pick_state.unit = unit_registry.units.get(&tile).copied().filter(|unit| {
+            targets
+                .pickables
+                .get(*unit)
+                .is_ok_and(|(_, _, visibility, ..)| visibility != Some(&Visibility::Hidden))
+        });
+        return;
+    }
 
-    if let Some(cursor_pos) = window.cursor_position() {
-        if let Some(world_pos) = camera.viewport_to_world_2d(camera_transform, cursor_pos) {
-            pick_state.selected = pick_nearst(&pickables, &world_pos);
-        }
+    // the cursor is over the ui, nothing behind it should be picked
+    if ui_elements
+        .iter()
+        .any(|interaction| *interaction != Interaction::None)
+    {
+        pick_state.selected = None;
+        pick_state.unit = None;
+        return;
     }
+
+    let Some(world_pos) = pointer.world_position() else {
+        return;
+    };
+
+    let picked = targets.pick(&world_pos);
+    // units stand in for the tile below them
+    let unit = picked.and_then(|entity| Some((entity, units.get(entity).ok()?)));
+    pick_state.unit = unit.map(|(entity, _)| entity);
+    pick_state.selected = match unit {
+        Some((_, unit)) => targets.pick_index.top_tile(&unit.tile()),
+        None => picked,
+    };
 }
 
 fn pick_nearst(
-    pickables: &Query<(&Pickable, &GlobalTransform, Entity)>,
+    candidates: impl Iterator<Item = Entity>,
+    pickables: &PickableQuery,
+    images: &Assets<Image>,
+    atlases: &Assets<TextureAtlas>,
     world_pos: &Vec2,
 ) -> Option<Entity> {
     let mut nearest: Option<Entity> = None;
-    let mut distance = -1.;
-    for (pickable, transform, entity) in &(*pickables) {
-        let obj_translation = transform.translation();
-        let corrected_pos = Vec2::new(
-            world_pos.x - obj_translation.x,
-            world_pos.y - obj_translation.y,
-        );
+    // priority, then the one drawn in front
+    let mut best: Option<(i32, f32)> = None;
+    for candidate in candidates {
+        // the index may still list despawned tiles
+        let Ok((pickable, transform, visibility, sprite, image, atlas_sprite, atlas, entity)) =
+            pickables.get(candidate)
+        else {
+            continue;
+        };
+        let depth = (pickable.priority, transform.translation().z);
+        if visibility == Some(&Visibility::Hidden) || best.is_some_and(|best| depth <= best) {
+            continue;
+        }
 
-        if distance >= 0. && obj_translation.z < distance {
+        let local = transform
+            .affine()
+            .inverse()
+            .transform_point3(world_pos.extend(transform.translation().z))
+            .truncate();
+        if !pickable.triangles.is_empty() && !pickable.contains(&local) {
             continue;
         }
 
-        for triangle in &pickable.triangles {
-            if triangle.contains(&corrected_pos) {
-                nearest = Some(entity);
-                distance = obj_translation.z;
+        let sprite_image = match (sprite, image, atlas_sprite, atlas) {
+            (Some(sprite), Some(image), _, _) => SpriteImage::from_sprite(sprite, image, images),
+            (_, _, Some(sprite), Some(atlas)) => {
+                SpriteImage::from_atlas_sprite(sprite, atlas, images, atlases)
             }
+            _ => None,
+        };
+        let hit = match sprite_image {
+            Some(sprite_image) => {
+                // without triangles the whole sprite rectangle counts
+                (!pickable.triangles.is_empty() || sprite_image.contains(&local))
+                    && (!pickable.alpha_mask || sprite_image.is_opaque_at(&local))
+            }
+            // not loaded yet, only the triangles can be checked
+            None => !pickable.triangles.is_empty(),
+        };
+        if hit {
+            nearest = Some(entity);
+            best = Some(depth);
         }
     }
     nearest
 }
+
+// The part of an image a sprite shows
+struct SpriteImage<'a> {
+    image: &'a Image,
+    rect: Rect,
+    // on screen, before scaling
+    size: Vec2,
+    flip_x: bool,
+    flip_y: bool,
+}
+
+impl<'a> SpriteImage<'a> {
+    fn from_sprite(
+        sprite: &Sprite,
+        image: &Handle<Image>,
+        images: &'a Assets<Image>,
+    ) -> Option<Self> {
+        let image = images.get(image)?;
+        let rect = sprite
+            .rect
+            .unwrap_or(Rect::from_corners(Vec2::ZERO, image.size()));
+        Some(SpriteImage {
+            image,
+            rect,
+            size: sprite.custom_size.unwrap_or(rect.size()),
+            flip_x: sprite.flip_x,
+            flip_y: sprite.flip_y,
+        })
+    }
+
+    fn from_atlas_sprite(
+        sprite: &TextureAtlasSprite,
+        atlas: &Handle<TextureAtlas>,
+        images: &'a Assets<Image>,
+        atlases: &Assets<TextureAtlas>,
+    ) -> Option<Self> {
+        let atlas = atlases.get(atlas)?;
+        let rect = *atlas.textures.get(sprite.index)?;
+        Some(SpriteImage {
+            image: images.get(&atlas.texture)?,
+            rect,
+            size: sprite.custom_size.unwrap_or(rect.size()),
+            flip_x: sprite.flip_x,
+            flip_y: sprite.flip_y,
+        })
+    }
+
+    // Position inside the rect, 0 to 1 from the top left corner
+    fn uv(&self, local: &Vec2) -> Vec2 {
+        let mut uv = Vec2::new(local.x / self.size.x + 0.5, 0.5 - local.y / self.size.y);
+        if self.flip_x {
+            uv.x = 1. - uv.x;
+        }
+        if self.flip_y {
+            uv.y = 1. - uv.y;
+        }
+        uv
+    }
+
+    fn contains(&self, local: &Vec2) -> bool {
+        let uv = self.uv(local);
+        (0. ..1.).contains(&uv.x) && (0. ..1.).contains(&uv.y)
+    }
+
+    fn is_opaque_at(&self, local: &Vec2) -> bool {
+        if !self.contains(local) {
+            return false;
+        }
+        // other formats don't come from image files
+        if !matches!(
+            self.image.texture_descriptor.format,
+            TextureFormat::Rgba8UnormSrgb | TextureFormat::Rgba8Unorm
+        ) {
+            return true;
+        }
+
+        let pixel = self.rect.min + self.uv(local) * self.rect.size();
+        let width = self.image.texture_descriptor.size.width as usize;
+        let index = (pixel.y as usize * width + pixel.x as usize) * 4 + 3;
+        self.image
+            .data
+            .get(index)
+            .is_none_or(|alpha| *alpha > ALPHA_THRESHOLD)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use bevy::{ecs::system::SystemState, prelude::*};
+
+    use crate::game::isometric::iso_transform;
+
+    use super::*;
+
+    // as big as the handmade map
+    const SIZE: i32 = 60;
+    const LAYERS: i32 = 3;
+    const TILE_SIZE: Vec2 = Vec2::new(232., 110.);
+    const IMAGE_HEIGHT: f32 = 352.;
+
+    type PickParams<'w, 's> = (
+        PickableQuery<'w, 's>,
+        Query<
+            'w,
+            's,
+            (
+                Entity,
+                &'static Tile,
+                &'static Pickable,
+                &'static GlobalTransform,
+            ),
+        >,
+        Res<'w, Assets<Image>>,
+        Res<'w, Assets<TextureAtlas>>,
+    );
+
+    fn map_app() -> App {
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .add_plugin(AssetPlugin::default())
+            .add_asset::<Image>()
+            .add_asset::<TextureAtlas>();
+
+        let pickable = Pickable::block(TILE_SIZE.x, TILE_SIZE.y, IMAGE_HEIGHT);
+        for z in 0..LAYERS {
+            for x in 0..SIZE {
+                for y in 0..SIZE {
+                    let translation = iso_transform(
+                        x as f32,
+                        y as f32,
+                        z as f32,
+                        TILE_SIZE.x,
+                        TILE_SIZE.y,
+                        false,
+                    );
+                    app.world.spawn((
+                        pickable.clone(),
+                        Tile {
+                            x,
+                            y,
+                            z,
+                            opacity: 1.,
+                        },
+                        GlobalTransform::from_translation(translation),
+                    ));
+                }
+            }
+        }
+        app
+    }
+
+    // Evenly spread over the whole map and a bit beyond
+    fn cursor_positions(count: i32) -> Vec<Vec2> {
+        let half_width = SIZE as f32 * TILE_SIZE.x / 2.;
+        let (bottom, top) = (-SIZE as f32 * TILE_SIZE.y, LAYERS as f32 * TILE_SIZE.y);
+        (0..count)
+            .flat_map(|i| (0..count).map(move |j| (i, j)))
+            .map(|(i, j)| {
+                Vec2::new(
+                    -half_width + (i as f32 + 0.37) / count as f32 * 2. * half_width,
+                    bottom + (j as f32 + 0.61) / count as f32 * (top - bottom),
+                )
+            })
+            .collect()
+    }
+
+    // what was picked at each position, how long it took
+    type Picks = (Vec<Option<Entity>>, Duration);
+
+    // Picks every position with and without the index, returning both results and timings
+    fn pick_all(app: &mut App, positions: &[Vec2]) -> (Picks, Picks) {
+        let mut state: SystemState<PickParams> = SystemState::new(&mut app.world);
+        let (pickables, tiles, images, atlases) = state.get(&app.world);
+        let index = PickIndex::new(TILE_SIZE, tiles.iter());
+
+        let start = Instant::now();
+        let scanned: Vec<Option<Entity>> = positions
+            .iter()
+            .map(|position| {
+                let everything = pickables.iter().map(|pickable| pickable.7);
+                pick_nearst(everything, &pickables, &images, &atlases, position)
+            })
+            .collect();
+        let scan_time = start.elapsed();
+
+        let start = Instant::now();
+        let indexed: Vec<Option<Entity>> = positions
+            .iter()
+            .map(|position| {
+                pick_nearst(
+                    index.candidates(position),
+                    &pickables,
+                    &images,
+                    &atlases,
+                    position,
+                )
+            })
+            .collect();
+        let index_time = start.elapsed();
+
+        ((scanned, scan_time), (indexed, index_time))
+    }
+
+    #[test]
+    fn index_picks_the_same_tiles_as_a_full_scan() {
+        let mut app = map_app();
+        let ((scanned, _), (indexed, _)) = pick_all(&mut app, &cursor_positions(20));
+        assert!(scanned.iter().filter(|picked| picked.is_some()).count() > 50);
+        assert_eq!(scanned, indexed);
+    }
+
+    #[test]
+    fn index_only_returns_nearby_tiles() {
+        let mut app = map_app();
+        let mut state: SystemState<PickParams> = SystemState::new(&mut app.world);
+        let (_, tiles, _, _) = state.get(&app.world);
+        let index = PickIndex::new(TILE_SIZE, tiles.iter());
+
+        let center = iso_transform(30., 30., 1., TILE_SIZE.x, TILE_SIZE.y, false).truncate();
+        let candidates = index.candidates(&center).count();
+        assert!(
+            candidates > 0 && candidates < 100,
+            "{candidates} candidates"
+        );
+    }
+
+    // cargo test --release benchmark_picking -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn benchmark_picking() {
+        let mut app = map_app();
+        let positions = cursor_positions(30);
+        let ((_, scan_time), (_, index_time)) = pick_all(&mut app, &positions);
+        let per_pick = |time: Duration| time / positions.len() as u32;
+        println!(
+            "{} tiles, {} picks: full scan {:?} per pick, index {:?} per pick",
+            SIZE * SIZE * LAYERS,
+            positions.len(),
+            per_pick(scan_time),
+            per_pick(index_time),
+        );
+        assert!(index_time < scan_time);
+    }
+}
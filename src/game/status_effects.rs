@@ -0,0 +1,329 @@
+use bevy::prelude::*;
+
+use crate::game_config::GameAssets;
+
+use super::{unit::Unit, GameEvent, GameSystemSets};
+
+// poison never deals more than this per turn, no matter how often it was applied
+const MAX_POISON: u32 = 3;
+
+pub struct StatusEffectsPlugin;
+
+impl Plugin for StatusEffectsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems((
+            apply_status_effect_events.in_set(GameSystemSets::Update),
+            apply_stat_modifiers.in_set(GameSystemSets::Update),
+            update_status_icons.in_set(GameSystemSets::Render),
+        ));
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum EffectKind {
+    // takes damage at the end of each of its turns
+    Poison,
+    // skips its turns
+    Stun,
+    // moves one tile less
+    Slow,
+    // absorbs incoming attack damage
+    Shield,
+    // moves one tile further
+    Haste,
+}
+
+impl EffectKind {
+    // as written in map files
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "poison" => Some(EffectKind::Poison),
+            "stun" => Some(EffectKind::Stun),
+            "slow" => Some(EffectKind::Slow),
+            "shield" => Some(EffectKind::Shield),
+            "haste" => Some(EffectKind::Haste),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            EffectKind::Poison => "poison",
+            EffectKind::Stun => "stun",
+            EffectKind::Slow => "slow",
+            EffectKind::Shield => "shield",
+            EffectKind::Haste => "haste",
+        }
+    }
+
+    pub fn icon(&self) -> (&'static str, Color) {
+        match self {
+            EffectKind::Poison => ("☠", Color::rgb(0.4, 0.9, 0.3)),
+            EffectKind::Stun => ("★", Color::rgb(1.0, 0.9, 0.2)),
+            EffectKind::Slow => ("▼", Color::rgb(0.4, 0.6, 1.0)),
+            EffectKind::Shield => ("◆", Color::rgb(0.9, 0.9, 0.9)),
+            EffectKind::Haste => ("▲", Color::rgb(1.0, 0.6, 0.2)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum EffectDuration {
+    // turns of the affected unit
+    Turns(u32),
+    // rounds of the whole turn order
+    Rounds(u32),
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct StatusEffect {
+    pub kind: EffectKind,
+    pub duration: EffectDuration,
+    // poison damage per turn or shield points, unused by the other effects
+    pub strength: u32,
+}
+
+impl StatusEffect {
+    pub fn new(kind: EffectKind, duration: EffectDuration, strength: u32) -> Self {
+        StatusEffect {
+            kind,
+            duration,
+            strength,
+        }
+    }
+}
+
+#[derive(Component, Default)]
+pub struct StatusEffects {
+    effects: Vec<StatusEffect>,
+}
+
+impl StatusEffects {
+    // Stacking rules:
+    // - slow and haste cancel each other out
+    // - poison and shields add up their strength
+    // - applying an effect again keeps the longer duration
+    pub fn add(&mut self, effect: StatusEffect) {
+        let opposite = match effect.kind {
+            EffectKind::Slow => Some(EffectKind::Haste),
+            EffectKind::Haste => Some(EffectKind::Slow),
+            _ => None,
+        };
+        if let Some(opposite) = opposite {
+            if self.has(opposite) {
+                self.effects.retain(|existing| existing.kind != opposite);
+                return;
+            }
+        }
+
+        let Some(existing) = self
+            .effects
+            .iter_mut()
+            .find(|existing| existing.kind == effect.kind)
+        else {
+            self.effects.push(effect);
+            return;
+        };
+
+        existing.strength = match effect.kind {
+            EffectKind::Poison => (existing.strength + effect.strength).min(MAX_POISON),
+            EffectKind::Shield => existing.strength + effect.strength,
+            _ => existing.strength.max(effect.strength),
+        };
+        existing.duration = match (existing.duration, effect.duration) {
+            (EffectDuration::Turns(a), EffectDuration::Turns(b)) => EffectDuration::Turns(a.max(b)),
+            (EffectDuration::Rounds(a), EffectDuration::Rounds(b)) => {
+                EffectDuration::Rounds(a.max(b))
+            }
+            (_, new) => new,
+        };
+    }
+
+    pub fn has(&self, kind: EffectKind) -> bool {
+        self.effects.iter().any(|effect| effect.kind == kind)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &StatusEffect> {
+        self.effects.iter()
+    }
+
+    pub fn travel_distance(&self, base: u32) -> u32 {
+        let mut distance = base;
+        if self.has(EffectKind::Haste) {
+            distance += 1;
+        }
+        if self.has(EffectKind::Slow) {
+            distance = distance.saturating_sub(1).max(1);
+        }
+        distance
+    }
+
+    // damage the shield can still absorb
+    pub fn shield(&self) -> u32 {
+        self.effects
+            .iter()
+            .find(|effect| effect.kind == EffectKind::Shield)
+            .map_or(0, |shield| shield.strength)
+    }
+
+    // Returns the damage that gets through the shield
+    pub fn absorb(&mut self, damage: u32) -> u32 {
+        let Some(shield) = self
+            .effects
+            .iter_mut()
+            .find(|effect| effect.kind == EffectKind::Shield)
+        else {
+            return damage;
+        };
+
+        let absorbed = shield.strength.min(damage);
+        shield.strength -= absorbed;
+        if shield.strength == 0 {
+            self.effects
+                .retain(|effect| effect.kind != EffectKind::Shield);
+        }
+        damage - absorbed
+    }
+
+    // Called when the unit's turn ends or is skipped. Returns the poison damage to apply
+    pub fn end_turn(&mut self) -> u32 {
+        let damage = self
+            .effects
+            .iter()
+            .filter(|effect| effect.kind == EffectKind::Poison)
+            .map(|effect| effect.strength)
+            .sum();
+
+        self.tick(|duration| match duration {
+            EffectDuration::Turns(turns) => Some(EffectDuration::Turns(turns.saturating_sub(1))),
+            EffectDuration::Rounds(_) => None,
+        });
+        damage
+    }
+
+    pub fn end_round(&mut self) {
+        self.tick(|duration| match duration {
+            EffectDuration::Rounds(rounds) => {
+                Some(EffectDuration::Rounds(rounds.saturating_sub(1)))
+            }
+            EffectDuration::Turns(_) => None,
+        });
+    }
+
+    fn tick(&mut self, tick: impl Fn(EffectDuration) -> Option<EffectDuration>) {
+        for effect in self.effects.iter_mut() {
+            if let Some(duration) = tick(effect.duration) {
+                effect.duration = duration;
+            }
+        }
+        self.effects.retain(|effect| {
+            !matches!(
+                effect.duration,
+                EffectDuration::Turns(0) | EffectDuration::Rounds(0)
+            )
+        });
+    }
+}
+
+#[derive(Component)]
+struct StatusIcons;
+
+fn apply_status_effect_events(
+    mut event_reader: EventReader<GameEvent>,
+    mut units: Query<&mut StatusEffects>,
+) {
+    for event in event_reader.iter() {
+        if let GameEvent::ApplyEffect(entity, effect) = event {
+            if let Ok(mut effects) = units.get_mut(*entity) {
+                effects.add(*effect);
+            }
+        }
+    }
+}
+
+fn apply_stat_modifiers(mut units: Query<(&mut Unit, &StatusEffects)>) {
+    for (mut unit, effects) in units.iter_mut() {
+        let travel_distance = effects.travel_distance(unit.base_travel_distance);
+        if unit.travel_distance != travel_distance {
+            unit.travel_distance = travel_distance;
+        }
+    }
+}
+
+fn update_status_icons(
+    mut commands: Commands,
+    game_assets: Res<GameAssets>,
+    units: Query<(Entity, &StatusEffects, Option<&Children>), Changed<StatusEffects>>,
+    icons: Query<Entity, With<StatusIcons>>,
+) {
+    for (entity, effects, children) in units.iter() {
+        for child in children.into_iter().flatten() {
+            if icons.contains(*child) {
+                commands.entity(*child).despawn_recursive();
+            }
+        }
+
+        let sections: Vec<TextSection> = effects
+            .iter()
+            .map(|effect| {
+                let (icon, color) = effect.kind.icon();
+                TextSection::new(
+                    icon,
+                    TextStyle {
+                        font: game_assets.font.clone(),
+                        font_size: 48.,
+                        color,
+                    },
+                )
+            })
+            .collect();
+        if sections.is_empty() {
+            continue;
+        }
+
+        let icon = commands
+            .spawn((
+                Text2dBundle {
+                    text: Text::from_sections(sections),
+                    // above the unit's head
+                    transform: Transform::from_xyz(0., 110., 0.1),
+                    ..default()
+                },
+                StatusIcons,
+            ))
+            .id();
+        commands.entity(entity).add_child(icon);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_effects_only_run_out_when_rounds_end() {
+        let mut effects = StatusEffects::default();
+        effects.add(StatusEffect::new(
+            EffectKind::Slow,
+            EffectDuration::Rounds(2),
+            0,
+        ));
+        effects.add(StatusEffect::new(
+            EffectKind::Poison,
+            EffectDuration::Turns(1),
+            1,
+        ));
+
+        assert_eq!(effects.end_turn(), 1);
+        assert!(effects.has(EffectKind::Slow));
+        assert!(!effects.has(EffectKind::Poison));
+
+        effects.end_round();
+        assert_eq!(
+            effects.iter().next().map(|effect| effect.duration),
+            Some(EffectDuration::Rounds(1))
+        );
+        effects.end_round();
+        assert!(!effects.has(EffectKind::Slow));
+    }
+}
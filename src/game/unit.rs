@@ -1,16 +1,25 @@
 use std::collections::HashMap;
 
-use bevy::prelude::*;
+use bevy::{ecs::system::SystemParam, prelude::*};
 
 use crate::{
-    assets::types::TiledMap, game::map::MapLayout, game_config::GameAssets, math::max, AppState,
+    assets::{animation_sheet::AnimationSheet, types::TiledMap},
+    game::map::MapLayout,
+    game_config::GameAssets,
+    math::max,
+    util::rng::Rng,
 };
 
 use super::{
-    animation::{Animatable, Animation},
+    abilities::{Abilities, Ability, AbilityEffect, TargetKind, TargetShape},
+    animation::Animatable,
+    combat::{forecast_attack, knockback, CombatRng, KnockbackStop, COLLISION_DAMAGE},
+    combat_feedback::{CombatFeedback, Corpse},
     game_state::GameStateEvent,
     isometric::{iso_transform, IsometricDirection},
-    map::MapState,
+    map::{neighbours, reload_map, MapReloaded, MapState},
+    picking::{Pickable, UNIT_PRIORITY},
+    status_effects::{EffectDuration, EffectKind, StatusEffect, StatusEffects},
     GameEvent, GameSystemSets,
 };
 
@@ -19,13 +28,23 @@ pub struct UnitPlugin;
 impl Plugin for UnitPlugin {
     fn build(&self, app: &mut App) {
         app.insert_resource(UnitRegistry::default());
+        app.insert_resource(CombatRng(Rng::from_time()));
         app.add_systems((
+            spawn_units.in_set(GameSystemSets::Update),
             process_unit_event.in_set(GameSystemSets::Update),
-            apply_system_buffers.after(process_unit_event),
+            apply_system_buffers
+                .after(spawn_units)
+                .after(process_unit_event),
             update_unit_transform.in_set(GameSystemSets::Logic),
+            animate_forced_movement
+                .in_set(GameSystemSets::Logic)
+                .before(update_unit_transform),
             move_units
                 .in_set(GameSystemSets::Logic)
                 .before(update_unit_transform),
+            keep_units_on_map
+                .in_set(GameSystemSets::Update)
+                .after(reload_map),
         ));
     }
 }
@@ -35,8 +54,131 @@ pub struct UnitRegistry {
     pub units: HashMap<(i32, i32), Entity>,
 }
 
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum UnitKind {
+    Ogre,
+    // ranged ogre throwing rocks
+    OgreSlinger,
+    // ogre riding a giant bat
+    BatRider,
+}
+
+// the units every participant places, one per placing round
+const UNIT_LINEUP: [UnitKind; 3] = [UnitKind::Ogre, UnitKind::OgreSlinger, UnitKind::BatRider];
+
+impl UnitKind {
+    pub fn for_placing_round(round: u32) -> Self {
+        UNIT_LINEUP[round as usize % UNIT_LINEUP.len()]
+    }
+
+    pub fn is_air(&self) -> bool {
+        *self == UnitKind::BatRider
+    }
+
+    // name of the unit's texture and animation sheet, all ogres share one for now
+    pub fn sprite(&self) -> &'static str {
+        "ogre"
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            UnitKind::Ogre => "Ogre",
+            UnitKind::OgreSlinger => "Ogre slinger",
+            UnitKind::BatRider => "Bat rider",
+        }
+    }
+
+    pub fn abilities(&self) -> Vec<Ability> {
+        match self {
+            UnitKind::Ogre => vec![
+                Ability {
+                    name: "Charge",
+                    cost: 2,
+                    cooldown: 3,
+                    range: 1,
+                    shape: TargetShape::Line(3),
+                    target: TargetKind::Direction,
+                    effect: AbilityEffect::Charge(3),
+                },
+                Ability {
+                    name: "Shove",
+                    cost: 1,
+                    cooldown: 2,
+                    range: 1,
+                    shape: TargetShape::Single,
+                    target: TargetKind::Enemy,
+                    effect: AbilityEffect::Push(1),
+                },
+                Ability {
+                    name: "Roar",
+                    cost: 2,
+                    cooldown: 4,
+                    range: 1,
+                    shape: TargetShape::Cone(2),
+                    target: TargetKind::Direction,
+                    effect: AbilityEffect::ApplyEffect(StatusEffect::new(
+                        EffectKind::Slow,
+                        EffectDuration::Turns(2),
+                        0,
+                    )),
+                },
+            ],
+            UnitKind::BatRider => vec![
+                Ability {
+                    name: "Dive",
+                    cost: 2,
+                    cooldown: 3,
+                    range: 1,
+                    shape: TargetShape::Line(2),
+                    target: TargetKind::Direction,
+                    effect: AbilityEffect::Charge(2),
+                },
+                Ability {
+                    name: "Snatch",
+                    cost: 1,
+                    cooldown: 3,
+                    range: 3,
+                    shape: TargetShape::Single,
+                    target: TargetKind::Enemy,
+                    effect: AbilityEffect::Pull(2),
+                },
+            ],
+            UnitKind::OgreSlinger => vec![
+                Ability {
+                    name: "Boulder",
+                    cost: 2,
+                    cooldown: 3,
+                    range: 4,
+                    shape: TargetShape::Radius(1),
+                    target: TargetKind::AnyTile,
+                    effect: AbilityEffect::Damage(2),
+                },
+                Ability {
+                    name: "Patch up",
+                    cost: 1,
+                    cooldown: 2,
+                    range: 2,
+                    shape: TargetShape::Single,
+                    target: TargetKind::Ally,
+                    effect: AbilityEffect::Heal(3),
+                },
+                Ability {
+                    name: "Blink",
+                    cost: 2,
+                    cooldown: 4,
+                    range: 4,
+                    shape: TargetShape::Single,
+                    target: TargetKind::EmptyTile,
+                    effect: AbilityEffect::Teleport,
+                },
+            ],
+        }
+    }
+}
+
 #[derive(Component)]
 pub struct Unit {
+    pub kind: UnitKind,
     pub x: f32,
     pub y: f32,
     pub z: f32,
@@ -44,214 +186,635 @@ pub struct Unit {
     pub owner: usize,
 
     // movement stats
+    // base_travel_distance modified by status effects
     pub travel_distance: u32,
+    pub base_travel_distance: u32,
     pub is_air: bool,
-    pub turn_prio: i32,
     travel_speed: f32,
 
-    // animations
-    idle: Animation,
-    move_up_right: Animation,
-    move_up_left: Animation,
-    move_down_right: Animation,
-    move_down_left: Animation,
+    // combat stats
+    pub health: u32,
+    pub max_health: u32,
+    pub attack: u32,
+    pub attack_range: u32,
+    pub sight_radius: u32,
+    pub defense: u32,
+    pub defending: bool,
+
+    // picks the directional animation states
+    pub facing: IsometricDirection,
 
     // movement state
     // waypoint index, waypoint progress, waypoints
     path: Option<(u32, Vec<(i32, i32)>)>,
     path_progress: Option<f32>,
     render_priority: Option<f32>,
+    forced_movement: Option<ForcedMovement>,
 }
 
+// Knockbacks move the unit right away, only the sprite slides over
+struct ForcedMovement {
+    // from the unit's position back to where the knockback started
+    offset: Vec3,
+    progress: f32,
+    facing: IsometricDirection,
+    // the unit fell off the map and gets removed once it is out of sight
+    remove: bool,
+}
+
+const DEFEND_BONUS: u32 = 2;
+const MAX_ENERGY: u32 = 3;
+// how many layers above its tile a flying unit hovers
+const FLIGHT_ALTITUDE: f32 = 0.75;
+// tiles per second
+const KNOCKBACK_SPEED: f32 = 4.;
+// how many layers a unit falls when knocked off the map before it disappears
+const FALL_DEPTH: f32 = 3.;
+
 impl Unit {
+    pub fn defense(&self) -> u32 {
+        if self.defending {
+            self.defense + DEFEND_BONUS
+        } else {
+            self.defense
+        }
+    }
+
+    // the tile the unit currently stands on, or is closest to while moving
+    pub fn tile(&self) -> (i32, i32) {
+        (self.x.round() as i32, self.y.round() as i32)
+    }
+
     pub fn move_path(&mut self, path: Vec<(i32, i32)>) {
         self.path = Some((0, path));
     }
+
+    pub fn is_moving(&self) -> bool {
+        self.path.is_some()
+    }
+
+    // the tile the unit last reached on its way and the ones it has yet to reach
+    pub fn tiles_ahead(&self) -> &[(i32, i32)] {
+        match &self.path {
+            Some((waypoint, path)) => &path[*waypoint as usize..],
+            None => &[],
+        }
+    }
+
+    fn position(&self) -> Vec3 {
+        Vec3::new(self.x, self.y, self.z)
+    }
+
+    pub fn attack_state(&self) -> String {
+        format!("attack_{}", self.facing.name())
+    }
+
+    pub fn death_state(&self) -> String {
+        format!("death_{}", self.facing.name())
+    }
+
+    fn altitude(&self) -> f32 {
+        if self.is_air {
+            FLIGHT_ALTITUDE
+        } else {
+            0.
+        }
+    }
 }
 
+#[derive(Component)]
+struct UnitShadow;
+
 fn generate_entity(
-    x: i32,
-    y: i32,
-    z: i32,
+    (x, y, z): (i32, i32, i32),
     owner: usize,
-    turn_prio: i32,
+    kind: UnitKind,
     commands: &mut Commands,
     texture: Handle<Image>,
-    idle: Animation,
-    move_up_left: Animation,
-    move_up_right: Animation,
-    move_down_left: Animation,
-    move_down_right: Animation,
+    animatable: Option<Animatable>,
 ) -> Entity {
-    commands
-        .spawn((
-            SpriteBundle {
-                texture,
-                sprite: Sprite {
-                    rect: Some(Rect::new(0., 0., 0., 0.)),
-                    ..Default::default()
-                },
-                transform: Transform::from_scale(Vec3::new(0.5, 0.5, 0.5)),
-                ..default()
-            },
-            Unit {
-                owner,
-                turn_prio,
-                travel_distance: 3,
-                x: x as f32,
-                y: y as f32,
-                z: z as f32,
-                path: None,
-                path_progress: None,
-                render_priority: None,
-                is_air: false,
-                travel_speed: 0.25,
-                idle: idle.clone(),
-                move_up_left,
-                move_up_right,
-                move_down_left,
-                move_down_right,
+    // health, attack, attack range, sight radius, color
+    let (health, attack, attack_range, sight_radius, color) = match kind {
+        UnitKind::Ogre => (10, 4, 1, 4, Color::WHITE),
+        UnitKind::OgreSlinger => (6, 3, 3, 5, Color::rgb(0.7, 0.8, 1.0)),
+        UnitKind::BatRider => (7, 3, 1, 5, Color::rgb(0.8, 0.6, 1.0)),
+    };
+
+    let mut entity = commands.spawn((
+        SpriteBundle {
+            texture,
+            sprite: Sprite {
+                rect: Some(Rect::new(0., 0., 0., 0.)),
+                color,
+                ..Default::default()
             },
-            Animatable::from_anim(idle),
-        ))
-        .id()
+            transform: Transform::from_scale(Vec3::new(0.5, 0.5, 0.5)),
+            ..default()
+        },
+        // only the drawn part of the animation frame
+        Pickable {
+            triangles: vec![],
+            alpha_mask: true,
+            priority: UNIT_PRIORITY,
+        },
+        Unit {
+            kind,
+            owner,
+            travel_distance: 3,
+            base_travel_distance: 3,
+            x: x as f32,
+            y: y as f32,
+            z: z as f32,
+            path: None,
+            path_progress: None,
+            render_priority: None,
+            forced_movement: None,
+            is_air: kind.is_air(),
+            travel_speed: 0.25,
+            health,
+            max_health: health,
+            attack,
+            attack_range,
+            sight_radius,
+            defense: 1,
+            defending: false,
+            facing: IsometricDirection::DownRight,
+        },
+        StatusEffects::default(),
+        Abilities::new(MAX_ENERGY, kind.abilities()),
+    ));
+    match animatable {
+        Some(animatable) => {
+            entity.insert(animatable);
+        }
+        None => warn!("{kind:?} has no animations, it won't be shown"),
+    }
+    entity.id()
 }
 
-fn process_unit_event(
+// Textures and animation sheets of the units
+#[derive(SystemParam)]
+struct UnitAssets<'w> {
+    game_assets: Res<'w, GameAssets>,
+    animation_sheets: Res<'w, Assets<AnimationSheet>>,
+}
+
+impl UnitAssets<'_> {
+    fn texture(&self, kind: UnitKind) -> Handle<Image> {
+        self.game_assets.units[kind.sprite()].clone()
+    }
+
+    // None if the sheet didn't load, the loading screen shows why
+    fn animatable(&self, kind: UnitKind) -> Option<Animatable> {
+        self.game_assets
+            .unit_animations
+            .get(kind.sprite())
+            .and_then(|handle| self.animation_sheets.get(handle))
+            .and_then(|sheet| Animatable::from_sheet(sheet, "idle"))
+    }
+}
+
+// What happened to a unit, for the game state and for the players to see
+#[derive(SystemParam)]
+struct UnitEvents<'w> {
+    state: EventWriter<'w, GameStateEvent>,
+    feedback: EventWriter<'w, CombatFeedback>,
+}
+
+fn spawn_units(
     mut event_reader: EventReader<GameEvent>,
     mut event_writer: EventWriter<GameStateEvent>,
     mut unit_registry: ResMut<UnitRegistry>,
     map_layout: Res<MapLayout>,
-    game_assets: Res<GameAssets>,
+    unit_assets: UnitAssets,
+    mut commands: Commands,
+) {
+    for event in event_reader.iter() {
+        let (participant, kind, tile) = match event {
+            GameEvent::SpawnUnit(x, y, participant, kind) => (*participant, *kind, (*x, *y)),
+            GameEvent::PlaceAiUnit(participant, kind) => {
+                // the first free tile of the deployment zone, or along the diagonal without one
+                let mut zone: Vec<(i32, i32)> = map_layout
+                    .deployment_zones
+                    .get(participant)
+                    .into_iter()
+                    .flatten()
+                    .filter(|tile| {
+                        map_layout.can_deploy(*participant, tile)
+                            && !unit_registry.units.contains_key(tile)
+                    })
+                    .copied()
+                    .collect();
+                zone.sort_unstable();
+                let (x, y) = zone.first().copied().unwrap_or_else(|| {
+                    let (mut x, mut y) = (-1, -1);
+                    loop {
+                        x += 1;
+                        y += 1;
+                        if map_layout.tiles.contains_key(&(x, y))
+                            && !unit_registry.units.contains_key(&(x, y))
+                        {
+                            break (x, y);
+                        }
+                    }
+                });
+                (*participant, *kind, (x, y))
+            }
+            _ => continue,
+        };
+        let Some(height) = map_layout.tiles.get(&tile) else {
+            warn!("can't place a unit at {tile:?}, the tile is not part of the map");
+            continue;
+        };
+        let entity = place_unit(
+            (tile.0, tile.1, *height as i32),
+            participant,
+            kind,
+            &unit_assets,
+            &mut commands,
+        );
+        unit_registry.units.insert(tile, entity);
+        event_writer.send(GameStateEvent::SpawnedUnit(entity));
+    }
+}
+
+fn process_unit_event(
+    mut event_reader: EventReader<GameEvent>,
+    mut events: UnitEvents,
+    mut unit_registry: ResMut<UnitRegistry>,
+    map_layout: Res<MapLayout>,
+    mut units: Query<(&mut Unit, &mut StatusEffects)>,
+    mut combat_rng: ResMut<CombatRng>,
     mut commands: Commands,
 ) {
     for event in event_reader.iter() {
         match event {
-            GameEvent::SpawnUnit(x, y, participant) => {
-                place_ogre(
-                    *x,
-                    *y,
-                    *participant,
-                    1,
-                    &game_assets,
+            // handled by spawn_units
+            GameEvent::SpawnUnit(_, _, _, _) | GameEvent::PlaceAiUnit(_, _) => {}
+            // bots decide what to do in the ai module
+            GameEvent::AiTurn(_) => {}
+            // handled by the abilities module
+            GameEvent::UseAbility(_, _, _) => {}
+            // defending only lasts until the unit's next turn
+            GameEvent::TurnStarted(entity) => {
+                if let Ok((mut unit, _)) = units.get_mut(*entity) {
+                    unit.defending = false;
+                }
+            }
+            GameEvent::Attack(attacker, target) => {
+                let Ok(
+                    [(mut attacker_unit, mut attacker_effects), (mut target_unit, mut target_effects)],
+                ) = units.get_many_mut([*attacker, *target])
+                else {
+                    continue;
+                };
+                let forecast = forecast_attack(
+                    (&*attacker_unit, &*attacker_effects),
+                    (&*target_unit, &*target_effects),
                     &map_layout,
-                    &mut commands,
+                );
+                events
+                    .feedback
+                    .send(CombatFeedback::Attack(*attacker, target_unit.tile()));
+                if combat_rng.0.chance(forecast.attack.hit_chance as u64) {
+                    let damage = target_effects.absorb(forecast.attack.damage);
+                    damage_unit(
+                        *target,
+                        &mut target_unit,
+                        damage,
+                        &mut unit_registry,
+                        &mut commands,
+                        &mut events,
+                    );
+                } else {
+                    events.feedback.send(CombatFeedback::Miss(*target));
+                }
+                if let Some(counter) = forecast.counter.filter(|_| target_unit.health > 0) {
+                    events
+                        .feedback
+                        .send(CombatFeedback::Attack(*target, attacker_unit.tile()));
+                    if combat_rng.0.chance(counter.hit_chance as u64) {
+                        let damage = attacker_effects.absorb(counter.damage);
+                        damage_unit(
+                            *attacker,
+                            &mut attacker_unit,
+                            damage,
+                            &mut unit_registry,
+                            &mut commands,
+                            &mut events,
+                        );
+                    } else {
+                        events.feedback.send(CombatFeedback::Miss(*attacker));
+                    }
+                }
+                // dying already ends the attacker's turn
+                if attacker_unit.health > 0 {
+                    events.state.send(GameStateEvent::Attacked);
+                }
+            }
+            GameEvent::Damage(entity, damage) => {
+                let Ok((mut unit, _)) = units.get_mut(*entity) else {
+                    continue;
+                };
+                damage_unit(
+                    *entity,
+                    &mut unit,
+                    *damage,
                     &mut unit_registry,
-                    &mut event_writer,
+                    &mut commands,
+                    &mut events,
                 );
             }
-            GameEvent::PlaceAiUnit(participant) => {
-                let (mut x, mut y) = (-1, -1);
-                loop {
-                    x += 1;
-                    y += 1;
-                    if map_layout.tiles.contains_key(&(x, y))
-                        && !unit_registry.units.contains_key(&(x, y))
-                    {
-                        break;
-                    }
+            // handled by the status effects module
+            GameEvent::ApplyEffect(_, _) => {}
+            GameEvent::Knockback(entity, direction, distance) => {
+                let Ok((mut unit, _)) = units.get_mut(*entity) else {
+                    continue;
+                };
+                if unit.health == 0 {
+                    continue;
                 }
-                place_ogre(
-                    x,
-                    y,
-                    *participant,
-                    0,
-                    &game_assets,
+                let from = unit.tile();
+                let knockback = knockback(
+                    from,
+                    *direction,
+                    *distance,
+                    &unit,
                     &map_layout,
-                    &mut commands,
+                    &unit_registry,
+                );
+
+                let start = unit.position();
+                let destination = knockback.destination;
+                unit_registry.units.remove(&from);
+                let height = map_layout.tiles.get(&destination).copied();
+                unit.x = destination.0 as f32;
+                unit.y = destination.1 as f32;
+                unit.z = height.unwrap_or_default() as f32;
+
+                // pushed over the edge, the unit is gone for good. the same goes for a unit
+                // standing where the map has no tile
+                let fall = match (&knockback.stop, height) {
+                    (_, None) => Some(destination),
+                    (KnockbackStop::Gap(gap), _) if !unit.is_air => Some(*gap),
+                    _ => None,
+                };
+                if let Some(gap) = fall {
+                    unit.x = gap.0 as f32;
+                    unit.y = gap.1 as f32;
+                    unit.z -= FALL_DEPTH;
+                    unit.health = 0;
+                    unit.forced_movement = Some(ForcedMovement {
+                        offset: start - unit.position(),
+                        progress: 0.,
+                        facing: direction.opposite(),
+                        remove: true,
+                    });
+                    events.state.send(GameStateEvent::UnitDied(*entity));
+                    continue;
+                }
+
+                unit_registry.units.insert(destination, *entity);
+                unit.facing = direction.opposite();
+                unit.forced_movement = Some(ForcedMovement {
+                    offset: start - unit.position(),
+                    progress: 0.,
+                    facing: direction.opposite(),
+                    remove: false,
+                });
+                damage_unit(
+                    *entity,
+                    &mut unit,
+                    knockback.damage(),
                     &mut unit_registry,
-                    &mut event_writer,
+                    &mut commands,
+                    &mut events,
                 );
+
+                if let KnockbackStop::Unit(other) = knockback.stop {
+                    let Ok((mut other_unit, _)) = units.get_mut(other) else {
+                        continue;
+                    };
+                    damage_unit(
+                        other,
+                        &mut other_unit,
+                        COLLISION_DAMAGE,
+                        &mut unit_registry,
+                        &mut commands,
+                        &mut events,
+                    );
+                }
             }
         }
     }
 }
 
-fn place_ogre(
-    x: i32,
-    y: i32,
-    owner: usize,
-    turn_prio: i32,
-    game_assets: &Res<GameAssets>,
-    map_layout: &Res<MapLayout>,
-    commands: &mut Commands,
+fn damage_unit(
+    entity: Entity,
+    unit: &mut Unit,
+    damage: u32,
     unit_registry: &mut ResMut<UnitRegistry>,
-    event_writer: &mut EventWriter<GameStateEvent>,
+    commands: &mut Commands,
+    events: &mut UnitEvents,
 ) {
-    let texture = game_assets.units.get("ogre").unwrap().clone();
-    let z = *map_layout.tiles.get(&(x, y)).unwrap() as i32;
-    let ogre_idle = Animation::new(0.4, 192, 192, 64, 64, vec![(0, 5)], true);
-    let ogre_walk_down_right = Animation::new(
-        0.4,
-        192,
-        192,
-        64,
-        64,
-        vec![(0, 5), (1, 5), (2, 5), (3, 5)],
-        true,
-    );
-    let ogre_walk_down_left = Animation::new(
-        0.4,
-        192,
-        192,
-        64,
-        64,
-        vec![(0, 7), (1, 7), (2, 7), (3, 7)],
-        true,
-    );
-    let ogre_walk_up_left = Animation::new(
-        0.4,
-        192,
-        192,
-        64,
-        64,
-        vec![(0, 1), (1, 1), (2, 1), (3, 1)],
-        true,
-    );
-    let ogre_walk_up_right = Animation::new(
-        0.4,
-        192,
-        192,
-        64,
-        64,
-        vec![(0, 3), (1, 3), (2, 3), (3, 3)],
-        true,
-    );
+    if unit.health == 0 {
+        // already died this frame
+        return;
+    }
+
+    if damage > 0 {
+        events.feedback.send(CombatFeedback::Damage(entity, damage));
+    }
+    unit.health = unit.health.saturating_sub(damage);
+    if unit.health == 0 {
+        // a moving unit is registered where it started
+        unit_registry.units.retain(|_, other| *other != entity);
+        // the body stays behind until it faded out
+        commands.entity(entity).despawn_descendants();
+        commands
+            .entity(entity)
+            .remove::<(Unit, Pickable, StatusEffects, Abilities)>()
+            .insert(Corpse::new(unit.death_state()));
+        events.state.send(GameStateEvent::UnitDied(entity));
+    }
+}
+
+// After a map reload units stay where they are, unless their tile is gone
+fn keep_units_on_map(
+    mut commands: Commands,
+    mut reloaded: EventReader<MapReloaded>,
+    mut units: Query<(Entity, &mut Unit)>,
+    map_layout: Res<MapLayout>,
+    mut unit_registry: ResMut<UnitRegistry>,
+    mut map_state: ResMut<MapState>,
+    mut event_writer: EventWriter<GameStateEvent>,
+) {
+    if reloaded.iter().count() == 0 {
+        return;
+    }
+
+    for (entity, mut unit) in units.iter_mut() {
+        // moving units are checked where they will end up
+        let tile = unit
+            .path
+            .as_ref()
+            .and_then(|(_, path)| path.last().copied())
+            .unwrap_or(unit.tile());
+        if let Some(height) = map_layout.tiles.get(&tile) {
+            if unit.path.is_none() {
+                unit.z = *height as f32;
+            }
+            continue;
+        }
+
+        warn!("removing unit at {tile:?}, its tile is no longer part of the map");
+        unit_registry.units.retain(|_, other| *other != entity);
+        if unit.path.is_some() {
+            map_state.unit_moving = false;
+        }
+        commands.entity(entity).despawn_recursive();
+        event_writer.send(GameStateEvent::UnitDied(entity));
+    }
+}
+
+fn walk_state(direction: IsometricDirection) -> String {
+    format!("walk_{}", direction.name())
+}
+
+fn place_unit(
+    tile: (i32, i32, i32),
+    owner: usize,
+    kind: UnitKind,
+    unit_assets: &UnitAssets,
+    commands: &mut Commands,
+) -> Entity {
     let entity = generate_entity(
-        x,
-        y,
-        z,
+        tile,
         owner,
-        turn_prio,
+        kind,
         commands,
-        texture,
-        ogre_idle,
-        ogre_walk_up_left,
-        ogre_walk_up_right,
-        ogre_walk_down_left,
-        ogre_walk_down_right,
+        unit_assets.texture(kind),
+        unit_assets.animatable(kind),
     );
 
-    unit_registry.units.insert((x, y), entity);
-    event_writer.send(GameStateEvent::SpawnedUnit(entity));
+    if kind.is_air() {
+        let shadow = commands
+            .spawn((
+                SpriteBundle {
+                    texture: unit_assets.game_assets.shadow.clone(),
+                    sprite: Sprite {
+                        color: Color::rgba(1., 1., 1., 0.4),
+                        ..default()
+                    },
+                    ..default()
+                },
+                UnitShadow,
+            ))
+            .id();
+        commands.entity(entity).add_child(shadow);
+    }
+    entity
 }
 
 fn update_unit_transform(
     tilemaps: Res<Assets<TiledMap>>,
     game_assets: Res<GameAssets>,
-    mut units: Query<(&mut Transform, &Unit)>,
+    map_layout: Res<MapLayout>,
+    mut units: Query<(&mut Transform, &Unit, Option<&Children>), Without<UnitShadow>>,
+    mut shadows: Query<(&mut Transform, &mut Visibility), With<UnitShadow>>,
 ) {
     let tilemap = tilemaps.get(&game_assets.map).unwrap();
     let (tile_w, tile_h) = (tilemap.tilewidth as f32, tilemap.tileheight as f32);
 
-    for (mut transform, unit) in units.iter_mut() {
-        transform.translation = iso_transform(unit.x, unit.y, unit.z, tile_w, tile_h, true);
+    for (mut transform, unit, children) in units.iter_mut() {
+        let mut position = unit.position();
+        if let Some(forced_movement) = &unit.forced_movement {
+            position += (1. - forced_movement.progress) * forced_movement.offset;
+        }
+        transform.translation = iso_transform(
+            position.x,
+            position.y,
+            position.z + unit.altitude(),
+            tile_w,
+            tile_h,
+            true,
+        );
         if let Some(render_prio) = unit.render_priority {
             transform.translation.z = render_prio;
         }
+        // sort like the higher end of the knockback
+        if let Some(forced_movement) = &unit.forced_movement {
+            let start = unit.position() + forced_movement.offset;
+            let prio =
+                |position: Vec3| iso_transform(position.x, position.y, position.z, 1., 1., true).z;
+            transform.translation.z = max(prio(start), prio(unit.position())) + unit.altitude();
+        }
+
+        // the shadow stays on the ground below the unit and disappears over gaps
+        for child in children.into_iter().flatten() {
+            let Ok((mut shadow_transform, mut visibility)) = shadows.get_mut(*child) else {
+                continue;
+            };
+            let tile = (position.x.round() as i32, position.y.round() as i32);
+            let Some(ground) = map_layout.tiles.get(&tile) else {
+                *visibility = Visibility::Hidden;
+                continue;
+            };
+            *visibility = Visibility::Inherited;
+
+            let ground =
+                iso_transform(position.x, position.y, *ground as f32, tile_w, tile_h, true);
+            // just above the tile, but below the unit
+            let offset = ground - transform.translation - Vec3::new(0., 0., 0.25);
+            shadow_transform.translation = offset / transform.scale;
+        }
+    }
+}
+
+fn animate_forced_movement(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut units: Query<(Entity, &mut Unit, &mut Animatable)>,
+) {
+    for (entity, mut unit, mut animatable) in units.iter_mut() {
+        let Some(forced_movement) = &unit.forced_movement else {
+            continue;
+        };
+        let (progress, facing, remove) = (
+            forced_movement.progress,
+            forced_movement.facing,
+            forced_movement.remove,
+        );
+        // stumbling backwards, facing whoever knocked the unit back
+        if progress == 0. {
+            animatable.play(&walk_state(facing));
+        }
+
+        let length = forced_movement.offset.length().max(1.);
+        let progress = progress + KNOCKBACK_SPEED * time.delta_seconds() / length;
+        if progress < 1. {
+            unit.forced_movement.as_mut().unwrap().progress = progress;
+            continue;
+        }
+
+        unit.forced_movement = None;
+        if remove {
+            commands.entity(entity).despawn_recursive();
+        } else {
+            animatable.play("idle");
+        }
     }
 }
 
+// Flying units keep their height while crossing gaps
+fn travel_height(path: &[(i32, i32)], waypoint: usize, map_layout: &MapLayout) -> f32 {
+    path[..=waypoint]
+        .iter()
+        .rev()
+        .find_map(|tile| map_layout.tiles.get(tile))
+        .map_or(0., |height| *height as f32)
+}
+
 fn move_units(
     mut units: Query<(&mut Unit, &mut Animatable, Entity)>,
     time: Res<Time>,
@@ -260,12 +823,17 @@ fn move_units(
     mut map_state: ResMut<MapState>,
     mut event_writer: EventWriter<GameStateEvent>,
 ) {
+    let owners: HashMap<Entity, usize> = units
+        .iter()
+        .map(|(unit, _, entity)| (entity, unit.owner))
+        .collect();
+
     for (mut unit, mut animatable, entity) in units.iter_mut() {
         if unit.path.is_none() {
             continue;
         }
-        let path = std::mem::replace(&mut unit.path, None);
-        let (mut current_waypoint, path) = path.unwrap();
+        let path = unit.path.take();
+        let (mut current_waypoint, mut path) = path.unwrap();
 
         let waypoint_current = path[current_waypoint as usize];
         let waypoint_next = path[current_waypoint as usize + 1];
@@ -280,22 +848,19 @@ fn move_units(
                     waypoint_next.1 - waypoint_current.1,
                 ))
                 .unwrap();
-                let animation = match dir {
-                    IsometricDirection::UpRight => unit.move_up_right.clone(),
-                    IsometricDirection::UpLeft => unit.move_up_left.clone(),
-                    IsometricDirection::DownRight => unit.move_down_right.clone(),
-                    IsometricDirection::DownLeft => unit.move_down_left.clone(),
-                };
-                animatable.play(animation, true);
+                unit.facing = dir;
+                animatable.play(&walk_state(dir));
                 0.
             }
         };
 
+        let height_current = travel_height(&path, current_waypoint as usize, &map_layout);
+        let height_next = travel_height(&path, current_waypoint as usize + 1, &map_layout);
         if unit.render_priority.is_none() {
             let prio_1 = iso_transform(
                 waypoint_current.0 as f32,
                 waypoint_current.1 as f32,
-                map_layout.tiles[&(waypoint_current.0, waypoint_current.1)] as f32,
+                height_current + unit.altitude(),
                 1.,
                 1.,
                 true,
@@ -304,7 +869,7 @@ fn move_units(
             let prio_2 = iso_transform(
                 waypoint_next.0 as f32,
                 waypoint_next.1 as f32,
-                map_layout.tiles[&(waypoint_next.0, waypoint_next.1)] as f32,
+                height_next + unit.altitude(),
                 1.,
                 1.,
                 true,
@@ -324,15 +889,53 @@ fn move_units(
             unit.path_progress = None;
             // without this line unit gets set to next waypoint for 1 frame
             progress = 0.;
+
+            // an enemy hidden in the fog stops the unit once it walks next to it, like a zone of
+            // control it couldn't see. flying units pass over it unless it stands on their destination
+            let reached = path[current_waypoint as usize];
+            let is_enemy_at = |tile: &(i32, i32)| {
+                unit_registry
+                    .units
+                    .get(tile)
+                    .is_some_and(|other| owners.get(other) != Some(&unit.owner))
+            };
+            let ambushed = match path.get(current_waypoint as usize + 1) {
+                None => false,
+                Some(next) if unit.is_air => {
+                    current_waypoint as usize + 2 == path.len() && is_enemy_at(next)
+                }
+                Some(_) => neighbours(&reached).iter().any(is_enemy_at),
+            };
+            if ambushed {
+                path.truncate(current_waypoint as usize + 1);
+                // never stop on top of a friendly unit or above a gap, the unit turns back to the
+                // last tile it can stop on instead. it started on one
+                let can_stop = |tile: &(i32, i32)| {
+                    map_layout.tiles.contains_key(tile)
+                        && unit_registry
+                            .units
+                            .get(tile)
+                            .is_none_or(|other| *other == entity)
+                };
+                let stop = path.iter().rposition(can_stop).unwrap_or(0);
+                let retreat: Vec<(i32, i32)> = path[stop..current_waypoint as usize]
+                    .iter()
+                    .rev()
+                    .copied()
+                    .collect();
+                path.extend(retreat);
+            }
+
             if current_waypoint as usize == path.len() - 1 {
                 let last_waypoint = path.last().unwrap();
                 unit.x = last_waypoint.0 as f32;
                 unit.y = last_waypoint.1 as f32;
+                unit.z = travel_height(&path, current_waypoint as usize, &map_layout);
                 unit_registry.units.remove(&path[0]);
                 unit_registry.units.insert(*last_waypoint, entity);
                 map_state.unit_moving = false;
-                animatable.play(unit.idle.clone(), true);
-                event_writer.send(GameStateEvent::MovedUnit(entity));
+                animatable.play("idle");
+                event_writer.send(GameStateEvent::MovedUnit);
                 continue;
             }
         } else {
@@ -344,6 +947,8 @@ fn move_units(
             + progress * path[current_waypoint as usize + 1].0 as f32;
         unit.y = (1. - progress) * path[current_waypoint as usize].1 as f32
             + progress * path[current_waypoint as usize + 1].1 as f32;
+        unit.z = (1. - progress) * travel_height(&path, current_waypoint as usize, &map_layout)
+            + progress * travel_height(&path, current_waypoint as usize + 1, &map_layout);
 
         unit.path = Some((current_waypoint, path));
     }
@@ -0,0 +1,118 @@
+use std::collections::{HashMap, HashSet};
+
+use bevy::prelude::*;
+
+use crate::AppState;
+
+use super::{
+    combat::has_line_of_sight,
+    game_state::GameState,
+    map::MapLayout,
+    unit::{Unit, UnitRegistry},
+    GameSystemSets,
+};
+
+pub struct VisionPlugin;
+
+impl Plugin for VisionPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Vision::default());
+        app.add_systems((
+            reset_vision.in_schedule(OnExit(AppState::Game)),
+            update_vision.in_set(GameSystemSets::Update),
+            hide_units.in_set(GameSystemSets::Render),
+        ));
+    }
+}
+
+// What every participant currently sees and has seen so far
+#[derive(Resource, Default)]
+pub struct Vision {
+    visible: HashMap<usize, HashSet<(i32, i32)>>,
+    explored: HashMap<usize, HashSet<(i32, i32)>>,
+}
+
+impl Vision {
+    pub fn can_see(&self, participant: usize, tile: &(i32, i32)) -> bool {
+        self.visible
+            .get(&participant)
+            .is_some_and(|visible| visible.contains(tile))
+    }
+
+    pub fn has_explored(&self, participant: usize, tile: &(i32, i32)) -> bool {
+        self.explored
+            .get(&participant)
+            .is_some_and(|explored| explored.contains(tile))
+    }
+
+    // Units of other participants standing in the fog don't exist for this participant
+    pub fn can_see_unit(&self, participant: usize, unit: &Unit) -> bool {
+        unit.owner == participant || self.can_see(participant, &unit.tile())
+    }
+}
+
+fn reset_vision(mut vision: ResMut<Vision>) {
+    *vision = Vision::default();
+}
+
+fn update_vision(units: Query<&Unit>, map_layout: Res<MapLayout>, mut vision: ResMut<Vision>) {
+    let mut visible: HashMap<usize, HashSet<(i32, i32)>> = HashMap::new();
+    for unit in units.iter() {
+        let location = unit.tile();
+        let radius = unit.sight_radius as i32;
+        let seen = visible.entry(unit.owner).or_default();
+
+        for dx in -radius..=radius {
+            let remaining = radius - dx.abs();
+            for dy in -remaining..=remaining {
+                let tile = (location.0 + dx, location.1 + dy);
+                if !seen.contains(&tile)
+                    && map_layout.tiles.contains_key(&tile)
+                    && has_line_of_sight(&location, &tile, &map_layout)
+                {
+                    seen.insert(tile);
+                }
+            }
+        }
+    }
+
+    for (participant, seen) in visible.iter() {
+        vision
+            .explored
+            .entry(*participant)
+            .or_default()
+            .extend(seen.iter());
+    }
+    vision.visible = visible;
+}
+
+fn hide_units(
+    game_state: Res<GameState>,
+    vision: Res<Vision>,
+    mut units: Query<(&Unit, &mut Visibility)>,
+) {
+    let Some(me) = game_state.local_participant() else {
+        return;
+    };
+
+    for (unit, mut visibility) in units.iter_mut() {
+        *visibility = if vision.can_see_unit(me, unit) {
+            Visibility::Inherited
+        } else {
+            Visibility::Hidden
+        };
+    }
+}
+
+// Looks up the unit on a tile the way the participant sees the map
+pub fn visible_unit_at(
+    tile: &(i32, i32),
+    participant: usize,
+    vision: &Vision,
+    unit_registry: &UnitRegistry,
+    units: &Query<&Unit>,
+) -> Option<Entity> {
+    let entity = unit_registry.units.get(tile)?;
+    let unit = units.get(*entity).ok()?;
+    vision.can_see_unit(participant, unit).then_some(*entity)
+}
@@ -1,19 +1,50 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
-use bevy::prelude::{Handle, Image, Resource};
+use bevy::prelude::{Font, Handle, Image, Resource, TextureAtlas};
 
-use crate::assets::types::{TiledMap, TiledSet};
+use crate::{
+    assets::{
+        animation_sheet::AnimationSheet,
+        types::{TiledMap, TiledSet},
+    },
+    game::picking::Pickable,
+};
+
+#[derive(Clone, PartialEq)]
+pub enum MapChoice {
+    // asset path of a handmade map
+    File(String),
+    // procedurally generated from a seed
+    Generated(u64),
+}
 
 #[derive(Resource)]
 pub struct GameConfig {
-    pub tileset: String,
-    pub map: String,
+    pub map: MapChoice,
+    // open the map in the editor instead of starting a match
+    pub editor: bool,
 }
 
 #[derive(Resource)]
 pub struct GameAssets {
     pub map: Handle<TiledMap>,
-    pub tileset: Handle<TiledSet>,
-    pub tiles: Vec<Handle<Image>>,
+    pub tilesets: Vec<Handle<TiledSet>>,
+    // by gid
+    pub tiles: HashMap<u32, TileTexture>,
+    // by gid, before flipping
+    pub tile_pickables: HashMap<u32, Pickable>,
+    // by gid, tiles nothing can enter
+    pub impassable_tiles: HashSet<u32>,
     pub units: HashMap<String, Handle<Image>>,
+    // by the same name as the unit's texture
+    pub unit_animations: HashMap<String, Handle<AnimationSheet>>,
+    pub font: Handle<Font>,
+    pub shadow: Handle<Image>,
+}
+
+#[derive(Clone)]
+pub enum TileTexture {
+    Image(Handle<Image>),
+    // atlas, index
+    Atlas(Handle<TextureAtlas>, usize),
 }
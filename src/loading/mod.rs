@@ -1,93 +1,487 @@
 use std::collections::HashMap;
 
-use bevy::prelude::*;
+use bevy::{
+    asset::LoadState,
+    ecs::system::SystemParam,
+    prelude::*,
+    render::render_resource::{Extent3d, TextureDimension, TextureFormat},
+};
 
 use crate::{
-    assets::types::{TiledMap, TiledSet},
-    game_config::{GameAssets, GameConfig},
+    assets::{
+        animation_sheet::{AnimationSheet, AnimationSheetError},
+        types::{TiledMap, TiledSet},
+        AssetErrors,
+    },
+    game::{
+        game_state::GameState,
+        map::impassable_tiles,
+        map_generator::generate_map,
+        map_validation::{validate_map, MapError},
+        picking::tile_pickables,
+    },
+    game_config::{GameAssets, GameConfig, MapChoice, TileTexture},
     AppState,
 };
 
 #[derive(Resource)]
 struct LoadingResource {
     map: Handle<TiledMap>,
-    tileset: Handle<TiledSet>,
+    // with their first gid, known once the map is loaded
+    tilesets: Option<Vec<(u32, Handle<TiledSet>)>>,
     units: HashMap<String, Handle<Image>>,
-    tiles: Option<Vec<Handle<Image>>>,
+    unit_animations: HashMap<String, Handle<AnimationSheet>>,
+    tiles: Option<HashMap<u32, TileTexture>>,
+    font: Handle<Font>,
+    // generated, so it is not part of all
+    shadow: Handle<Image>,
 
     all: Vec<HandleUntyped>,
+    // filled once everything is loaded
+    map_errors: Option<Vec<MapError>>,
+    // unit animations whose frames don't fit their sprite sheet, filled once everything is loaded
+    sheet_errors: Option<Vec<AnimationSheetError>>,
 }
 
+const BAR_WIDTH: f32 = 400.;
+const ERROR_COLOR: Color = Color::rgb(1.0, 0.4, 0.4);
+
+#[derive(Component)]
+struct LoadingScreen;
+
+#[derive(Component)]
+struct ProgressBar;
+
+#[derive(Component)]
+struct LoadingStatus;
+
+#[derive(Component)]
+struct BackToMenuButton;
+
 pub struct LoadingPlugin;
 
 impl Plugin for LoadingPlugin {
     fn build(&self, app: &mut App) {
         app.add_system(start_loading.in_schedule(OnEnter(AppState::Loading)));
-        app.add_system(load.in_set(OnUpdate(AppState::Loading)));
+        app.add_system(stop_loading.in_schedule(OnExit(AppState::Loading)));
+        app.add_systems(
+            (load, update_loading_screen, back_to_menu).in_set(OnUpdate(AppState::Loading)),
+        );
     }
 }
 
-fn start_loading(mut command: Commands, assets: Res<AssetServer>, game_config: Res<GameConfig>) {
-    let tileset_h: Handle<TiledSet> = assets.load(&game_config.tileset);
-    let map_h: Handle<TiledMap> = assets.load(&game_config.map);
+fn start_loading(
+    mut command: Commands,
+    assets: Res<AssetServer>,
+    mut images: ResMut<Assets<Image>>,
+    mut tilemaps: ResMut<Assets<TiledMap>>,
+    game_config: Res<GameConfig>,
+) {
+    let map_h: Handle<TiledMap> = match &game_config.map {
+        MapChoice::File(path) => assets.load(path),
+        MapChoice::Generated(seed) => tilemaps.add(generate_map(*seed)),
+    };
 
     // units
     let ogre_unit = assets.load("units/ogre.png");
+    let ogre_animations: Handle<AnimationSheet> = assets.load("units/ogre.anim.json");
+
+    let font = assets.load("fonts/DejaVuSans.ttf");
+
+    spawn_loading_screen(&mut command, &font);
+
+    let mut all = vec![
+        ogre_unit.clone_untyped(),
+        ogre_animations.clone_untyped(),
+        font.clone_untyped(),
+    ];
+    // generated maps exist right away, their tilesets are loaded like any other map's
+    if let MapChoice::File(_) = game_config.map {
+        all.push(map_h.clone_untyped());
+    }
 
     let resource = LoadingResource {
-        all: vec![
-            map_h.clone_untyped(),
-            tileset_h.clone_untyped(),
-            ogre_unit.clone_untyped(),
-        ],
+        all,
         map: map_h,
-        tileset: tileset_h,
+        tilesets: None,
         tiles: None,
         units: HashMap::from([("ogre".to_string(), ogre_unit.clone())]),
+        unit_animations: HashMap::from([("ogre".to_string(), ogre_animations)]),
+        font,
+        shadow: images.add(shadow_image()),
+        map_errors: None,
+        sheet_errors: None,
     };
 
     command.insert_resource(resource);
 }
 
+fn spawn_loading_screen(commands: &mut Commands, font: &Handle<Font>) {
+    let text_style = TextStyle {
+        font: font.clone(),
+        font_size: 22.,
+        color: Color::WHITE,
+    };
+
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    size: Size::new(Val::Percent(100.), Val::Percent(100.)),
+                    flex_direction: FlexDirection::Column,
+                    justify_content: JustifyContent::Center,
+                    align_items: AlignItems::Center,
+                    gap: Size::height(Val::Px(20.)),
+                    ..default()
+                },
+                ..default()
+            },
+            LoadingScreen,
+        ))
+        .with_children(|parent| {
+            parent
+                .spawn(NodeBundle {
+                    style: Style {
+                        size: Size::new(Val::Px(BAR_WIDTH), Val::Px(24.)),
+                        ..default()
+                    },
+                    background_color: Color::rgb(0.2, 0.2, 0.2).into(),
+                    ..default()
+                })
+                .with_children(|bar| {
+                    bar.spawn((
+                        NodeBundle {
+                            style: Style {
+                                size: Size::new(Val::Percent(0.), Val::Percent(100.)),
+                                ..default()
+                            },
+                            background_color: Color::rgb(0.4, 0.7, 1.0).into(),
+                            ..default()
+                        },
+                        ProgressBar,
+                    ));
+                });
+
+            parent.spawn((
+                TextBundle::from_section("", text_style.clone())
+                    .with_text_alignment(TextAlignment::Center),
+                LoadingStatus,
+            ));
+
+            // only shown once something failed to load
+            parent
+                .spawn((
+                    ButtonBundle {
+                        style: Style {
+                            size: Size::new(Val::Px(200.), Val::Px(50.)),
+                            justify_content: JustifyContent::Center,
+                            align_items: AlignItems::Center,
+                            ..default()
+                        },
+                        background_color: Color::rgb(0.2, 0.2, 0.2).into(),
+                        visibility: Visibility::Hidden,
+                        ..default()
+                    },
+                    BackToMenuButton,
+                ))
+                .with_children(|button| {
+                    button.spawn(TextBundle::from_section("Back to menu", text_style));
+                });
+        });
+}
+
+// Soft black ellipse drawn below flying units
+fn shadow_image() -> Image {
+    let (width, height) = (64, 32);
+    let mut data = Vec::with_capacity(width * height * 4);
+    for y in 0..height {
+        for x in 0..width {
+            let dx = (x as f32 + 0.5) / width as f32 * 2. - 1.;
+            let dy = (y as f32 + 0.5) / height as f32 * 2. - 1.;
+            let alpha = (1. - (dx * dx + dy * dy)).clamp(0., 1.);
+            data.extend_from_slice(&[0, 0, 0, (alpha * 255.) as u8]);
+        }
+    }
+
+    Image::new(
+        Extent3d {
+            width: width as u32,
+            height: height as u32,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8UnormSrgb,
+    )
+}
+
+// The asset server and the Tiled files loaded through it
+#[derive(SystemParam)]
+pub(crate) struct TiledAssets<'w> {
+    pub assets: Res<'w, AssetServer>,
+    pub tilemaps: Res<'w, Assets<TiledMap>>,
+    pub tilesets: Res<'w, Assets<TiledSet>>,
+    pub atlases: ResMut<'w, Assets<TextureAtlas>>,
+}
+
+// The unit sprite sheets and the animations cut from them
+#[derive(SystemParam)]
+struct UnitSheets<'w> {
+    images: Res<'w, Assets<Image>>,
+    sheets: Res<'w, Assets<AnimationSheet>>,
+}
+
+impl UnitSheets<'_> {
+    fn validate(&self, loading: &LoadingResource) -> Vec<AnimationSheetError> {
+        loading
+            .unit_animations
+            .iter()
+            .filter_map(|(kind, sheet)| {
+                let image = self.images.get(loading.units.get(kind)?)?;
+                self.sheets.get(sheet)?.validate_size(image.size()).err()
+            })
+            .collect()
+    }
+}
+
 fn load(
-    assets: Res<AssetServer>,
-    tilesets: Res<Assets<TiledSet>>,
+    mut tiled: TiledAssets,
+    unit_sheets: UnitSheets,
+    game_state: Res<GameState>,
+    game_config: Res<GameConfig>,
     mut loading: ResMut<LoadingResource>,
     mut next_state: ResMut<NextState<AppState>>,
     mut command: Commands,
 ) {
-    let tileset = match tilesets.get(&loading.tileset) {
-        Some(tileset) => tileset,
-        _ => return,
+    let Some(tilemap) = tiled.tilemaps.get(&loading.map) else {
+        return;
     };
 
+    if loading.tilesets.is_none() {
+        let handles: Vec<(u32, Handle<TiledSet>)> = tilemap
+            .tilesets
+            .iter()
+            .map(|tileset| (tileset.firstgid, tiled.assets.load(tileset.path.as_path())))
+            .collect();
+        loading
+            .all
+            .extend(handles.iter().map(|(_, handle)| handle.clone_untyped()));
+        loading.tilesets = Some(handles);
+    }
+
     if loading.tiles.is_none() {
-        loading.tiles = Some(
-            tileset
-                .tiles
-                .iter()
-                .map(|tile| {
-                    let tile = assets.load(tile.path());
-                    loading.all.push(tile.clone_untyped());
-                    tile
-                })
-                .collect(),
-        );
+        let Some((tiles, images)) = tile_textures(
+            loading.tilesets.as_ref().unwrap(),
+            &tiled.tilesets,
+            &tiled.assets,
+            &mut tiled.atlases,
+        ) else {
+            return;
+        };
+        loading.all.extend(images);
+        loading.tiles = Some(tiles);
     }
 
     for item in &loading.all {
-        match assets.get_load_state(item) {
-            bevy::asset::LoadState::Loaded => (),
+        match tiled.assets.get_load_state(item) {
+            LoadState::Loaded => (),
             _ => return,
         }
     }
 
+    if loading.sheet_errors.is_none() {
+        let errors = unit_sheets.validate(&loading);
+        for error in &errors {
+            warn!("{error}");
+        }
+        loading.sheet_errors = Some(errors);
+    }
+    if loading
+        .sheet_errors
+        .as_ref()
+        .is_some_and(|errors| !errors.is_empty())
+    {
+        return;
+    }
+
+    let map_tilesets: Vec<(u32, &TiledSet)> = loading
+        .tilesets
+        .iter()
+        .flatten()
+        .filter_map(|(firstgid, handle)| Some((*firstgid, tiled.tilesets.get(handle)?)))
+        .collect();
+    if loading.map_errors.is_none() {
+        let errors = validate_map(
+            tilemap,
+            &map_tilesets,
+            game_state.participants.len(),
+            game_state.units_per_participant,
+        );
+        for error in &errors {
+            warn!("invalid map: {error}");
+        }
+        loading.map_errors = Some(errors);
+    }
+    // the editor is where broken maps get fixed
+    if !game_config.editor
+        && loading
+            .map_errors
+            .as_ref()
+            .is_some_and(|errors| !errors.is_empty())
+    {
+        return;
+    }
+
     command.insert_resource(GameAssets {
         map: loading.map.clone(),
-        tileset: loading.tileset.clone(),
+        tilesets: loading
+            .tilesets
+            .iter()
+            .flatten()
+            .map(|(_, handle)| handle.clone())
+            .collect(),
         tiles: loading.tiles.clone().unwrap(),
+        tile_pickables: tile_pickables(tilemap, &map_tilesets),
+        impassable_tiles: impassable_tiles(&map_tilesets),
         units: loading.units.clone(),
+        unit_animations: loading.unit_animations.clone(),
+        font: loading.font.clone(),
+        shadow: loading.shadow.clone(),
+    });
+    next_state.set(if game_config.editor {
+        AppState::Editor
+    } else {
+        AppState::Game
     });
-    command.remove_resource::<LoadingResource>();
-    next_state.set(AppState::Game);
+}
+
+// Textures by gid and the images they need, None until every tileset is loaded
+pub(crate) fn tile_textures(
+    map_tilesets: &[(u32, Handle<TiledSet>)],
+    tilesets: &Assets<TiledSet>,
+    assets: &AssetServer,
+    atlases: &mut Assets<TextureAtlas>,
+) -> Option<(HashMap<u32, TileTexture>, Vec<HandleUntyped>)> {
+    let mut tiles = HashMap::new();
+    let mut images: Vec<HandleUntyped> = vec![];
+    for (firstgid, handle) in map_tilesets {
+        let tileset = tilesets.get(handle)?;
+
+        if let Some(image_path) = &tileset.image_path {
+            let image: Handle<Image> = assets.load(image_path.as_path());
+            images.push(image.clone_untyped());
+            let rows = tileset.tilecount.div_ceil(tileset.columns);
+            let atlas = atlases.add(TextureAtlas::from_grid(
+                image,
+                Vec2::new(tileset.tilewidth as f32, tileset.tileheight as f32),
+                tileset.columns as usize,
+                rows as usize,
+                Some(Vec2::splat(tileset.spacing as f32)),
+                Some(Vec2::splat(tileset.margin as f32)),
+            ));
+            for id in 0..tileset.tilecount {
+                tiles.insert(
+                    firstgid + id,
+                    TileTexture::Atlas(atlas.clone(), id as usize),
+                );
+            }
+        } else {
+            for tile in &tileset.tiles {
+                let Some(path) = &tile.path else {
+                    continue;
+                };
+                let image: Handle<Image> = assets.load(path.as_path());
+                images.push(image.clone_untyped());
+                tiles.insert(firstgid + tile.id, TileTexture::Image(image));
+            }
+        }
+    }
+    Some((tiles, images))
+}
+
+fn update_loading_screen(
+    assets: Res<AssetServer>,
+    asset_errors: Res<AssetErrors>,
+    loading: Res<LoadingResource>,
+    mut progress_bar: Query<&mut Style, With<ProgressBar>>,
+    mut status: Query<&mut Text, With<LoadingStatus>>,
+    mut back_button: Query<&mut Visibility, With<BackToMenuButton>>,
+) {
+    let mut loaded = 0;
+    let mut errors = vec![];
+    for item in &loading.all {
+        match assets.get_load_state(item) {
+            LoadState::Loaded => loaded += 1,
+            LoadState::Failed => {
+                let Some(path) = assets.get_handle_path(item) else {
+                    errors.push("Failed to load an unknown asset".to_string());
+                    continue;
+                };
+                let path = path.path();
+                errors.push(match asset_errors.get(path) {
+                    Some(error) => format!("Failed to load {}: {error}", path.display()),
+                    None => format!("Failed to load {}", path.display()),
+                });
+            }
+            _ => (),
+        }
+    }
+
+    errors.extend(
+        loading
+            .map_errors
+            .iter()
+            .flatten()
+            .map(|error| error.to_string()),
+    );
+    errors.extend(
+        loading
+            .sheet_errors
+            .iter()
+            .flatten()
+            .map(|error| error.to_string()),
+    );
+
+    // the list grows while loading, as the map decides which tiles are needed
+    let progress = loaded as f32 / loading.all.len().max(1) as f32;
+    for mut style in progress_bar.iter_mut() {
+        style.size.width = Val::Percent(progress * 100.);
+    }
+
+    for mut text in status.iter_mut() {
+        let section = &mut text.sections[0];
+        if errors.is_empty() {
+            section.value = format!("Loading... {}%", (progress * 100.) as u32);
+            section.style.color = Color::WHITE;
+        } else {
+            section.value = errors.join("\n");
+            section.style.color = ERROR_COLOR;
+        }
+    }
+
+    for mut visibility in back_button.iter_mut() {
+        *visibility = if errors.is_empty() {
+            Visibility::Hidden
+        } else {
+            Visibility::Inherited
+        };
+    }
+}
+
+fn back_to_menu(
+    buttons: Query<&Interaction, (Changed<Interaction>, With<BackToMenuButton>)>,
+    mut next_state: ResMut<NextState<AppState>>,
+) {
+    for interaction in buttons.iter() {
+        if *interaction == Interaction::Clicked {
+            next_state.set(AppState::Menu);
+        }
+    }
+}
+
+fn stop_loading(mut commands: Commands, screens: Query<Entity, With<LoadingScreen>>) {
+    for screen in screens.iter() {
+        commands.entity(screen).despawn_recursive();
+    }
+    commands.remove_resource::<LoadingResource>();
 }
@@ -3,8 +3,11 @@ use bevy::{
     diagnostic::{FrameTimeDiagnosticsPlugin, LogDiagnosticsPlugin},
     prelude::*,
 };
-use game::{picking::PickCamera, GamePlugin};
-use game_config::GameConfig;
+use game::{
+    game_state::GameState, map_generator::export_map, map_validation::validate_map_file,
+    picking::PickCamera, GamePlugin,
+};
+use game_config::{GameConfig, MapChoice};
 use loading::LoadingPlugin;
 use main_menu::MainMenuPlugin;
 
@@ -18,38 +21,88 @@ mod util;
 
 #[derive(Debug, Default, Clone, Eq, States, PartialEq, Hash)]
 pub enum AppState {
-    Menu,
     #[default]
+    Menu,
     Loading,
     Game,
+    Editor,
 }
 
 fn main() {
+    // cargo run -- --validate-map tilemap/1.tmj
+    // cargo run -- --export-map <seed>
+    let args: Vec<String> = std::env::args().collect();
+    if let [_, flag, argument] = args.as_slice() {
+        match flag.as_str() {
+            "--validate-map" => std::process::exit(validate_map(argument)),
+            "--export-map" => std::process::exit(export_generated_map(argument)),
+            _ => {}
+        }
+    }
+
     App::new()
         .add_state::<AppState>()
         .insert_resource(GameConfig {
-            map: "tilemap/1.tmj".to_string(),
-            tileset: "tileset/prototype/Map/map_tiles.tsj".to_string(),
+            map: MapChoice::File("tilemap/1.tmj".to_string()),
+            editor: false,
         })
-        .add_plugins(DefaultPlugins)
+        // maps and tilesets saved in Tiled show up right away in debug builds
+        .add_plugins(DefaultPlugins.set(AssetPlugin {
+            watch_for_changes: cfg!(debug_assertions),
+            ..default()
+        }))
         .add_plugin(AssetsPlugin)
         .add_plugin(LoadingPlugin)
         .add_plugin(GamePlugin)
         .add_plugin(MainMenuPlugin)
         // debugging
         .add_plugin(LogDiagnosticsPlugin::default())
-        .add_plugin(FrameTimeDiagnosticsPlugin::default())
+        .add_plugin(FrameTimeDiagnosticsPlugin)
         // end debugging
         .add_startup_system(spawn_camera)
         .run()
 }
 
+fn validate_map(map: &str) -> i32 {
+    let game_state = GameState::default();
+    let errors = validate_map_file(
+        map,
+        game_state.participants.len(),
+        game_state.units_per_participant,
+    );
+    if errors.is_empty() {
+        println!("{map} is valid");
+        return 0;
+    }
+    for error in &errors {
+        eprintln!("{map}: {error}");
+    }
+    1
+}
+
+fn export_generated_map(seed: &str) -> i32 {
+    let Ok(seed) = seed.parse() else {
+        eprintln!("the seed has to be a number");
+        return 1;
+    };
+    match export_map(seed) {
+        Ok(path) => {
+            println!("saved {}", path.display());
+            0
+        }
+        Err(error) => {
+            eprintln!("can't save the map: {error}");
+            1
+        }
+    }
+}
+
 fn spawn_camera(mut commands: Commands) {
     commands.spawn((
         Camera2dBundle {
             transform: Transform::from_xyz(0., 0., 1000.),
             ..Default::default()
         },
-        PickCamera::default(),
+        PickCamera,
     ));
 }
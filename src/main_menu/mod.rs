@@ -1,13 +1,214 @@
-use bevy::prelude::Plugin;
+use bevy::prelude::*;
+
+use crate::{
+    game::map_generator::export_map,
+    game_config::{GameConfig, MapChoice},
+    util::rng::Rng,
+    AppState,
+};
+
+// handmade maps the match setup cycles through before the generated one
+const HANDMADE_MAPS: [&str; 1] = ["tilemap/1.tmj"];
+
+const BUTTON_COLOR: Color = Color::rgb(0.2, 0.2, 0.2);
+const BUTTON_HOVER_COLOR: Color = Color::rgb(0.35, 0.35, 0.35);
+const DISABLED_TEXT_COLOR: Color = Color::rgb(0.5, 0.5, 0.5);
 
 pub struct MainMenuPlugin;
 
 impl Plugin for MainMenuPlugin {
-    fn build(&self, app: &mut bevy::prelude::App) {
-        app.add_startup_system(setup_main_menu);
+    fn build(&self, app: &mut App) {
+        app.add_systems((
+            setup_main_menu.in_schedule(OnEnter(AppState::Menu)),
+            destroy_main_menu.in_schedule(OnExit(AppState::Menu)),
+            menu_input.in_set(OnUpdate(AppState::Menu)),
+            update_main_menu.in_set(OnUpdate(AppState::Menu)),
+        ));
+    }
+}
+
+#[derive(Component)]
+struct MainMenu;
+
+#[derive(Component, Clone, Copy, PartialEq)]
+enum MenuButton {
+    // cycles through the handmade maps and the generator
+    Map,
+    NewSeed,
+    Export,
+    Start,
+    Edit,
+}
+
+#[derive(Component)]
+struct MenuStatus;
+
+fn spawn_button(parent: &mut ChildBuilder, button: MenuButton, font: &Handle<Font>) {
+    parent
+        .spawn((
+            ButtonBundle {
+                style: Style {
+                    size: Size::new(Val::Px(320.), Val::Px(50.)),
+                    justify_content: JustifyContent::Center,
+                    align_items: AlignItems::Center,
+                    ..default()
+                },
+                background_color: BUTTON_COLOR.into(),
+                ..default()
+            },
+            button,
+        ))
+        .with_children(|button| {
+            button.spawn(TextBundle::from_section(
+                "",
+                TextStyle {
+                    font: font.clone(),
+                    font_size: 22.,
+                    color: Color::WHITE,
+                },
+            ));
+        });
+}
+
+fn setup_main_menu(mut commands: Commands, assets: Res<AssetServer>) {
+    let font = assets.load("fonts/DejaVuSans.ttf");
+
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    size: Size::new(Val::Percent(100.), Val::Percent(100.)),
+                    flex_direction: FlexDirection::Column,
+                    justify_content: JustifyContent::Center,
+                    align_items: AlignItems::Center,
+                    gap: Size::height(Val::Px(10.)),
+                    ..default()
+                },
+                ..default()
+            },
+            MainMenu,
+        ))
+        .with_children(|parent| {
+            for button in [
+                MenuButton::Map,
+                MenuButton::NewSeed,
+                MenuButton::Export,
+                MenuButton::Start,
+                MenuButton::Edit,
+            ] {
+                spawn_button(parent, button, &font);
+            }
+            parent.spawn((
+                TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font,
+                        font_size: 18.,
+                        color: Color::WHITE,
+                    },
+                ),
+                MenuStatus,
+            ));
+        });
+}
+
+fn destroy_main_menu(mut commands: Commands, menus: Query<Entity, With<MainMenu>>) {
+    for menu in menus.iter() {
+        commands.entity(menu).despawn_recursive();
     }
 }
 
-fn setup_main_menu() {
-    println!("main menu now active");
+fn menu_input(
+    buttons: Query<(&Interaction, &MenuButton), Changed<Interaction>>,
+    mut game_config: ResMut<GameConfig>,
+    mut status: Query<&mut Text, With<MenuStatus>>,
+    mut next_state: ResMut<NextState<AppState>>,
+) {
+    for (interaction, button) in buttons.iter() {
+        if *interaction != Interaction::Clicked {
+            continue;
+        }
+
+        match (button, &game_config.map) {
+            (MenuButton::Map, MapChoice::File(path)) => {
+                let next = HANDMADE_MAPS
+                    .iter()
+                    .position(|map| map == path)
+                    .and_then(|index| HANDMADE_MAPS.get(index + 1));
+                game_config.map = match next {
+                    Some(map) => MapChoice::File(map.to_string()),
+                    None => MapChoice::Generated(Rng::seed_from_time()),
+                };
+            }
+            (MenuButton::Map, MapChoice::Generated(_)) => {
+                game_config.map = MapChoice::File(HANDMADE_MAPS[0].to_string());
+            }
+            (MenuButton::NewSeed, MapChoice::Generated(_)) => {
+                game_config.map = MapChoice::Generated(Rng::seed_from_time());
+            }
+            (MenuButton::Export, MapChoice::Generated(seed)) => {
+                let message = match export_map(*seed) {
+                    Ok(path) => format!("Saved {}", path.display()),
+                    Err(error) => format!("Can't save the map: {error}"),
+                };
+                for mut text in status.iter_mut() {
+                    text.sections[0].value = message.clone();
+                }
+            }
+            (MenuButton::Start, _) => {
+                game_config.editor = false;
+                next_state.set(AppState::Loading);
+            }
+            (MenuButton::Edit, _) => {
+                game_config.editor = true;
+                next_state.set(AppState::Loading);
+            }
+            _ => {}
+        }
+    }
+}
+
+fn update_main_menu(
+    game_config: Res<GameConfig>,
+    mut buttons: Query<(&MenuButton, &Interaction, &mut BackgroundColor, &Children)>,
+    mut texts: Query<&mut Text>,
+) {
+    let seed = match game_config.map {
+        MapChoice::Generated(seed) => Some(seed),
+        MapChoice::File(_) => None,
+    };
+
+    for (button, interaction, mut background, children) in buttons.iter_mut() {
+        let label = match (button, &game_config.map) {
+            (MenuButton::Map, MapChoice::File(path)) => format!("Map: {path}"),
+            (MenuButton::Map, MapChoice::Generated(_)) => "Map: generated".to_string(),
+            (MenuButton::NewSeed, _) => match seed {
+                Some(seed) => format!("Seed: {seed}"),
+                None => "Seed: -".to_string(),
+            },
+            (MenuButton::Export, _) => "Export as .tmj".to_string(),
+            (MenuButton::Start, _) => "Start".to_string(),
+            (MenuButton::Edit, _) => "Edit map".to_string(),
+        };
+        // seed and export only apply to generated maps
+        let enabled = seed.is_some()
+            || matches!(
+                button,
+                MenuButton::Map | MenuButton::Start | MenuButton::Edit
+            );
+
+        *background = if *interaction == Interaction::Hovered && enabled {
+            BUTTON_HOVER_COLOR.into()
+        } else {
+            BUTTON_COLOR.into()
+        };
+        if let Ok(mut text) = texts.get_mut(children[0]) {
+            text.sections[0].value = label;
+            text.sections[0].style.color = if enabled {
+                Color::WHITE
+            } else {
+                DISABLED_TEXT_COLOR
+            };
+        }
+    }
 }
@@ -0,0 +1,38 @@
+use std::time::SystemTime;
+
+// SplitMix64, the same seed always produces the same numbers
+pub struct Rng(pub u64);
+
+impl Rng {
+    // differs between runs
+    pub fn from_time() -> Self {
+        Rng(Self::seed_from_time())
+    }
+
+    pub fn seed_from_time() -> u64 {
+        SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|duration| duration.as_nanos() as u64)
+            .unwrap_or(0)
+    }
+
+    pub fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^ (z >> 31)
+    }
+
+    pub fn range(&mut self, min: i32, max: i32) -> i32 {
+        min + (self.next() % (max - min + 1) as u64) as i32
+    }
+
+    pub fn chance(&mut self, percent: u64) -> bool {
+        self.next() % 100 < percent
+    }
+
+    pub fn pick(&mut self, ids: &[u32]) -> u32 {
+        ids[(self.next() % ids.len() as u64) as usize]
+    }
+}